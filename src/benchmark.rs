@@ -1,39 +1,100 @@
+use super::cache::{Cache, Hasher};
+use super::precision::{exp_score, Score};
+use super::session::{Interrupt, Session, SolverStats};
+use super::syntax::{Exp, Typ};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::Read;
+use std::os::unix::process::CommandExt;
 use std::process::{Command, Stdio};
-use std::time::Duration;
-use wait_timeout::ChildExt;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Several outcomes involve running the program before and after migration.
 /// Those outcomes have a steps field. The program is expected to terminate
 /// in at most the given number of steps, or we have an unexpected outcome.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct Outcome {
     #[serde(default, skip_serializing_if = "is_false")]
     assert_unusable: bool,
     result: Option<Expect>,
     #[serde(default, skip_serializing_if = "is_none")]
     migration: Option<String>,
+    /// The number of steps that the result of migration takes to run. When
+    /// present, the harness records the actual number of steps here, so that
+    /// changes show up when comparing results.
+    #[serde(default, skip_serializing_if = "is_none")]
+    steps: Option<u64>,
+    /// The resources that the tool used. These change from run to run, so
+    /// comparisons of results ignore them.
+    #[serde(default, skip_serializing_if = "is_none")]
+    timing: Option<Timing>,
+}
+
+/// The resources that a tool used on one benchmark
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+struct Timing {
+    /// Wall-clock time, in seconds
+    seconds: f64,
+    /// Peak resident memory, in kilobytes, which is unknown for builtin engines
+    #[serde(default, skip_serializing_if = "is_none")]
+    peak_memory_kb: Option<u64>,
+    /// The work done by the solver, which is only known for builtin TypeWhich engines
+    #[serde(default, skip_serializing_if = "is_none")]
+    solver: Option<SolverStats>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 enum Expect {
     Rejection(Rejection),
-    NewRuntimeError { num_stars: usize },
-    Unusable { num_stars: usize },
+    /// The tool ran out of time
+    Timeout,
+    NewRuntimeError {
+        precision: Score,
+    },
+    Unusable {
+        precision: Score,
+    },
     FullyCompatible {
-        num_stars: usize,
+        precision: Score,
         #[serde(skip_serializing_if = "is_false", default)]
         manually_verify: bool,
     },
     Disaster,
     Restricted {
-        num_stars: usize,
+        precision: Score,
     },
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+impl std::fmt::Display for Expect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expect::Rejection(..) => write!(f, "Rejection"),
+            Expect::Timeout => write!(f, "Timeout"),
+            Expect::NewRuntimeError { precision } => {
+                write!(f, "NewRuntimeError (precision {})", precision)
+            }
+            Expect::Unusable { precision } => write!(f, "Unusable (precision {})", precision),
+            Expect::FullyCompatible {
+                precision,
+                manually_verify,
+            } => write!(
+                f,
+                "FullyCompatible (precision {}{})",
+                precision,
+                if *manually_verify {
+                    ", verify manually"
+                } else {
+                    ""
+                }
+            ),
+            Expect::Disaster => write!(f, "Disaster"),
+            Expect::Restricted { precision } => write!(f, "Restricted (precision {})", precision),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct Rejection {
     stdout: String,
     stderr: String,
@@ -46,24 +107,133 @@ struct RuntimeError {
     program: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Benchmark {
     file: String,
     #[serde(skip_serializing_if = "is_none")]
     context: Option<String>,
     #[serde(default)]
     results: std::collections::HashMap<String, Outcome>,
-    #[serde(default)] // default is zero
-    num_stars: usize,
+    /// The precision of the original program
+    #[serde(default)]
+    precision: Score,
     #[serde(skip_serializing_if = "is_none")]
-    assert_compatible: Option<String>
+    assert_compatible: Option<String>,
+    /// A file that the program reads its input from. The original program
+    /// and the result of migration must print the same output.
+    #[serde(default, skip_serializing_if = "is_none")]
+    input: Option<String>,
+    /// The language of the program, which is Grift for `.grift` files and
+    /// GTLC otherwise, if absent
+    #[serde(default, skip_serializing_if = "is_none")]
+    language: Option<Language>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The language of a benchmark, which determines how we parse the program, the result of
+/// migrating it, and its context
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Language {
+    Gtlc,
+    Grift,
+}
+
+impl Language {
+    fn parse(self, session: &Session, code: &str) -> Result<Exp, String> {
+        match self {
+            Language::Gtlc => super::parser::parse(session, code),
+            // The Grift parser panics on syntax errors.
+            Language::Grift => super::test_suite::guard(|| Ok(super::grift::parse(session, code))),
+        }
+    }
+
+    /// Prints a program the way that `migrate --print-program` does
+    fn print(self, e: &Exp) -> Result<String, String> {
+        match self {
+            Language::Gtlc => Ok(format!("{}\n", e)),
+            Language::Grift => super::grift::print(e),
+        }
+    }
+
+    fn env(self) -> im_rc::HashMap<String, Typ> {
+        match self {
+            Language::Gtlc => Default::default(),
+            Language::Grift => super::grift::env(),
+        }
+    }
+
+    /// Plugs a program into a context, which refers to the program as `HOLE`. We plug GTLC
+    /// programs into the text of the context. A Grift program may be a sequence of definitions,
+    /// which is not an expression, so we plug Grift programs into the parsed context instead.
+    fn plug(self, context: &str, program: &str, exp: Option<&Exp>) -> Option<Exp> {
+        match self {
+            Language::Gtlc => parse(self, &context.replace("HOLE", program)),
+            Language::Grift => {
+                let mut plugged = parse(self, context)?;
+                plugged.plug(exp?);
+                Some(plugged)
+            }
+        }
+    }
+}
+
+impl Benchmark {
+    fn language(&self) -> Language {
+        match self.language {
+            Some(language) => language,
+            None if self.file.ends_with(".grift") => Language::Grift,
+            None => Language::Gtlc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct MigrationTool {
     title: String,
-    command: Vec<String>,
+    #[serde(flatten)]
+    runner: Runner,
+    /// The number of seconds that the tool may run on one benchmark, which
+    /// is `DEFAULT_TIMEOUT` if absent
+    #[serde(default, skip_serializing_if = "is_none")]
+    timeout: Option<u64>,
+    /// The number of megabytes of memory that the tool may allocate, which
+    /// builtin engines ignore
+    #[serde(default, skip_serializing_if = "is_none")]
+    memory_limit: Option<u64>,
+}
+
+/// How to run a migration tool. In YAML, a tool has either a `command` or a `builtin` field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Runner {
+    /// An external program, which receives the benchmark file as its last argument, and prints
+    /// the result of migration
+    #[serde(rename = "command")]
+    Command(Vec<String>),
+    /// An engine in this crate, which runs in the same process as the harness
+    #[serde(rename = "builtin")]
+    Builtin(Builtin),
+}
+
+/// The options of a builtin engine, which correspond to the flags of the migrate subcommand
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Builtin {
+    engine: Engine,
+    /// `--precise`
+    #[serde(default, skip_serializing_if = "is_false")]
+    precise: bool,
+    /// `--rigid-vars`
+    #[serde(default, skip_serializing_if = "is_false")]
+    rigid_vars: bool,
+    /// `--skip-type-check`
+    #[serde(default, skip_serializing_if = "is_false")]
+    skip_type_check: bool,
 }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum Engine {
+    TypeWhich,
+    InsAndOuts,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Benchmarks {
     tools: Vec<MigrationTool>,
@@ -78,23 +248,10 @@ fn is_none<T>(v: &Option<T>) -> bool {
     return v.is_none();
 }
 
-fn count_stars(e: &super::syntax::Exp) -> usize {
-    use super::syntax::{Exp, Typ};
-    match e {
-        Exp::Lit(..) | Exp::Var(..) => 0,
-        Exp::App(e1, e2) | Exp::BinaryOp(_, e1, e2) => count_stars(e1) + count_stars(e2),
-        // If we introduce an annotation, we get an extra star! This can produce surprising results
-        // For example, the original program `1 + true` has zero stars, but after migration, we get
-        // `1 + true as any`, which has 1 star.
-        Exp::Ann(e, t) | Exp::Fun(_, t, e) => {
-            (match t {
-                Typ::Any => 1,
-                _ => 0,
-            }) + count_stars(e)
-        }
-        Exp::If(e1, e2, e3) => count_stars(e1) + count_stars(e2) + count_stars(e3),
-        _ => panic!("count_stars on {:?}", e),
-    }
+/// Adds the precision of one program to the total for a tool
+fn add_precision(totals: &mut HashMap<String, Score>, tool_title: &str, precision: Score) {
+    let total = totals.get_mut(tool_title).unwrap();
+    *total = *total + precision;
 }
 
 fn get_outcome<'a>(
@@ -108,85 +265,280 @@ fn get_outcome<'a>(
                 assert_unusable: false,
                 result: None,
                 migration: None,
+                steps: None,
+                timing: None,
             },
         );
     }
     return results.get_mut(tool_name).unwrap();
 }
 
-// Run the program after coercion insertion. True means it ran successfully.
-// False means a coercion error occurred. Anything else causes a panic.
-// Store the number of stores in num_stars, if provided.
-fn eval(code: String, num_stars: Option<&mut usize>) -> Option<bool> {
-    match super::parser::parse(code) {
-        Ok(mut ast) => {
-            if let Some(num_stars) = num_stars {
-                *num_stars = count_stars(&ast);
-            }
-            super::insert_coercions::insert_coercions(&mut ast).expect("coercion insertion failed");
-            Some(super::eval::eval(ast).is_ok())
+/// The number of steps that a program may take when the benchmark does not
+/// say how many steps it takes. Programs that take longer are assumed to
+/// diverge.
+const DEFAULT_FUEL: u64 = 1_000_000;
+
+fn parse(language: Language, code: &str) -> Option<Exp> {
+    language.parse(&Session::default(), code).ok()
+}
+
+// Run the program after coercion insertion, for at most fuel steps. True means
+// it ran successfully. False means a coercion error occurred. None means that
+// the program did not parse, ran out of fuel, or needs an expression that the
+// evaluator does not support, and the benchmark then reports a disaster. A
+// program that runs to completion also produces the output that it printed.
+fn eval(language: Language, code: &str, fuel: u64, input: &str) -> Option<(bool, u64, String)> {
+    let mut ast = parse(language, code)?;
+    // Unannotated variables are dynamically typed, as in the eval subcommand.
+    ast.dynamic_metavars();
+    eval_exp(ast, fuel, input)
+}
+
+fn eval_exp(mut ast: Exp, fuel: u64, input: &str) -> Option<(bool, u64, String)> {
+    super::insert_coercions::insert_coercions(&mut ast).expect("coercion insertion failed");
+    match super::eval::eval_io(ast, fuel, input) {
+        (Ok(_), steps, output) => Some((true, steps, output)),
+        (Err(super::eval::Error::Unsupported(e)), _, _) => {
+            eprintln!("Cannot run unsupported expression {}", e);
+            None
+        }
+        (Err(super::eval::Error::OutOfFuel(steps)), _, _) => {
+            eprintln!("Out of fuel after {} steps", steps);
+            None
         }
-        Err(_messages) => None,
+        (Err(_), steps, output) => Some((false, steps, output)),
     }
 }
 
-
-fn check_if_compatible(migrated: &str, expected: &Option<String>) -> bool {
+fn check_if_compatible(language: Language, migrated: &Exp, expected: &Option<String>) -> bool {
     match expected {
         None => false,
         Some(expected) => {
-            let expected_ast = super::parser::parse(expected).expect("could not parse expected compatible");
-            let migrated_ast = super::parser::parse(migrated).expect("coud not parse migrated compatible");
-            super::precision::exp_lt(&expected_ast, &migrated_ast)
+            let expected_ast =
+                parse(language, expected).expect("could not parse expected compatible");
+            super::precision::exp_lt(&expected_ast, migrated)
         }
     }
 }
 
-// Run one benchmark program using one migration tool.
-fn benchmark_one(tool: &MigrationTool, benchmark: &mut Benchmark) {
-    // Run the program with a 30 second timeout.
-    let mut child = Command::new(&tool.command[0])
-        .args(&tool.command[1..])
-        .arg(&benchmark.file)
+/// The number of seconds that a tool may run on one benchmark, when the tool
+/// does not set its own timeout.
+const DEFAULT_TIMEOUT: u64 = 30;
+
+/// What happened when a migration tool ran on one benchmark
+enum ToolRun {
+    /// The text of the migrated program
+    Migrated(String),
+    Rejected(Rejection),
+    TimedOut,
+}
+
+// Read a pipe of a child process to the end in a background thread, so that the child cannot
+// block on a full pipe while we wait for it.
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut text = String::new();
+        pipe.read_to_string(&mut text).unwrap();
+        text
+    })
+}
+
+// Run a migration tool on one file, with the time and memory limits of the tool. We cannot
+// use Child::wait, since it does not report the resources that the child used.
+fn run_command(tool: &MigrationTool, args: &[String], file: &str) -> (ToolRun, Timing) {
+    let mut command = Command::new(&args[0]);
+    command
+        .args(&args[1..])
+        .arg(file)
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("failed to spawn");
-    let migrate_ok = match child.wait_timeout(Duration::from_secs(30)).unwrap() {
-        None => {
+        .stderr(Stdio::piped());
+    if let Some(megabytes) = tool.memory_limit {
+        let bytes = (megabytes * 1024 * 1024) as libc::rlim_t;
+        let limit = libc::rlimit {
+            rlim_cur: bytes,
+            rlim_max: bytes,
+        };
+        // Safety: setrlimit is async-signal-safe, so it may run between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+    let start = Instant::now();
+    let mut child = command.spawn().expect("failed to spawn");
+    let stdout = read_in_background(child.stdout.take().unwrap());
+    let stderr = read_in_background(child.stderr.take().unwrap());
+    let deadline = start + Duration::from_secs(tool.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let mut timed_out = false;
+    loop {
+        let waited = unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut usage) };
+        if waited == pid {
+            break;
+        }
+        assert_eq!(
+            waited,
+            0,
+            "wait4 failed: {}",
+            std::io::Error::last_os_error()
+        );
+        if !timed_out && Instant::now() >= deadline {
             child.kill().unwrap();
             eprintln!("Killed");
-            false
+            timed_out = true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let timing = Timing {
+        seconds: start.elapsed().as_secs_f64(),
+        // On Linux, ru_maxrss is in kilobytes.
+        peak_memory_kb: Some(usage.ru_maxrss as u64),
+        solver: None,
+    };
+    let stdout = stdout.join().unwrap();
+    let stderr = stderr.join().unwrap();
+    if timed_out {
+        return (ToolRun::TimedOut, timing);
+    }
+    if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 {
+        (ToolRun::Migrated(stdout), timing)
+    } else {
+        (ToolRun::Rejected(Rejection { stdout, stderr }), timing)
+    }
+}
+
+// Migrate one file with a builtin engine, and print the result, the way that the migrate
+// subcommand does. We score the printed program, as for command tools, so that the results of a
+// builtin tool and the same command are the same.
+fn migrate_builtin(
+    session: &Session,
+    builtin: Builtin,
+    language: Language,
+    file: &str,
+) -> Result<String, String> {
+    let source = std::fs::read_to_string(file).map_err(|err| err.to_string())?;
+    let env = language.env();
+    let original = language.parse(session, &source)?;
+    let migrated = match builtin.engine {
+        Engine::TypeWhich => super::fuzz::migrate(session, &env, &original)?,
+        Engine::InsAndOuts => super::fuzz::migrate_ins_and_outs(&original),
+    };
+    if !builtin.skip_type_check {
+        super::type_check::tcheck(&env, &migrated)?;
+    }
+    language.print(&migrated)
+}
+
+// Run a builtin engine on one file in another thread, so that it can time out. A thread cannot
+// be killed, so when TypeWhich times out, we interrupt its solver and let the thread finish on its
+// own. InsAndOuts does not use the solver and cannot be interrupted, so it keeps running in the
+// background until the harness exits. The engine shares the memory of the harness, so it has no
+// memory limit, and we do not know its peak memory.
+fn run_builtin(
+    tool: &MigrationTool,
+    builtin: Builtin,
+    language: Language,
+    file: &str,
+) -> (ToolRun, Timing) {
+    let (sender, receiver) = mpsc::channel();
+    let file = file.to_string();
+    let interrupt = Interrupt::default();
+    let stop = interrupt.clone();
+    let start = Instant::now();
+    std::thread::spawn(move || {
+        let mut session = Session::new(super::Options {
+            context: !builtin.precise,
+            rigid_vars: builtin.rigid_vars,
+            annot: super::Annot::Ignore,
+            ..super::Options::default()
+        });
+        session.interrupt = interrupt;
+        let result =
+            super::test_suite::guard(|| migrate_builtin(&session, builtin, language, &file));
+        let _ = sender.send((result, session.solver_stats()));
+    });
+    let timeout = Duration::from_secs(tool.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let result = receiver.recv_timeout(timeout);
+    let mut timing = Timing {
+        seconds: start.elapsed().as_secs_f64(),
+        peak_memory_kb: None,
+        solver: None,
+    };
+    let result = match result {
+        Ok((result, solver)) => {
+            if matches!(builtin.engine, Engine::TypeWhich) {
+                timing.solver = Some(solver);
+            }
+            result
+        }
+        Err(_) => {
+            stop.interrupt();
+            eprintln!("Timed out");
+            return (ToolRun::TimedOut, timing);
         }
-        Some(code) => code.success(),
     };
-    // Save standard out.
-    let mut tool_stdout = String::new();
-    child
-        .stdout
-        .unwrap()
-        .read_to_string(&mut tool_stdout)
-        .unwrap();
-    // Save standard err.
-    let mut tool_stderr = String::new();
-    child
-        .stderr
-        .unwrap()
-        .read_to_string(&mut tool_stderr)
-        .unwrap();
+    match result {
+        Ok(migrated) => (ToolRun::Migrated(migrated), timing),
+        Err(message) => {
+            let rejection = Rejection {
+                stdout: String::new(),
+                stderr: format!("{}\n", message),
+            };
+            (ToolRun::Rejected(rejection), timing)
+        }
+    }
+}
+
+fn run_tool(tool: &MigrationTool, language: Language, file: &str) -> (ToolRun, Timing) {
+    match &tool.runner {
+        Runner::Command(args) => run_command(tool, args, file),
+        Runner::Builtin(builtin) => run_builtin(tool, *builtin, language, file),
+    }
+}
+
+// The precision of the original program of a benchmark, or nothing if it does not parse.
+fn original_precision(benchmark: &Benchmark) -> Score {
+    let original_program = std::fs::read_to_string(&benchmark.file).expect("reading benchmark");
+    match parse(benchmark.language(), &original_program) {
+        Some(ast) => exp_score(&ast),
+        None => Score::default(),
+    }
+}
+
+// Run one benchmark program using one migration tool. The precision of the original program must
+// already be in benchmark.precision.
+fn benchmark_one(tool: &MigrationTool, benchmark: &mut Benchmark) {
+    let language = benchmark.language();
+    let (run, timing) = run_tool(tool, language, &benchmark.file);
 
     // Get a pointer to the outcome where we will store the result.
     let mut outcome = get_outcome(&tool.title, &mut benchmark.results);
+    outcome.timing = Some(timing);
 
-    // Timeout or error from the migration tool.
-    if migrate_ok == false {
-        outcome.result = Some(Expect::Rejection(Rejection {
-            stdout: tool_stdout,
-            stderr: tool_stderr,
-        }));
-        return;
-    }
+    // The text of the result of migration, and the result itself, which is None if it does not
+    // parse.
+    let (tool_stdout, migrated) = match run {
+        ToolRun::TimedOut => {
+            outcome.result = Some(Expect::Timeout);
+            return;
+        }
+        // Error from the migration tool.
+        ToolRun::Rejected(rejection) => {
+            outcome.result = Some(Expect::Rejection(rejection));
+            return;
+        }
+        ToolRun::Migrated(stdout) => {
+            let migrated = parse(language, &stdout);
+            (stdout, migrated)
+        }
+    };
 
     // For us to manually check the result of migration
     outcome.migration = Some(tool_stdout.clone());
@@ -194,38 +546,70 @@ fn benchmark_one(tool: &MigrationTool, benchmark: &mut Benchmark) {
 
     // If benchmark.assert_compatible is present, it a program that is more type-precise
     // than the original program. Verify that this is true and blow up if it is not the case.
-    if check_if_compatible(&original_program, &benchmark.assert_compatible) == false &&
-       benchmark.assert_compatible.is_some() {
-           outcome.result = Some(Expect::Disaster);
-           println!("assert_compatible is not more precise than the original program");
-           return;
+    if benchmark.assert_compatible.is_some() {
+        let original_ast =
+            parse(language, &original_program).expect("could not parse original program");
+        if !check_if_compatible(language, &original_ast, &benchmark.assert_compatible) {
+            outcome.result = Some(Expect::Disaster);
+            println!("assert_compatible is not more precise than the original program");
+            return;
+        }
     }
 
-    // Flag that determines if the original program runs without error. Also, store the number of
-    // stars in the original program in benchmark.num_stars.
-    let original_runs_ok = eval(original_program.clone(), Some(&mut benchmark.num_stars));
-    // Flag that determines if the result of migration runs without error. Also, store the number of
-    // stars in the result of migration in outcome.stars_after_migration.
-    let mut stars_after_migration = 0;
-    let migrated_runs_ok = eval(tool_stdout.clone(), Some(&mut stars_after_migration));
+    // Flag that determines if the original program runs without error.
+    let input = match &benchmark.input {
+        Some(file) => std::fs::read_to_string(file).expect("reading benchmark input"),
+        None => String::new(),
+    };
+    let original_run = eval(language, &original_program, DEFAULT_FUEL, &input);
+    // Ascriptions do not count, so this scores the same binders as benchmark.precision, even
+    // though migration turns `1 + true` into `1 + (true : any)`.
+    let precision_after_migration = migrated.as_ref().map(exp_score).unwrap_or_default();
+    // Flag that determines if the result of migration runs without error. The result of migration
+    // runs out of fuel if it takes more steps than the benchmark expects.
+    let fuel = outcome.steps.unwrap_or(DEFAULT_FUEL);
+    let migrated_run = migrated
+        .clone()
+        .and_then(|migrated| eval_exp(migrated, fuel, &input));
+    if outcome.steps.is_some() {
+        outcome.steps = migrated_run.as_ref().map(|(_, steps, _)| *steps);
+    }
+
+    // When both programs run to completion, migration must not change what they print.
+    if let (Some((true, _, original_output)), Some((true, _, migrated_output))) =
+        (&original_run, &migrated_run)
+    {
+        if original_output != migrated_output {
+            outcome.result = Some(Expect::Disaster);
+            println!("the result of migration printed different output");
+            return;
+        }
+    }
+    let original_runs_ok = original_run.map(|(ok, _, _)| ok);
+    let migrated_runs_ok = migrated_run.map(|(ok, _, _)| ok);
 
     // Check if the result of migration is less precise than what is known to be a maximally precise
     // version of the original program.
-    let result_is_known_compatible = check_if_compatible(&tool_stdout, &benchmark.assert_compatible);
+    let result_is_known_compatible = match &migrated {
+        Some(migrated) => check_if_compatible(language, migrated, &benchmark.assert_compatible),
+        None => false,
+    };
 
     match &benchmark.context {
         None => match (original_runs_ok, migrated_runs_ok) {
             (None, _) => outcome.result = Some(Expect::Disaster),
             (_, None) => outcome.result = Some(Expect::Disaster),
             (Some(true), Some(false)) => {
-                outcome.result = Some(Expect::NewRuntimeError { num_stars: stars_after_migration });
+                outcome.result = Some(Expect::NewRuntimeError {
+                    precision: precision_after_migration,
+                });
             }
             (Some(true), Some(true)) => {
                 // No context, so we assume it is fully compatible. *But*, we set manually_verify
                 // if the result is not less precise than the known most precise version.
                 outcome.result = Some(Expect::FullyCompatible {
-                    num_stars: stars_after_migration,
-                    manually_verify: benchmark.num_stars != stars_after_migration
+                    precision: precision_after_migration,
+                    manually_verify: benchmark.precision != precision_after_migration
                         && !result_is_known_compatible,
                 });
             }
@@ -233,7 +617,7 @@ fn benchmark_one(tool: &MigrationTool, benchmark: &mut Benchmark) {
                 // Program crashes before and after migration in the empty context. All crashes
                 // are compatible.
                 outcome.result = Some(Expect::FullyCompatible {
-                    num_stars: stars_after_migration,
+                    precision: precision_after_migration,
                     manually_verify: false,
                 });
             }
@@ -242,10 +626,16 @@ fn benchmark_one(tool: &MigrationTool, benchmark: &mut Benchmark) {
             }
         },
         Some(context) => {
-            let original_in_context = context.replace("HOLE", &original_program);
-            let migrated_in_context = context.replace("HOLE", &tool_stdout);
-            let original_runs_ok_in_context = eval(original_in_context, None);
-            let migrated_runs_ok_in_context = eval(migrated_in_context, None);
+            let original_ast = parse(language, &original_program);
+            let original_in_context =
+                language.plug(context, &original_program, original_ast.as_ref());
+            let migrated_in_context = language.plug(context, &tool_stdout, migrated.as_ref());
+            let original_runs_ok_in_context = original_in_context
+                .and_then(|exp| eval_exp(exp, DEFAULT_FUEL, &input))
+                .map(|(ok, _, _)| ok);
+            let migrated_runs_ok_in_context = migrated_in_context
+                .and_then(|exp| eval_exp(exp, DEFAULT_FUEL, &input))
+                .map(|(ok, _, _)| ok);
             match (
                 original_runs_ok,
                 migrated_runs_ok,
@@ -255,17 +645,19 @@ fn benchmark_one(tool: &MigrationTool, benchmark: &mut Benchmark) {
                 (Some(true), Some(true), Some(true), Some(false)) => {
                     if outcome.assert_unusable {
                         // Requires manual inspection
-                        outcome.result = Some(Expect::Unusable { num_stars: stars_after_migration });
+                        outcome.result = Some(Expect::Unusable {
+                            precision: precision_after_migration,
+                        });
                     } else {
                         outcome.result = Some(Expect::Restricted {
-                            num_stars: stars_after_migration,
+                            precision: precision_after_migration,
                         });
                     }
                 }
                 (Some(true), Some(true), Some(true), Some(true)) => {
                     outcome.result = Some(Expect::FullyCompatible {
-                        num_stars: stars_after_migration,
-                        manually_verify: benchmark.num_stars != stars_after_migration
+                        precision: precision_after_migration,
+                        manually_verify: benchmark.precision != precision_after_migration
                             && !result_is_known_compatible,
                     });
                 }
@@ -286,22 +678,23 @@ pub fn summarize_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error>
     let mut unusable = HashMap::<String, i32>::new();
     let mut restricted = HashMap::<String, i32>::new();
     let mut compatible = HashMap::<String, i32>::new();
-    let mut num_stars_left = HashMap::<String, i32>::new();
-    let mut num_original_stars = HashMap::<String, i32>::new();
+    let mut precision_after = HashMap::<String, Score>::new();
+    let mut precision_before = HashMap::<String, Score>::new();
     for tool in &benchmarks.tools {
         rejected.insert(tool.title.clone(), 0);
         new_runtime_err.insert(tool.title.clone(), 0);
         unusable.insert(tool.title.clone(), 0);
         restricted.insert(tool.title.clone(), 0);
         compatible.insert(tool.title.clone(), 0);
-        num_stars_left.insert(tool.title.clone(), 0);
-        num_original_stars.insert(tool.title.clone(), 0);
+        precision_after.insert(tool.title.clone(), Score::default());
+        precision_before.insert(tool.title.clone(), Score::default());
     }
 
     for b in &benchmarks.benchmarks {
         for (tool_title, outcome) in &b.results {
             match outcome.result {
-                Some(Expect::Rejection(..)) => {
+                // A tool that runs out of time rejects the program.
+                Some(Expect::Rejection(..)) | Some(Expect::Timeout) => {
                     *rejected.get_mut(tool_title).unwrap() += 1;
                 }
                 Some(Expect::NewRuntimeError { .. }) => {
@@ -310,14 +703,14 @@ pub fn summarize_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error>
                 Some(Expect::Unusable { .. }) => {
                     *unusable.get_mut(tool_title).unwrap() += 1;
                 }
-                Some(Expect::FullyCompatible { num_stars, .. }) => {
-                    *num_stars_left.get_mut(tool_title).unwrap() += num_stars as i32;
-                    *num_original_stars.get_mut(tool_title).unwrap() += b.num_stars as i32;
+                Some(Expect::FullyCompatible { precision, .. }) => {
+                    add_precision(&mut precision_after, tool_title, precision);
+                    add_precision(&mut precision_before, tool_title, b.precision);
                     *compatible.get_mut(tool_title).unwrap() += 1;
                 }
-                Some(Expect::Restricted { num_stars }) => {
-                    *num_stars_left.get_mut(tool_title).unwrap() += num_stars as i32;
-                    *num_original_stars.get_mut(tool_title).unwrap() += b.num_stars as i32;
+                Some(Expect::Restricted { precision }) => {
+                    add_precision(&mut precision_after, tool_title, precision);
+                    add_precision(&mut precision_before, tool_title, b.precision);
                     *restricted.get_mut(tool_title).unwrap() += 1;
                 }
                 Some(Expect::Disaster) => {}
@@ -340,10 +733,10 @@ pub fn summarize_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error>
         let unusable_denom = new_runtime_err_denom - new_runtime_err;
         let restricted = restricted.get(title).unwrap();
         let restricted_denom = unusable_denom - unusable;
-        let stars = num_stars_left.get(title).unwrap();
-        let stars_denom = num_original_stars.get(title).unwrap();
+        let after = precision_after.get(title).unwrap();
+        let before = precision_before.get(title).unwrap();
         println!(
-            "{} & {} / {} & {} / {} & {} / {} &  {} / {} & {} / {} \\\\ ",
+            "{} & {} / {} & {} / {} & {} / {} &  {} / {} & {:.1} / {:.1} \\\\ ",
             title,
             rejected,
             rejected_denom,
@@ -353,8 +746,36 @@ pub fn summarize_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error>
             unusable_denom,
             restricted,
             restricted_denom,
-            stars,
-            stars_denom
+            after.percent(),
+            before.percent()
+        );
+    }
+
+    // The resources that each tool used: total and maximum seconds, and peak memory in megabytes
+    println!();
+    for tool in &benchmarks.tools {
+        let timings = benchmarks
+            .benchmarks
+            .iter()
+            .filter_map(|b| {
+                b.results
+                    .get(&tool.title)
+                    .and_then(|outcome| outcome.timing)
+            })
+            .collect::<Vec<_>>();
+        let total_seconds: f64 = timings.iter().map(|t| t.seconds).sum();
+        let max_seconds = timings.iter().map(|t| t.seconds).fold(0.0, f64::max);
+        let max_memory_kb = timings
+            .iter()
+            .filter_map(|t| t.peak_memory_kb)
+            .max()
+            .unwrap_or(0);
+        println!(
+            "{} & {:.1} & {:.1} & {:.1} \\\\ ",
+            tool.title,
+            total_seconds,
+            max_seconds,
+            max_memory_kb as f64 / 1024.0
         );
     }
 
@@ -366,7 +787,7 @@ pub fn summarize_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error>
 ///
 /// 1. What percentage of migrations are safe?
 /// 2. What percentage of migrations are compatible with all contexts?
-/// 3. What percentage of type annotations are improved?
+/// 3. What percentage of the type constructors in annotations are not `any` after migration?
 ///
 /// A migration that is safe may still be unusable. A program with improved type annotations
 /// may not be safe.
@@ -377,41 +798,35 @@ pub fn summarize_latex_concise(src_file: impl AsRef<str>) -> Result<(), std::io:
     let mut restricted = HashMap::<String, i32>::new();
     let mut compatible = HashMap::<String, i32>::new();
     let mut migrated = HashMap::<String, i32>::new();
-    let mut num_stars_left = HashMap::<String, i32>::new();
-    let mut num_original_stars = HashMap::<String, i32>::new();
+    let mut precision_after = HashMap::<String, Score>::new();
     for tool in &benchmarks.tools {
         restricted.insert(tool.title.clone(), 0);
         compatible.insert(tool.title.clone(), 0);
         migrated.insert(tool.title.clone(), 0);
-        num_stars_left.insert(tool.title.clone(), 0);
-        num_original_stars.insert(tool.title.clone(), 0);
+        precision_after.insert(tool.title.clone(), Score::default());
     }
 
     for b in &benchmarks.benchmarks {
         for (tool_title, outcome) in &b.results {
             match outcome.result {
-                Some(Expect::Rejection(..)) => { }
-                Some(Expect::NewRuntimeError { num_stars }) => {
+                Some(Expect::Rejection(..)) | Some(Expect::Timeout) => {}
+                Some(Expect::NewRuntimeError { precision }) => {
                     *migrated.get_mut(tool_title).unwrap() += 1;
-                    *num_stars_left.get_mut(tool_title).unwrap() += num_stars as i32;
-                    *num_original_stars.get_mut(tool_title).unwrap() += b.num_stars as i32;
+                    add_precision(&mut precision_after, tool_title, precision);
                 }
-                Some(Expect::Unusable { num_stars }) => {
+                Some(Expect::Unusable { precision }) => {
                     *migrated.get_mut(tool_title).unwrap() += 1;
-                    *num_stars_left.get_mut(tool_title).unwrap() += num_stars as i32;
-                    *num_original_stars.get_mut(tool_title).unwrap() += b.num_stars as i32;
+                    add_precision(&mut precision_after, tool_title, precision);
                     *restricted.get_mut(tool_title).unwrap() += 1;
                 }
-                Some(Expect::FullyCompatible { num_stars, .. }) => {
+                Some(Expect::FullyCompatible { precision, .. }) => {
                     *migrated.get_mut(tool_title).unwrap() += 1;
-                    *num_stars_left.get_mut(tool_title).unwrap() += num_stars as i32;
-                    *num_original_stars.get_mut(tool_title).unwrap() += b.num_stars as i32;
+                    add_precision(&mut precision_after, tool_title, precision);
                     *compatible.get_mut(tool_title).unwrap() += 1;
                 }
-                Some(Expect::Restricted { num_stars }) => {
+                Some(Expect::Restricted { precision }) => {
                     *migrated.get_mut(tool_title).unwrap() += 1;
-                    *num_stars_left.get_mut(tool_title).unwrap() += num_stars as i32;
-                    *num_original_stars.get_mut(tool_title).unwrap() += b.num_stars as i32;
+                    add_precision(&mut precision_after, tool_title, precision);
                     *restricted.get_mut(tool_title).unwrap() += 1;
                 }
                 Some(Expect::Disaster) => {}
@@ -429,36 +844,246 @@ pub fn summarize_latex_concise(src_file: impl AsRef<str>) -> Result<(), std::io:
         let migrated = *migrated.get(title).unwrap();
         let restricted = *restricted.get(title).unwrap();
         let compatible = *compatible.get(title).unwrap();
-        let stars = *num_stars_left.get(title).unwrap();
-        let stars_denom = *num_original_stars.get(title).unwrap();
+        let precision = precision_after.get(title).unwrap();
         println!(
             "{} & {:.2} & {:.2} & {:.2} & {:.2} \\\\ ",
             title,
             (migrated as f64) / num_benchmarks as f64,
             ((restricted + compatible) as f64) / (num_benchmarks as f64),
             (compatible as f64) / (num_benchmarks as f64),
-            1.0 - (stars as f64) / (stars_denom as f64),
+            precision.percent() / 100.0,
         );
     }
 
     Ok(())
 }
 
-pub fn benchmark_main(src_file: impl AsRef<str>, ignore: &[String]) -> Result<(), std::io::Error> {
+fn show_result(result: &Option<Expect>) -> String {
+    match result {
+        Some(expect) => expect.to_string(),
+        None => "no result".to_string(),
+    }
+}
+
+fn show_migration(migration: &Option<String>) -> String {
+    match migration {
+        Some(migration) => migration.trim().replace('\n', "\n      "),
+        None => "no migration".to_string(),
+    }
+}
+
+/// The ways in which one outcome differs from the expected outcome
+fn diff_outcomes(expected: &Outcome, actual: &Outcome) -> Vec<String> {
+    let mut diffs = Vec::new();
+    if expected.result != actual.result {
+        diffs.push(format!(
+            "result changed from {} to {}",
+            show_result(&expected.result),
+            show_result(&actual.result)
+        ));
+    }
+    if expected.migration != actual.migration {
+        diffs.push(format!(
+            "migration changed\n    - {}\n    + {}",
+            show_migration(&expected.migration),
+            show_migration(&actual.migration)
+        ));
+    }
+    if expected.steps != actual.steps {
+        diffs.push(format!(
+            "steps changed from {:?} to {:?}",
+            expected.steps, actual.steps
+        ));
+    }
+    diffs
+}
+
+/// The ways in which the results of running the benchmarks differ from the expected results,
+/// for the tools that ran. The results match when there are no differences.
+fn diff_benchmarks(expected: &Benchmarks, actual: &Benchmarks) -> Vec<String> {
+    let mut diffs = Vec::new();
+    for b in &actual.benchmarks {
+        let expected_b = match expected.benchmarks.iter().find(|e| e.file == b.file) {
+            Some(expected_b) => expected_b,
+            None => {
+                diffs.push(format!("{}: not in the expected results", b.file));
+                continue;
+            }
+        };
+        if expected_b.precision != b.precision {
+            diffs.push(format!(
+                "{}: precision changed from {} to {}",
+                b.file, expected_b.precision, b.precision
+            ));
+        }
+        for tool in &actual.tools {
+            let outcome = match b.results.get(&tool.title) {
+                Some(outcome) => outcome,
+                None => continue,
+            };
+            match expected_b.results.get(&tool.title) {
+                None => diffs.push(format!(
+                    "{}, {}: not in the expected results",
+                    b.file, tool.title
+                )),
+                Some(expected_outcome) => {
+                    for diff in diff_outcomes(expected_outcome, outcome) {
+                        diffs.push(format!("{}, {}: {}", b.file, tool.title, diff));
+                    }
+                }
+            }
+        }
+    }
+    for e in &expected.benchmarks {
+        if !actual.benchmarks.iter().any(|b| b.file == e.file) {
+            diffs.push(format!("{}: missing from the results", e.file));
+        }
+    }
+    diffs
+}
+
+/// The hash of everything about a tool that its results depend on: the definition of the tool,
+/// the program that it runs, and the harness, which runs builtin engines and classifies results.
+fn tool_hash(tool: &MigrationTool, harness: &[u8]) -> u64 {
+    let mut hasher = Hasher::new();
+    hasher.write(harness);
+    hasher.write(serde_yaml::to_string(tool).unwrap().as_bytes());
+    if let Runner::Command(args) = &tool.runner {
+        // A program on the PATH is not a file here, so it does not count.
+        hasher.write_opt(std::fs::read(&args[0]).ok().as_deref());
+    }
+    hasher.finish()
+}
+
+/// The key of the outcome of running a tool on a benchmark. Other than the benchmark itself,
+/// the outcome depends on the fields of the previous outcome that benchmark_one reads.
+fn cache_key(tool_hash: u64, tool_title: &str, benchmark: &Benchmark) -> u64 {
+    let read = |file: &String| std::fs::read(file).ok();
+    let previous = benchmark.results.get(tool_title);
+    let settings = format!(
+        "{:?} {:?} {:?}",
+        benchmark.language(),
+        previous.map(|outcome| outcome.assert_unusable),
+        previous.and_then(|outcome| outcome.steps)
+    );
+    let mut hasher = Hasher::new();
+    hasher
+        .write_u64(tool_hash)
+        .write(benchmark.file.as_bytes())
+        .write_opt(read(&benchmark.file).as_deref())
+        .write_opt(benchmark.context.as_ref().map(String::as_bytes))
+        .write_opt(benchmark.assert_compatible.as_ref().map(String::as_bytes))
+        .write_opt(benchmark.input.as_ref().and_then(read).as_deref())
+        .write(settings.as_bytes());
+    hasher.finish()
+}
+
+/// Runs the benchmarks and prints the results as YAML. With `expect`, instead compares the
+/// results to the expected results in that file, prints the differences, and exits with an
+/// error if there are any.
+pub fn benchmark_main(
+    src_file: impl AsRef<str>,
+    ignore: &[String],
+    expect: Option<&str>,
+    jobs: usize,
+    cache_dir: Option<&str>,
+) -> Result<(), std::io::Error> {
     let src_text = std::fs::read_to_string(src_file.as_ref())?;
     let mut benchmarks: Benchmarks = serde_yaml::from_str(&src_text).expect("syntax error");
     // Filter out tools that are in the ignore list.
-    benchmarks.tools.retain(|tool| false == ignore.contains(&tool.title));
-    for mut b in benchmarks.benchmarks.iter_mut() {
+    benchmarks
+        .tools
+        .retain(|tool| false == ignore.contains(&tool.title));
+    for b in benchmarks.benchmarks.iter_mut() {
         // Remove the expected outcomes for ignored tools, or we panic later.
-        b.results.retain(|tool_title, _| false == ignore.contains(tool_title));
-        for t in &benchmarks.tools {
-            eprintln!("Running {} on {} ...", t.title, b.file);
-            benchmark_one(&t, &mut b);
+        b.results
+            .retain(|tool_title, _| false == ignore.contains(tool_title));
+        b.precision = original_precision(b);
+    }
+
+    // Outcomes are cached unless cache_dir is None.
+    let cache = match cache_dir {
+        Some(dir) => {
+            let harness = std::fs::read(std::env::current_exe()?)?;
+            let hashes: Vec<u64> = benchmarks
+                .tools
+                .iter()
+                .map(|t| tool_hash(t, &harness))
+                .collect();
+            Some((Cache::new(dir)?, hashes))
+        }
+        None => None,
+    };
+
+    // Every job runs one tool on its own copy of one benchmark, and sends back the outcome.
+    let mut queue = Vec::new();
+    for (i, b) in benchmarks.benchmarks.iter().enumerate() {
+        for (j, t) in benchmarks.tools.iter().enumerate() {
+            let cache = cache
+                .as_ref()
+                .map(|(cache, hashes)| (cache.clone(), cache_key(hashes[j], &t.title, b)));
+            queue.push((i, t.clone(), b.clone(), cache));
+        }
+    }
+    let queue = Arc::new(Mutex::new(queue.into_iter()));
+    let (sender, receiver) = mpsc::channel();
+    let workers = (0..jobs.max(1))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            std::thread::spawn(move || loop {
+                let job = queue.lock().unwrap().next();
+                let (i, t, mut b, cache) = match job {
+                    Some(job) => job,
+                    None => return,
+                };
+                if let Some((cache, key)) = &cache {
+                    if let Some(outcome) = cache.get::<Outcome>(*key) {
+                        eprintln!("Reusing the result of {} on {}", t.title, b.file);
+                        sender.send((i, t.title, outcome)).unwrap();
+                        continue;
+                    }
+                }
+                eprintln!("Running {} on {} ...", t.title, b.file);
+                benchmark_one(&t, &mut b);
+                let outcome = b.results.remove(&t.title).unwrap();
+                // A timeout may be due to load, so the tool gets another chance next time.
+                if let Some((cache, key)) = &cache {
+                    if outcome.result != Some(Expect::Timeout) {
+                        cache.put(*key, &outcome).expect("writing to the cache");
+                    }
+                }
+                sender.send((i, t.title, outcome)).unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+    for (i, tool_title, outcome) in receiver {
+        benchmarks.benchmarks[i].results.insert(tool_title, outcome);
+    }
+    for worker in workers {
+        // A worker that panicked sends nothing more, so propagate the panic.
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
         }
     }
 
-    println!("{}", serde_yaml::to_string(&benchmarks).unwrap());
+    match expect {
+        None => println!("{}", serde_yaml::to_string(&benchmarks).unwrap()),
+        Some(expected_file) => {
+            let expected_text = std::fs::read_to_string(expected_file)?;
+            let expected: Benchmarks = serde_yaml::from_str(&expected_text).expect("syntax error");
+            let diffs = diff_benchmarks(&expected, &benchmarks);
+            for diff in &diffs {
+                println!("{}", diff);
+            }
+            if !diffs.is_empty() {
+                println!("{} differences from {}", diffs.len(), expected_file);
+                std::process::exit(1);
+            }
+            println!("The results match {}", expected_file);
+        }
+    }
     return Ok(());
 }
 
@@ -479,7 +1104,8 @@ pub fn details_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error> {
                 Expect::FullyCompatible { .. } => "Compatible",
                 Expect::NewRuntimeError { .. } => "Runtime Error",
                 Expect::Rejection { .. } => "Rejected",
-                Expect::Unusable{ .. } => "Unusable",
+                Expect::Timeout => "Timed out",
+                Expect::Unusable { .. } => "Unusable",
                 Expect::Restricted { .. } => "Restricted",
             };
             println!("\\paragraph{{{}}}: {}", &t.title, outcome_str);
@@ -490,3 +1116,703 @@ pub fn details_latex(src_file: impl AsRef<str>) -> Result<(), std::io::Error> {
     }
     return Ok(());
 }
+
+/// The formats of the report subcommand
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err("invalid report format"),
+        }
+    }
+}
+
+impl Expect {
+    /// The precision of the result of migration, if there is one
+    fn precision(&self) -> Option<Score> {
+        match self {
+            Expect::NewRuntimeError { precision }
+            | Expect::Unusable { precision }
+            | Expect::FullyCompatible { precision, .. }
+            | Expect::Restricted { precision } => Some(*precision),
+            Expect::Rejection(..) | Expect::Timeout | Expect::Disaster => None,
+        }
+    }
+
+    /// The name of the outcome in reports, which is one of `OUTCOME_NAMES`
+    fn name(&self) -> &'static str {
+        match self {
+            Expect::FullyCompatible { .. } => "Compatible",
+            Expect::Restricted { .. } => "Restricted",
+            Expect::Unusable { .. } => "Unusable",
+            Expect::NewRuntimeError { .. } => "Runtime error",
+            Expect::Rejection(..) => "Rejected",
+            Expect::Timeout => "Timed out",
+            Expect::Disaster => "Disaster",
+        }
+    }
+}
+
+const OUTCOME_NAMES: [&str; 7] = [
+    "Compatible",
+    "Restricted",
+    "Unusable",
+    "Runtime error",
+    "Rejected",
+    "Timed out",
+    "Disaster",
+];
+
+/// One line of the difference between two listings
+#[derive(Debug, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The difference between two listings, line by line, which keeps a longest common subsequence
+/// of their lines
+fn line_diff<'a>(before: &[&'a str], after: &[&'a str]) -> Vec<Line<'a>> {
+    let (n, m) = (before.len(), after.len());
+    // lcs[i][j] is the length of a longest common subsequence of before[i..] and after[j..]
+    let mut lcs = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if before[i] == after[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && before[i] == after[j] {
+            lines.push(Line::Same(before[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(Line::Removed(before[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(after[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// The annotation of one binder, before and after migration
+struct Annotation {
+    binder: String,
+    before: String,
+    after: String,
+}
+
+/// The original program and the result of migration, printed in the same way, so that they
+/// differ only where migration changed them. The annotations are `None` when the programs do
+/// not have the same binders.
+struct Comparison {
+    original: String,
+    migrated: String,
+    annotations: Option<Vec<Annotation>>,
+}
+
+fn compare_programs(language: Language, original: &str, migrated: &str) -> Comparison {
+    let original_exp = parse(language, original).map(|mut exp| {
+        // Missing annotations are `any`.
+        exp.dynamic_metavars();
+        exp
+    });
+    // The result of migrating a Grift program with a builtin engine is in GTLC syntax.
+    let migrated_exp = parse(language, migrated).or_else(|| parse(Language::Gtlc, migrated));
+    let annotations = match (&original_exp, &migrated_exp) {
+        (Some(original), Some(migrated)) => {
+            let (b1, b2) = (original.binders(), migrated.binders());
+            if b1.len() == b2.len() {
+                let annotations = b1
+                    .iter()
+                    .zip(b2.iter())
+                    .map(|((x, t1), (_, t2))| Annotation {
+                        binder: x.to_string(),
+                        before: t1.to_string(),
+                        after: t2.to_string(),
+                    })
+                    .collect();
+                Some(annotations)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let show = |exp: Option<Exp>, text: &str| match exp {
+        Some(exp) => exp.to_string(),
+        None => text.trim_end().to_string(),
+    };
+    Comparison {
+        original: show(original_exp, original),
+        migrated: show(migrated_exp, migrated),
+        annotations,
+    }
+}
+
+/// An identifier for a link target, made of the characters of `name` that are letters and digits
+fn anchor(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// A report in Markdown or HTML. The methods that return strings format inline text, and the
+/// others append blocks to the report.
+struct Report {
+    format: ReportFormat,
+    out: String,
+}
+
+impl Report {
+    fn text(&self, text: &str) -> String {
+        match self.format {
+            ReportFormat::Markdown => text.replace('|', "\\|"),
+            ReportFormat::Html => text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;"),
+        }
+    }
+
+    fn strong(&self, text: &str) -> String {
+        match self.format {
+            ReportFormat::Markdown => format!("**{}**", self.text(text)),
+            ReportFormat::Html => format!("<strong>{}</strong>", self.text(text)),
+        }
+    }
+
+    fn link(&self, text: &str, target: &str) -> String {
+        match self.format {
+            ReportFormat::Markdown => format!("[{}](#{})", self.text(text), target),
+            ReportFormat::Html => format!("<a href=\"#{}\">{}</a>", target, self.text(text)),
+        }
+    }
+
+    fn heading(&mut self, level: usize, id: &str, text: &str) {
+        let text = self.text(text);
+        let heading = match self.format {
+            ReportFormat::Markdown => {
+                format!("{} <a id=\"{}\"></a>{}\n\n", "#".repeat(level), id, text)
+            }
+            ReportFormat::Html => format!("<h{0} id=\"{1}\">{2}</h{0}>\n", level, id, text),
+        };
+        self.out.push_str(&heading);
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        let paragraph = match self.format {
+            ReportFormat::Markdown => format!("{}\n\n", text),
+            ReportFormat::Html => format!("<p>{}</p>\n", text),
+        };
+        self.out.push_str(&paragraph);
+    }
+
+    /// The cells are formatted text.
+    fn table(&mut self, header: &[String], rows: &[Vec<String>]) {
+        match self.format {
+            ReportFormat::Markdown => {
+                self.out.push_str(&format!("| {} |\n", header.join(" | ")));
+                self.out
+                    .push_str(&format!("|{}\n", "---|".repeat(header.len())));
+                for row in rows {
+                    self.out.push_str(&format!("| {} |\n", row.join(" | ")));
+                }
+                self.out.push('\n');
+            }
+            ReportFormat::Html => {
+                self.out.push_str("<table>\n<tr>");
+                for cell in header {
+                    self.out.push_str(&format!("<th>{}</th>", cell));
+                }
+                self.out.push_str("</tr>\n");
+                for row in rows {
+                    self.out.push_str("<tr>");
+                    for cell in row {
+                        self.out.push_str(&format!("<td>{}</td>", cell));
+                    }
+                    self.out.push_str("</tr>\n");
+                }
+                self.out.push_str("</table>\n");
+            }
+        }
+    }
+
+    fn preformatted(&mut self, text: &str) {
+        let block = match self.format {
+            ReportFormat::Markdown => format!("```\n{}\n```\n\n", text.trim_end()),
+            ReportFormat::Html => format!("<pre>{}</pre>\n", self.text(text.trim_end())),
+        };
+        self.out.push_str(&block);
+    }
+
+    /// Markdown cannot put listings side by side, so it shows them as a diff instead.
+    fn listings(&mut self, original: &str, migrated: &str) {
+        let before = original.lines().collect::<Vec<_>>();
+        let after = migrated.lines().collect::<Vec<_>>();
+        let lines = line_diff(&before, &after);
+        match self.format {
+            ReportFormat::Markdown => {
+                self.out.push_str("```diff\n");
+                for line in lines {
+                    let (prefix, text) = match line {
+                        Line::Same(text) => (' ', text),
+                        Line::Removed(text) => ('-', text),
+                        Line::Added(text) => ('+', text),
+                    };
+                    self.out.push_str(&format!("{} {}\n", prefix, text));
+                }
+                self.out.push_str("```\n\n");
+            }
+            ReportFormat::Html => {
+                let mut left = String::new();
+                let mut right = String::new();
+                for line in lines {
+                    match line {
+                        Line::Same(text) => {
+                            left.push_str(&format!("{}\n", self.text(text)));
+                            right.push_str(&format!("{}\n", self.text(text)));
+                        }
+                        Line::Removed(text) => {
+                            left.push_str(&format!("<mark>{}</mark>\n", self.text(text)))
+                        }
+                        Line::Added(text) => {
+                            right.push_str(&format!("<mark>{}</mark>\n", self.text(text)))
+                        }
+                    }
+                }
+                self.out.push_str(&format!(
+                    "<table class=\"listings\">\n<tr><th>Original</th><th>Migrated</th></tr>\n\
+                     <tr><td><pre>{}</pre></td><td><pre>{}</pre></td></tr>\n</table>\n",
+                    left, right
+                ));
+            }
+        }
+    }
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; vertical-align: top; }
+pre { margin: 0; }
+mark { background: #fff3a0; }";
+
+/// Renders the results of the benchmarks: a table of outcomes for each tool, a table of the
+/// outcome of every benchmark, which links to its details, and the details of every benchmark,
+/// with the changes that each tool made to its annotations.
+fn render_report(benchmarks: &Benchmarks, format: ReportFormat) -> String {
+    let mut report = Report {
+        format,
+        out: String::new(),
+    };
+    report.heading(1, "results", "Benchmark results");
+    report.heading(2, "summary", "Summary");
+
+    let mut header = vec![report.text("Tool")];
+    header.extend(OUTCOME_NAMES.iter().map(|name| report.text(name)));
+    header.push(report.text("Precision after migration"));
+    let mut rows = Vec::new();
+    for tool in &benchmarks.tools {
+        let results = benchmarks
+            .benchmarks
+            .iter()
+            .filter_map(|b| b.results.get(&tool.title)?.result.as_ref())
+            .collect::<Vec<_>>();
+        let mut row = vec![report.text(&tool.title)];
+        for name in OUTCOME_NAMES.iter() {
+            let count = results
+                .iter()
+                .filter(|result| result.name() == *name)
+                .count();
+            row.push(count.to_string());
+        }
+        let precision = results
+            .iter()
+            .filter_map(|result| result.precision())
+            .fold(Score::default(), |total, precision| total + precision);
+        row.push(report.text(&precision.to_string()));
+        rows.push(row);
+    }
+    report.table(&header, &rows);
+
+    let mut header = vec![report.text("Benchmark"), report.text("Precision")];
+    header.extend(benchmarks.tools.iter().map(|tool| report.text(&tool.title)));
+    let mut rows = Vec::new();
+    for b in &benchmarks.benchmarks {
+        let id = anchor(&b.file);
+        let mut row = vec![
+            report.link(&b.file, &id),
+            report.text(&b.precision.to_string()),
+        ];
+        for tool in &benchmarks.tools {
+            let cell = match b.results.get(&tool.title).and_then(|o| o.result.as_ref()) {
+                None => report.text("no result"),
+                Some(result) => {
+                    let target = format!("{}-{}", id, anchor(&tool.title));
+                    match result {
+                        Expect::Disaster => report.strong(result.name()),
+                        Expect::FullyCompatible {
+                            manually_verify: true,
+                            ..
+                        } => report.link(&format!("{} (verify)", result.name()), &target),
+                        _ => report.link(result.name(), &target),
+                    }
+                }
+            };
+            row.push(cell);
+        }
+        rows.push(row);
+    }
+    report.table(&header, &rows);
+
+    report.heading(2, "details", "Details");
+    for b in &benchmarks.benchmarks {
+        let id = anchor(&b.file);
+        report.heading(3, &id, &b.file);
+        let back = report.link("Back to the summary", "summary");
+        report.paragraph(&back);
+        let original = match std::fs::read_to_string(&b.file) {
+            Ok(original) => original,
+            Err(err) => {
+                let message = report.text(&format!("Cannot read the program: {}", err));
+                report.paragraph(&message);
+                continue;
+            }
+        };
+        report.preformatted(&original);
+        for tool in &benchmarks.tools {
+            let outcome = match b.results.get(&tool.title) {
+                Some(outcome) => outcome,
+                None => continue,
+            };
+            let name = outcome
+                .result
+                .as_ref()
+                .map_or("No result", |result| result.name());
+            let target = format!("{}-{}", id, anchor(&tool.title));
+            report.heading(4, &target, &format!("{}: {}", tool.title, name));
+            match (&outcome.result, &outcome.migration) {
+                (Some(Expect::Rejection(rejection)), _) => {
+                    report.preformatted(&format!("{}{}", rejection.stdout, rejection.stderr));
+                }
+                (_, Some(migration)) => {
+                    let comparison = compare_programs(b.language(), &original, migration);
+                    if let Some(annotations) = &comparison.annotations {
+                        let header = ["Binder", "Before", "After"]
+                            .iter()
+                            .map(|cell| report.text(cell))
+                            .collect::<Vec<_>>();
+                        let rows = annotations
+                            .iter()
+                            .map(|a| {
+                                let after = if a.before == a.after {
+                                    report.text(&a.after)
+                                } else {
+                                    report.strong(&a.after)
+                                };
+                                vec![report.text(&a.binder), report.text(&a.before), after]
+                            })
+                            .collect::<Vec<_>>();
+                        report.table(&header, &rows);
+                    }
+                    report.listings(&comparison.original, &comparison.migrated);
+                }
+                (_, None) => {}
+            }
+        }
+    }
+
+    match format {
+        ReportFormat::Markdown => report.out,
+        ReportFormat::Html => format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Benchmark results</title>\n<style>\n{}\n</style>\n</head>\n\
+             <body>\n{}</body>\n</html>\n",
+            HTML_STYLE, report.out
+        ),
+    }
+}
+
+pub fn report_main(src_file: impl AsRef<str>, format: ReportFormat) -> Result<(), std::io::Error> {
+    let src_text = std::fs::read_to_string(src_file.as_ref())?;
+    let benchmarks: Benchmarks = serde_yaml::from_str(&src_text).expect("syntax error");
+    print!("{}", render_report(&benchmarks, format));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        diff_benchmarks, eval_exp, line_diff, render_report, run_tool, Benchmark, Benchmarks,
+        Builtin, Engine, Language, Line, MigrationTool, ReportFormat, Runner, Timing, ToolRun,
+        DEFAULT_FUEL,
+    };
+    use crate::session::Session;
+
+    const EXPECTED: &str = r#"
+tools:
+  - title: TypeWhich
+    command: [ "./bin/TypeWhich", "migrate" ]
+benchmarks:
+  - file: a.gtlc
+    results:
+      TypeWhich:
+        result:
+          FullyCompatible:
+            precision:
+              precise: 1
+              total: 1
+        migration: "fun x:int. x"
+    precision:
+      precise: 0
+      total: 1
+"#;
+
+    fn parse(text: &str) -> Benchmarks {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn identical_results_match() {
+        assert!(diff_benchmarks(&parse(EXPECTED), &parse(EXPECTED)).is_empty());
+    }
+
+    #[test]
+    fn changed_results_differ() {
+        let actual = EXPECTED
+            .replace("FullyCompatible", "Restricted")
+            .replace("x:int", "x:any");
+        let diffs = diff_benchmarks(&parse(EXPECTED), &parse(&actual));
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].starts_with("a.gtlc, TypeWhich: result changed from FullyCompatible"));
+        assert!(diffs[1].contains("- fun x:int. x"));
+    }
+
+    #[test]
+    fn timings_are_ignored() {
+        let mut actual = parse(EXPECTED);
+        let outcome = actual.benchmarks[0].results.get_mut("TypeWhich").unwrap();
+        outcome.timing = Some(Timing {
+            seconds: 0.5,
+            peak_memory_kb: Some(2048),
+            solver: None,
+        });
+        assert!(diff_benchmarks(&parse(EXPECTED), &actual).is_empty());
+    }
+
+    #[test]
+    fn tools_are_commands_or_builtins() {
+        let benchmarks = parse(
+            r#"
+tools:
+  - title: MGT
+    command: [ "./bin/mgt" ]
+  - title: TypeWhich
+    builtin: { engine: TypeWhich, precise: true }
+    timeout: 10
+benchmarks: []
+"#,
+        );
+        assert!(matches!(&benchmarks.tools[0].runner, Runner::Command(args) if args.len() == 1));
+        assert!(matches!(
+            benchmarks.tools[1].runner,
+            Runner::Builtin(Builtin {
+                engine: Engine::TypeWhich,
+                precise: true,
+                rigid_vars: false,
+                ..
+            })
+        ));
+        assert_eq!(benchmarks.tools[1].timeout, Some(10));
+    }
+
+    #[test]
+    fn builtin_engines_migrate_like_the_migrate_subcommand() {
+        let migrate = |engine, precise, language, file| {
+            let tool = MigrationTool {
+                title: "Builtin".to_string(),
+                runner: Runner::Builtin(Builtin {
+                    engine,
+                    precise,
+                    rigid_vars: false,
+                    skip_type_check: false,
+                }),
+                timeout: None,
+                memory_limit: None,
+            };
+            match run_tool(&tool, language, file).0 {
+                ToolRun::Migrated(migrated) => migrated,
+                _ => panic!("migration failed"),
+            }
+        };
+        // The same results as in test.expected.yaml
+        let gtlc = "adversarial/01-farg-mismatch.gtlc";
+        assert_eq!(
+            migrate(Engine::TypeWhich, true, Language::Gtlc, gtlc),
+            "(fun f:bool -> int. f true) (fun x:bool. (x : any) + 100)\n"
+        );
+        assert_eq!(
+            migrate(Engine::InsAndOuts, false, Language::Gtlc, gtlc),
+            "(fun f:bool -> int. f true) (fun x:bool. x + 100)\n"
+        );
+        // Grift programs are printed in Grift, as by migrate --print-program.
+        assert_eq!(
+            migrate(
+                Engine::TypeWhich,
+                true,
+                Language::Grift,
+                "grift-suite/core/forty-two.grift"
+            ),
+            "(let ((add1 (lambda ((x : Int)) (+ x 1)))) (let ((f add1)) (f 41)))\n"
+        );
+    }
+
+    #[test]
+    fn builtin_engines_record_solver_statistics() {
+        let timing = |engine| {
+            let tool = MigrationTool {
+                title: "Builtin".to_string(),
+                runner: Runner::Builtin(Builtin {
+                    engine,
+                    precise: false,
+                    rigid_vars: false,
+                    skip_type_check: false,
+                }),
+                timeout: None,
+                memory_limit: None,
+            };
+            run_tool(&tool, Language::Gtlc, "adversarial/01-farg-mismatch.gtlc").1
+        };
+        let stats = timing(Engine::TypeWhich).solver.unwrap();
+        // Once for the precise type, and once for the context
+        assert_eq!(stats.checks, 2);
+        assert!(stats.variables > 0 && stats.soft_constraints > 0);
+        assert_eq!(timing(Engine::InsAndOuts).solver, None);
+    }
+
+    #[test]
+    fn unsupported_programs_are_not_run() {
+        let program = super::parse(Language::Grift, r#"(printf "~x" 1)"#).unwrap();
+        assert_eq!(eval_exp(program, DEFAULT_FUEL, ""), None);
+    }
+
+    #[test]
+    fn languages_plug_programs_into_contexts() {
+        let benchmark = |file: &str| Benchmark {
+            file: file.to_string(),
+            context: None,
+            results: Default::default(),
+            precision: Default::default(),
+            assert_compatible: None,
+            input: None,
+            language: None,
+        };
+        assert_eq!(benchmark("a.gtlc").language(), Language::Gtlc);
+        assert_eq!(benchmark("a.grift").language(), Language::Grift);
+
+        let program = "(fun x . x + 1)";
+        let exp = Language::Gtlc.parse(&Session::default(), program).unwrap();
+        let runs_ok = |language: Language, context| {
+            let plugged = language.plug(context, program, Some(&exp)).unwrap();
+            eval_exp(plugged, DEFAULT_FUEL, "").map(|(ok, _, _)| ok)
+        };
+        assert_eq!(runs_ok(Language::Gtlc, "HOLE 5"), Some(true));
+        assert_eq!(runs_ok(Language::Gtlc, "HOLE true"), Some(false));
+        assert_eq!(runs_ok(Language::Grift, "(HOLE 5)"), Some(true));
+        assert_eq!(runs_ok(Language::Grift, "(HOLE #t)"), Some(false));
+    }
+
+    #[test]
+    fn line_diffs_keep_common_lines() {
+        assert_eq!(
+            line_diff(&["a", "b", "c"], &["a", "x", "c"]),
+            vec![
+                Line::Same("a"),
+                Line::Removed("b"),
+                Line::Added("x"),
+                Line::Same("c")
+            ]
+        );
+        assert_eq!(line_diff(&[], &["a"]), vec![Line::Added("a")]);
+    }
+
+    #[test]
+    fn reports_link_summaries_to_details() {
+        let benchmarks = parse(
+            r#"
+tools:
+  - title: TypeWhich
+    builtin: { engine: TypeWhich, precise: true }
+benchmarks:
+  - file: adversarial/01-farg-mismatch.gtlc
+    results:
+      TypeWhich:
+        result:
+          FullyCompatible:
+            precision:
+              precise: 2
+              total: 4
+        migration: "(fun f:any -> int. f true) (fun x:any. x + 100)\n"
+    precision:
+      precise: 0
+      total: 2
+"#,
+        );
+        let markdown = render_report(&benchmarks, ReportFormat::Markdown);
+        assert!(markdown.contains("| TypeWhich | 1 | 0 | 0 | 0 | 0 | 0 | 0 | 2/4 (50.0%) |"));
+        assert!(markdown.contains(
+            "| [adversarial/01-farg-mismatch.gtlc](#adversarial-01-farg-mismatch-gtlc) | 0/2 (0.0%) \
+             | [Compatible](#adversarial-01-farg-mismatch-gtlc-typewhich) |"
+        ));
+        assert!(markdown.contains(
+            "#### <a id=\"adversarial-01-farg-mismatch-gtlc-typewhich\"></a>TypeWhich: Compatible"
+        ));
+        assert!(markdown.contains("| f | any | **any -> int** |"));
+        assert!(markdown.contains("| x | any | any |"));
+        assert!(markdown.contains("```diff\n- "));
+
+        let html = render_report(&benchmarks, ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<a href=\"#summary\">Back to the summary</a>"));
+        assert!(html.contains("<td><strong>any -&gt; int</strong></td>"));
+        assert!(html.contains("<mark>"));
+    }
+
+    #[test]
+    fn slow_tools_time_out() {
+        let tool = MigrationTool {
+            title: "Sleep".to_string(),
+            runner: Runner::Command(vec!["sleep".to_string()]),
+            timeout: Some(1),
+            memory_limit: None,
+        };
+        let (run, timing) = run_tool(&tool, Language::Gtlc, "10");
+        assert!(matches!(run, ToolRun::TimedOut));
+        assert!(timing.seconds >= 1.0 && timing.seconds < 10.0);
+    }
+}
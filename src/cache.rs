@@ -0,0 +1,160 @@
+//! A cache of results on disk. Every result is a YAML file in the cache
+//! directory, named after a hash of everything that the result depends on.
+//! We use the 64-bit FNV-1a hash, which is stable across runs and platforms,
+//! unlike the hasher in the standard library.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Numbers the temporary files of this process, so that threads that put the
+/// same key at once do not write to the same file.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Hashes a sequence of values. Every value is prefixed with its length, so
+/// that `["ab", "c"]` and `["a", "bc"]` have different hashes.
+pub struct Hasher(u64);
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        Hasher(FNV_OFFSET_BASIS)
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> &mut Hasher {
+        self.write_raw(&(bytes.len() as u64).to_le_bytes());
+        self.write_raw(bytes);
+        self
+    }
+
+    pub fn write_u64(&mut self, n: u64) -> &mut Hasher {
+        self.write_raw(&n.to_le_bytes());
+        self
+    }
+
+    pub fn write_opt(&mut self, bytes: Option<&[u8]>) -> &mut Hasher {
+        match bytes {
+            None => self.write_u64(0),
+            Some(bytes) => self.write_u64(1).write(bytes),
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Hasher {
+        Hasher::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl AsRef<Path>) -> std::io::Result<Cache> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Cache {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.yaml", key))
+    }
+
+    /// The result with the key, or `None` if there is no such result, or it
+    /// is not a `T`
+    pub fn get<T: DeserializeOwned>(&self, key: u64) -> Option<T> {
+        let text = std::fs::read_to_string(self.path(key)).ok()?;
+        serde_yaml::from_str(&text).ok()
+    }
+
+    /// Several processes and threads may share the cache, so we write the
+    /// result to a temporary file and rename it, which readers never see
+    /// half-written.
+    pub fn put<T: Serialize>(&self, key: u64, value: &T) -> std::io::Result<()> {
+        let text = serde_yaml::to_string(value)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+        let tmp = self.dir.join(format!(
+            "{:016x}.{}.{}.tmp",
+            key,
+            std::process::id(),
+            NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(tmp, self.path(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cache, Hasher};
+
+    #[test]
+    fn fnv_1a() {
+        assert_eq!(Hasher::new().finish(), 0xcbf29ce484222325);
+        let mut hasher = Hasher::new();
+        hasher.write_raw(b"a");
+        assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+        let hash = |parts: &[&str]| {
+            let mut hasher = Hasher::new();
+            for part in parts {
+                hasher.write(part.as_bytes());
+            }
+            hasher.finish()
+        };
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+        assert_ne!(
+            Hasher::new().write_opt(None).finish(),
+            Hasher::new().write_opt(Some(b"")).finish()
+        );
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let dir = std::env::temp_dir().join(format!("typewhich-cache-{}", std::process::id()));
+        let cache = Cache::new(&dir).unwrap();
+        assert_eq!(cache.get::<Vec<u64>>(1), None);
+        cache.put(1, &vec![1u64, 2, 3]).unwrap();
+        assert_eq!(cache.get::<Vec<u64>>(1), Some(vec![1, 2, 3]));
+        assert_eq!(cache.get::<String>(1), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn threads_put_the_same_key() {
+        let dir = std::env::temp_dir().join(format!("typewhich-race-{}", std::process::id()));
+        let cache = Cache::new(&dir).unwrap();
+        let threads = (0..8u64)
+            .map(|n| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        cache.put(1, &vec![n; 100]).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let result = cache.get::<Vec<u64>>(1).unwrap();
+        assert!(result.iter().all(|n| *n == result[0]));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,23 +1,75 @@
-use super::parser::next_metavar;
+use super::modular;
+use super::portfolio::shuffle;
+use super::presolve::{Ctor, Phi, UnionFind};
+use super::session::Session;
 use super::syntax::*;
 use super::z3_state::Z3State;
-use super::Options;
 use im_rc::HashMap;
 use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::sync::Mutex;
+use std::time::Instant;
 use z3::ast::{Ast, Bool, Dynamic};
 use z3::{Optimize, SatResult};
 
 type Env = HashMap<String, Typ>;
 
-struct State<'a> {
+/// The z3 crate cannot set the parameters of an `Optimize`, but Z3 reads the
+/// global parameters of the `opt` module when it creates one (which the test
+/// `optimizers_keep_their_engine` checks). Strategies in other threads must
+/// not see each other's engine, so the engine is only set while holding this
+/// lock, and restored before releasing it.
+static OPT_PARAMS: Mutex<()> = Mutex::new(());
+
+fn new_optimize<'a>(cxt: &'a z3::Context, engine: Option<&str>) -> Optimize<'a> {
+    let _lock = OPT_PARAMS.lock().unwrap_or_else(|err| err.into_inner());
+    let engine = match engine {
+        None => return Optimize::new(cxt),
+        Some(engine) => CString::new(engine).unwrap(),
+    };
+    let param = CString::new("opt.maxsat_engine").unwrap();
+    unsafe {
+        let mut old = std::ptr::null();
+        let old = if z3_sys::Z3_global_param_get(param.as_ptr(), &mut old) {
+            Some(CStr::from_ptr(old).to_owned())
+        } else {
+            None
+        };
+        z3_sys::Z3_global_param_set(param.as_ptr(), engine.as_ptr());
+        let solver = Optimize::new(cxt);
+        if let Some(old) = old {
+            z3_sys::Z3_global_param_set(param.as_ptr(), old.as_ptr());
+        }
+        solver
+    }
+}
+
+pub struct State<'a> {
     vars: RefCell<HashMap<u32, Dynamic<'a>>>,
     z3: Z3State<'a>,
-    solver: Optimize<'a>,
-    options: Options,
+    pub solver: Optimize<'a>,
+    session: Session,
+    /// Equalities that have been eliminated by the pre-solver
+    uf: RefCell<UnionFind>,
+    /// Coercions that we would like to be identities, which are sent to the
+    /// solver as soft constraints once the hard constraints are pre-solved
+    soft: RefCell<Vec<(Typ, Typ)>>,
 }
 
 impl<'a> State<'a> {
-    fn t2z3(&self, typ: &Typ) -> Dynamic<'a> {
+    pub fn new(z3: Z3State<'a>, session: &Session) -> Self {
+        let solver = new_optimize(z3.cxt, session.options.maxsat_engine.param());
+        State {
+            z3,
+            vars: Default::default(),
+            solver,
+            session: session.clone(),
+            uf: Default::default(),
+            soft: Default::default(),
+        }
+    }
+
+    pub fn t2z3(&self, typ: &Typ) -> Dynamic<'a> {
         match typ {
             Typ::Unit => self.z3.unit_z3.clone(),
             Typ::Int => self.z3.int_z3.clone(),
@@ -32,6 +84,10 @@ impl<'a> State<'a> {
             Typ::Vect(t) => self.z3.vect_ctor.apply(&[&self.t2z3(t)]),
             Typ::Any => self.z3.any_z3.clone(),
             Typ::Metavar(n) => {
+                let rep = self.uf.borrow_mut().find(typ);
+                if &rep != typ {
+                    return self.t2z3(&rep);
+                }
                 let mut vars = self.vars.borrow_mut();
                 match vars.get(n) {
                     Some(ast) => ast.clone(),
@@ -50,12 +106,114 @@ impl<'a> State<'a> {
         }
     }
 
-    fn cgen(&self, env: &Env, exp: &mut Exp) -> (Typ, Bool<'a>) {
+    fn phi2z3(&self, phi: &Phi) -> Bool<'a> {
+        match phi {
+            Phi::True => self.z3.true_z3(),
+            Phi::Eq(t1, t2) => self.t2z3(t1)._eq(&self.t2z3(t2)),
+            Phi::Is(Ctor::Arr, t) => self.z3.z3_is_arr(self.t2z3(t)),
+            Phi::Is(Ctor::List, t) => self.z3.z3_is_list(self.t2z3(t)),
+            Phi::Is(Ctor::Pair, t) => self.z3.z3_is_pair(self.t2z3(t)),
+            Phi::Is(Ctor::Box, t) => self.z3.z3_is_box(self.t2z3(t)),
+            Phi::Is(Ctor::Vect, t) => self.z3.z3_is_vect(self.t2z3(t)),
+            Phi::And(phis) => phis
+                .iter()
+                .fold(self.z3.true_z3(), |acc, phi| acc & self.phi2z3(phi)),
+            Phi::Or(phis) => phis
+                .iter()
+                .fold(self.z3.false_z3(), |acc, phi| acc | self.phi2z3(phi)),
+            Phi::Implies(phi1, phi2) => self.phi2z3(phi1).implies(&self.phi2z3(phi2)),
+        }
+    }
+
+    /// Sends the constraints to the solver, after eliminating the equalities
+    /// that the pre-solver can handle on its own.
+    fn assert_constraints(&self, phi: Phi) {
+        let phi = if self.session.options.presolve {
+            phi.presolve(&mut self.uf.borrow_mut())
+        } else {
+            phi
+        };
+        // Give every representative a Z3 variable, even if no remaining
+        // constraint mentions it, so that the model assigns it a type.
+        let bound = self.uf.borrow().bound();
+        for x in bound {
+            self.t2z3(&Typ::Metavar(x));
+        }
+        let phi_z3 = self.phi2z3(&phi);
+        if self.session.options.debug {
+            eprintln!(
+                "Pre-solver eliminated {} metavariables",
+                self.uf.borrow().bound().len()
+            );
+            eprintln!("Simplified constraints:");
+            eprintln!("{}", phi_z3.simplify());
+        }
+        self.solver.assert(&phi_z3);
+        if self.session.options.seed != 0 {
+            shuffle(&mut self.soft.borrow_mut(), self.session.options.seed);
+        }
+        let mut soft_constraints = 0;
+        for (t1, t2) in self.soft.borrow().iter() {
+            let t1 = self.uf.borrow_mut().resolve(t1);
+            let t2 = self.uf.borrow_mut().resolve(t2);
+            // Trivially satisfied, so the solver need not bother
+            if t1 != t2 {
+                self.solver
+                    .assert_soft(&self.t2z3(&t1)._eq(&self.t2z3(&t2)), 1, None);
+                soft_constraints += 1;
+            }
+        }
+        let variables = self.vars.borrow().len();
+        self.session.record_solver_stats(|stats| {
+            stats.variables += variables;
+            stats.soft_constraints += soft_constraints;
+        });
+    }
+
+    /// Checks the constraints, unless the session has been interrupted
+    /// before or while checking.
+    fn check(&self) -> Result<SatResult, String> {
+        let interrupted = || self.session.interrupt.is_interrupted();
+        if interrupted() {
+            return Err("interrupted".to_string());
+        }
+        let start = Instant::now();
+        // Only interrupt Z3 while it searches: an interrupted context fails
+        // to evaluate models too.
+        let handle = self.z3.cxt.handle();
+        let result = self
+            .session
+            .interrupt
+            .stop_during(|| handle.interrupt(), || self.solver.check(&[]));
+        let seconds = start.elapsed().as_secs_f64();
+        self.session.record_solver_stats(|stats| {
+            stats.checks += 1;
+            stats.seconds += seconds;
+        });
+        // An interrupted optimizer may report sat without a model.
+        if interrupted() {
+            return Err("interrupted".to_string());
+        }
+        Ok(result)
+    }
+
+    /// Sends the constraints of one group to the solver, guarded by `lit`, so
+    /// that the group can be retired later by asserting `!lit`. Does not
+    /// pre-solve, since unification cannot be undone.
+    pub fn assert_group(&self, lit: &Bool<'a>, phi: Phi) {
+        self.solver.assert(&lit.implies(&self.phi2z3(&phi)));
+        for (t1, t2) in self.soft.borrow_mut().drain(..) {
+            let eq = self.t2z3(&t1)._eq(&self.t2z3(&t2));
+            self.solver.assert_soft(&lit.implies(&eq), 1, None);
+        }
+    }
+
+    pub fn cgen(&self, env: &Env, exp: &mut Exp) -> (Typ, Phi) {
         match exp {
             Exp::PrimCoerce(..) => panic!("PrimCoerce should not appear in source"),
             // ---------------------------
             // Γ ⊢ lit => coerce(lit.typ(), α, lit), α, weaken(lit.typ(), α)
-            Exp::Lit(lit) => self.weaken(lit.typ(), exp, self.z3.true_z3()),
+            Exp::Lit(lit) => self.weaken(lit.typ(), exp, Phi::True),
             // ---------------------------
             // Γ ⊢ x => x, Γ(x), true
             Exp::Var(x) => {
@@ -64,10 +222,10 @@ impl<'a> State<'a> {
                     .unwrap_or_else(|| panic!("unbound identifier {}", x))
                     .clone();
 
-                if self.options.rigid_vars {
-                    (typ, self.z3.true_z3())
+                if self.session.options.rigid_vars {
+                    (typ, Phi::True)
                 } else {
-                    self.weaken(typ, exp, self.z3.true_z3())
+                    self.weaken(typ, exp, Phi::True)
                 }
             }
             // Γ,x:T_1 ⊢ e => T_2, φ
@@ -89,7 +247,7 @@ impl<'a> State<'a> {
                 let mut env = env.clone();
                 env.insert(x.clone(), t1.clone());
                 let (t2, phi1) = self.cgen(&env, body);
-                let phi2 = self.t2z3(t1)._eq(&self.t2z3(&t2));
+                let phi2 = Phi::eq(t1, &t2);
                 self.weaken(t1.clone(), exp, phi1 & phi2)
             }
             // Γ ⊢ e_1 => T_1, φ_1
@@ -101,11 +259,11 @@ impl<'a> State<'a> {
             Exp::App(e1, e2) => {
                 let (t1, phi1) = self.cgen(&env, e1);
                 let (t2, phi2) = self.cgen(&env, e2);
-                let alpha = next_metavar();
-                let beta = next_metavar();
+                let alpha = self.session.next_metavar();
+                let beta = self.session.next_metavar();
                 let arr = Typ::Arr(Box::new(alpha.clone()), Box::new(beta.clone()));
                 let phi3 = self.strengthen(t1.clone(), arr, e1);
-                let phi4 = self.t2z3(&t2)._eq(&self.t2z3(&alpha));
+                let phi4 = Phi::eq(&t2, &alpha);
                 self.weaken(beta, exp, phi1 & phi2 & phi3 & phi4)
             }
             // Γ ⊢ e => T, φ
@@ -151,9 +309,9 @@ impl<'a> State<'a> {
                 for (xi, ti, _) in es.iter() {
                     env.insert(xi.clone(), ti.clone());
                 }
-                let phis = es.iter_mut().fold(self.z3.true_z3(), |acc, (_, ti, ei)| {
+                let phis = es.iter_mut().fold(Phi::True, |acc, (_, ti, ei)| {
                     let (si, phii) = self.cgen(&env, ei);
-                    acc & self.t2z3(ti)._eq(&self.t2z3(&si)) & phii
+                    acc & Phi::eq(ti, &si) & phii
                 });
                 let (t, phi) = self.cgen(&env, e);
                 (t, phi & phis)
@@ -161,7 +319,7 @@ impl<'a> State<'a> {
             // Γ ⊢ e1 => T_1, φ_1
             // -------------------
             // Γ ⊢ e1 : T => coerce(T_1, T) e, T, φ_1 && ground(T_1) && ground(T)
-            Exp::Ann(e, typ) => {
+            Exp::Ann(e, typ, _) => {
                 let (t1, phi1) = self.cgen(env, e);
                 let phi2 = self.ground(&t1) & self.ground(&typ);
                 (typ.clone(), phi1 & phi2)
@@ -177,11 +335,9 @@ impl<'a> State<'a> {
             Exp::AddOverload(e1, e2) => {
                 let (t1, phi1) = self.cgen(&env, e1);
                 let (t2, phi2) = self.cgen(&env, e2);
-                let t1_z3 = self.t2z3(&t1);
-                let eq = t1_z3._eq(&self.t2z3(&t2));
-                let valid_type = t1_z3._eq(&self.z3.int_z3)
-                    | t1_z3._eq(&self.z3.str_z3)
-                    | t1_z3._eq(&self.z3.any_z3);
+                let eq = Phi::eq(&t1, &t2);
+                let valid_type =
+                    Phi::eq(&t1, &Typ::Int) | Phi::eq(&t1, &Typ::Str) | Phi::eq(&t1, &Typ::Any);
                 self.weaken(t1, exp, phi1 & phi2 & eq & valid_type)
             }
             // Γ ⊢ e_1 => T_1, φ_1
@@ -195,7 +351,7 @@ impl<'a> State<'a> {
                 let (t1, phi1) = self.cgen(&env, e1);
                 let (t2, phi2) = self.cgen(&env, e2);
                 let (t3, phi3) = self.cgen(&env, e3);
-                let phi4 = self.strengthen(t1, Typ::Bool, e1) & self.t2z3(&t2)._eq(&self.t2z3(&t3));
+                let phi4 = self.strengthen(t1, Typ::Bool, e1) & Phi::eq(&t2, &t3);
                 (t2, phi1 & phi2 & phi3 & phi4)
             }
             // Γ ⊢ e_1 => T_1, φ_1
@@ -214,8 +370,8 @@ impl<'a> State<'a> {
             //              φ_1 && strengthen(T_1, Pair(α,β)) && weaken(α, γ)
             Exp::Fst(e) => {
                 let (t1, phi1) = self.cgen(&env, e);
-                let alpha = next_metavar();
-                let beta = next_metavar();
+                let alpha = self.session.next_metavar();
+                let beta = self.session.next_metavar();
                 let phi2 =
                     self.strengthen(t1, Typ::Pair(Box::new(alpha.clone()), Box::new(beta)), e);
                 self.weaken(alpha, exp, phi1 & phi2)
@@ -226,8 +382,8 @@ impl<'a> State<'a> {
             //              φ_1 && strengthen(T_1, Pair(α,β)) && weaken(β, γ)
             Exp::Snd(e) => {
                 let (t1, phi1) = self.cgen(&env, e);
-                let alpha = next_metavar();
-                let beta = next_metavar();
+                let alpha = self.session.next_metavar();
+                let beta = self.session.next_metavar();
                 let phi2 =
                     self.strengthen(t1, Typ::Pair(Box::new(alpha), Box::new(beta.clone())), e);
                 self.weaken(beta, exp, phi1 & phi2)
@@ -246,16 +402,14 @@ impl<'a> State<'a> {
             }
             // ----------------------------------------------
             // Γ ⊢ empty α => coerce(List(α), β, empty α), β, weaken(List(α), β)
-            Exp::Empty(alpha) => {
-                self.weaken(Typ::List(Box::new(alpha.clone())), exp, self.z3.true_z3())
-            }
+            Exp::Empty(alpha) => self.weaken(Typ::List(Box::new(alpha.clone())), exp, Phi::True),
             // Γ ⊢ e => T, φ
             // ----------------------------------------------
             // Γ ⊢ head e => coerce(α, β) head coerce(T, List(α), e), β,
             //               φ && strengthen(T, List(α)) && weaken(α, β)
             Exp::Head(e) => {
                 let (t, phi1) = self.cgen(env, e);
-                let alpha = next_metavar();
+                let alpha = self.session.next_metavar();
                 let phi2 = self.strengthen(t, Typ::List(Box::new(alpha.clone())), e);
                 self.weaken(alpha, exp, phi1 & phi2)
             }
@@ -265,7 +419,7 @@ impl<'a> State<'a> {
             //               φ && strengthen(T, List(α)) && weaken(List(α), β)
             Exp::Tail(e) => {
                 let (t, phi1) = self.cgen(env, e);
-                let alpha = next_metavar();
+                let alpha = self.session.next_metavar();
                 let list_alpha = Typ::List(Box::new(alpha));
                 let phi2 = self.strengthen(t, list_alpha.clone(), e);
                 self.weaken(list_alpha, exp, phi1 & phi2)
@@ -276,7 +430,7 @@ impl<'a> State<'a> {
             //                   φ && strengthen(T, List(α)) && weaken(bool, β)
             Exp::IsEmpty(e) => {
                 let (t, phi1) = self.cgen(env, e);
-                let alpha = next_metavar();
+                let alpha = self.session.next_metavar();
                 let list_alpha = Typ::List(Box::new(alpha));
                 let phi2 = self.strengthen(t, list_alpha, e);
                 self.weaken(Typ::Bool, exp, phi1 & phi2)
@@ -294,7 +448,7 @@ impl<'a> State<'a> {
             //                && strengthen(T, Box(α)) && weaken(α, β)
             Exp::Unbox(e) => {
                 let (t, phi1) = self.cgen(env, e);
-                let alpha = next_metavar();
+                let alpha = self.session.next_metavar();
                 let phi2 = self.strengthen(t, Typ::Box(Box::new(alpha.clone())), e);
                 self.weaken(alpha, exp, phi1 & phi2)
             }
@@ -330,7 +484,7 @@ impl<'a> State<'a> {
             Exp::VectorRef(e1, e2) => {
                 let (t1, phi1) = self.cgen(env, e1);
                 let (t2, phi2) = self.cgen(env, e2);
-                let alpha = next_metavar();
+                let alpha = self.session.next_metavar();
                 let phi3 = self.strengthen(t1, Typ::Vect(Box::new(alpha.clone())), e1);
                 let phi4 = self.strengthen(t2, Typ::Int, e2);
                 self.weaken(alpha, exp, phi1 & phi2 & phi3 & phi4)
@@ -356,7 +510,7 @@ impl<'a> State<'a> {
             //                        φ && strengthen(T, Vect(α)) && weaken(int, β)
             Exp::VectorLen(e) => {
                 let (t, phi1) = self.cgen(env, e);
-                let alpha = next_metavar();
+                let alpha = self.session.next_metavar();
                 let phi2 = self.strengthen(t, Typ::Vect(Box::new(alpha)), e);
                 self.weaken(Typ::Int, exp, phi1 & phi2)
             }
@@ -365,29 +519,36 @@ impl<'a> State<'a> {
             // Γ ⊢ is_GROUND e => coerce(bool, α) is_GROUND e, α, φ && T = any && weaken(bool, α)
             Exp::IsBool(e) | Exp::IsInt(e) | Exp::IsString(e) | Exp::IsList(e) | Exp::IsFun(e) => {
                 let (t, phi1) = self.cgen(env, e);
-                let phi2 = self.t2z3(&t)._eq(&self.z3.any_z3);
+                let phi2 = Phi::eq(&t, &Typ::Any);
                 self.weaken(Typ::Bool, exp, phi1 & phi2)
             }
             // Γ ⊢ e => T_3, φ
             // ----------------------------------------------
             // Γ ⊢ coerce(T_1, T_2) e => coerce(T_1, T_2) e, T_2, φ && T_1 = T_3
-            Exp::Coerce(t1, t2, e) => {
+            Exp::Coerce(t1, t2, e, _) => {
                 let (t3, phi) = self.cgen(env, e);
-                if self.options.optimizer {
-                    self.solver
-                        .assert_soft(&self.t2z3(&t1)._eq(&self.t2z3(&t2)), 1, None);
+                if self.session.options.optimizer {
+                    self.soft.borrow_mut().push((t1.clone(), t2.clone()));
                 }
-                (t2.clone(), phi & self.t2z3(&t1)._eq(&self.t2z3(&t3)))
+                (t2.clone(), phi & Phi::eq(&t1, &t3))
             }
         }
     }
 
-    fn solve_model(&self, model: z3::Model) -> HashMap<u32, Typ> {
+    pub fn solve_model(&self, model: z3::Model) -> HashMap<u32, Typ> {
         let mut result = HashMap::new();
         for (x, x_ast) in self.vars.borrow().iter() {
             let x_val_ast = model.eval(x_ast).expect("evaluating metavar");
             result.insert(*x, self.z3.z3_to_typ(&model, x_val_ast));
         }
+        // Metavariables eliminated by the pre-solver get the type of their
+        // representative
+        let bound = self.uf.borrow().bound();
+        for x in bound {
+            let mut t = self.uf.borrow_mut().resolve(&Typ::Metavar(x));
+            annotate_typ(&result, &mut t);
+            result.insert(x, t);
+        }
         result
     }
 
@@ -397,7 +558,7 @@ impl<'a> State<'a> {
     /// DO NOT evaluate (model.eval) t before passing in. model.eval
     /// recursively evaluates. we only want to get the kind and its
     /// metavariables
-    fn negative_any(&self, model: &z3::Model<'a>, t: &Dynamic<'a>) -> Bool<'a> {
+    pub fn negative_any(&self, model: &z3::Model<'a>, t: &Dynamic<'a>) -> Bool<'a> {
         if self.z3.is_int(model, &t)
             || self.z3.is_unit(model, &t)
             || self.z3.is_float(model, &t)
@@ -433,11 +594,10 @@ impl<'a> State<'a> {
     }
 
     fn coerce(&self, t1: Typ, t2: Typ, exp: &mut Exp) {
-        if self.options.optimizer {
-            self.solver
-                .assert_soft(&self.t2z3(&t1)._eq(&self.t2z3(&t2)), 1, None);
+        if self.session.options.optimizer {
+            self.soft.borrow_mut().push((t1.clone(), t2.clone()));
         }
-        *exp = Exp::Coerce(t1, t2, Box::new(exp.take()));
+        *exp = Exp::Coerce(t1, t2, Box::new(exp.take()), None);
     }
 
     /// Modifies `exp` in place to coerce from t1 to t2. Generates a
@@ -452,10 +612,10 @@ impl<'a> State<'a> {
     ///
     /// T_1 = T_2 || (T_1 = any && ground(t2))
     #[must_use]
-    fn strengthen(&self, t1: Typ, t2: Typ, exp: &mut Exp) -> Bool<'a> {
-        let coerce_case = self.t2z3(&t1)._eq(&self.z3.any_z3) & self.ground(&t2);
+    fn strengthen(&self, t1: Typ, t2: Typ, exp: &mut Exp) -> Phi {
+        let coerce_case = Phi::eq(&t1, &Typ::Any) & self.ground(&t2);
         // we don't care about putting an ID coercion, that's fine
-        let dont_coerce_case = self.t2z3(&t1)._eq(&self.t2z3(&t2));
+        let dont_coerce_case = Phi::eq(&t1, &t2);
         self.coerce(t1, t2, exp);
         coerce_case | dont_coerce_case
     }
@@ -481,10 +641,10 @@ impl<'a> State<'a> {
     /// ----------------------------------------------
     /// Γ ⊢ e: T => coerce(T, α, e), α, φ
     ///             && T = α || (α = any && ground(T))      |> weaken'
-    fn weaken(&self, t1: Typ, exp: &mut Exp, phi1: Bool<'a>) -> (Typ, Bool<'a>) {
-        let alpha = next_metavar();
-        let coerce_case = self.t2z3(&alpha)._eq(&self.z3.any_z3) & self.ground(&t1);
-        let dont_coerce_case = self.t2z3(&t1)._eq(&self.t2z3(&alpha));
+    fn weaken(&self, t1: Typ, exp: &mut Exp, phi1: Phi) -> (Typ, Phi) {
+        let alpha = self.session.next_metavar();
+        let coerce_case = Phi::eq(&alpha, &Typ::Any) & self.ground(&t1);
+        let dont_coerce_case = Phi::eq(&t1, &alpha);
         self.coerce(t1, alpha.clone(), exp);
         (alpha, phi1 & (coerce_case | dont_coerce_case))
     }
@@ -513,28 +673,50 @@ impl<'a> State<'a> {
     ///                    && is_list(t) => t = list any
     ///                    && is_box(t) => t = box any
     ///                    && is_vect(t) => t = vect any
-    fn ground(&self, t: &Typ) -> Bool<'a> {
+    ///
+    /// With `modular`, a type without metavariables does not need a
+    /// constraint at all. Modular migration puts the solved types of earlier
+    /// definitions in the environment, e.g., `any -> int`, which is safe to
+    /// coerce to any.
+    fn ground(&self, t: &Typ) -> Phi {
+        if self.session.options.modular && !has_metavars(t) {
+            return if weakens_to_any(t) {
+                Phi::True
+            } else {
+                Phi::Or(vec![])
+            };
+        }
         let any_to_any = Typ::Arr(Box::new(Typ::Any), Box::new(Typ::Any));
-        self.z3
-            .z3_is_arr(self.t2z3(t))
-            .implies(&self.t2z3(t)._eq(&self.t2z3(&any_to_any)))
-            & self
-                .z3
-                .z3_is_list(self.t2z3(t))
-                .implies(&self.t2z3(t)._eq(&self.t2z3(&Typ::List(Box::new(Typ::Any)))))
-            & self.z3.z3_is_pair(self.t2z3(t)).implies(
-                &self
-                    .t2z3(t)
-                    ._eq(&self.t2z3(&Typ::Pair(Box::new(Typ::Any), Box::new(Typ::Any)))),
-            )
-            & self
-                .z3
-                .z3_is_box(self.t2z3(t))
-                .implies(&self.t2z3(t)._eq(&self.t2z3(&Typ::Box(Box::new(Typ::Any)))))
-            & self
-                .z3
-                .z3_is_vect(self.t2z3(t))
-                .implies(&self.t2z3(t)._eq(&self.t2z3(&Typ::Vect(Box::new(Typ::Any)))))
+        Phi::is(Ctor::Arr, t).implies(Phi::eq(t, &any_to_any))
+            & Phi::is(Ctor::List, t).implies(Phi::eq(t, &Typ::List(Box::new(Typ::Any))))
+            & Phi::is(Ctor::Pair, t).implies(Phi::eq(
+                t,
+                &Typ::Pair(Box::new(Typ::Any), Box::new(Typ::Any)),
+            ))
+            & Phi::is(Ctor::Box, t).implies(Phi::eq(t, &Typ::Box(Box::new(Typ::Any))))
+            & Phi::is(Ctor::Vect, t).implies(Phi::eq(t, &Typ::Vect(Box::new(Typ::Any))))
+    }
+}
+
+fn has_metavars(t: &Typ) -> bool {
+    match t {
+        Typ::Metavar(_) => true,
+        Typ::Arr(t1, t2) | Typ::Pair(t1, t2) => has_metavars(t1) || has_metavars(t2),
+        Typ::List(t) | Typ::Box(t) | Typ::Vect(t) => has_metavars(t),
+        Typ::Unit | Typ::Int | Typ::Float | Typ::Bool | Typ::Str | Typ::Char | Typ::Any => false,
+    }
+}
+
+/// Whether a type has any in all of its negative positions, so that it is
+/// safe to coerce to any. This is `ground` without the restrictions that Z3
+/// imposes: the result of a function may be any type that is safe.
+fn weakens_to_any(t: &Typ) -> bool {
+    match t {
+        Typ::Arr(t1, t2) => **t1 == Typ::Any && weakens_to_any(t2),
+        Typ::Pair(t1, t2) => **t1 == Typ::Any && **t2 == Typ::Any,
+        Typ::List(t) | Typ::Box(t) | Typ::Vect(t) => **t == Typ::Any,
+        Typ::Metavar(_) => false,
+        Typ::Unit | Typ::Int | Typ::Float | Typ::Bool | Typ::Str | Typ::Char | Typ::Any => true,
     }
 }
 
@@ -562,16 +744,16 @@ fn annotate_typ(env: &HashMap<u32, Typ>, t: &mut Typ) {
     }
 }
 
-fn annotate(env: &HashMap<u32, Typ>, exp: &mut Exp) {
+pub fn annotate(env: &HashMap<u32, Typ>, exp: &mut Exp) {
     match &mut *exp {
         Exp::PrimCoerce(..) => panic!("PrimCoerce should not appear in source"),
         Exp::Lit(..) | Exp::Var(..) => {}
         Exp::Empty(t) => annotate_typ(env, t),
-        Exp::Fun(_, t, e) | Exp::Fix(_, t, e) | Exp::Ann(e, t) => {
+        Exp::Fun(_, t, e) | Exp::Fix(_, t, e) | Exp::Ann(e, t, _) => {
             annotate_typ(env, t);
             annotate(env, e);
         }
-        Exp::Coerce(t1, t2, e) => {
+        Exp::Coerce(t1, t2, e, _) => {
             annotate(env, e);
             annotate_typ(env, t1);
             annotate_typ(env, t2);
@@ -623,40 +805,69 @@ fn annotate(env: &HashMap<u32, Typ>, exp: &mut Exp) {
 }
 
 #[cfg(test)]
-pub fn typeinf(exp: Exp) -> Result<Exp, String> {
-    typeinf_options(exp, &Default::default(), Options::default())
+pub fn typeinf(program: &str) -> Result<Exp, String> {
+    let session = Session::default();
+    let exp = super::parser::parse(&session, program)?;
+    typeinf_options(&session, exp, &Default::default())
+}
+
+/// Migrates `exp`, which must have been parsed with `session`, using the
+/// options of the session.
+pub fn typeinf_options(session: &Session, exp: Exp, env: &Env) -> Result<Exp, String> {
+    match exp {
+        Exp::LetRec(..) if session.options.modular => modular::typeinf_modular(session, exp, env),
+        _ => solve(session, exp, env, false),
+    }
+}
+
+/// Migrates a group of mutually recursive bindings on their own. With
+/// context, the types of the bindings are safe to use in any context.
+pub fn typeinf_bindings(
+    session: &Session,
+    bindings: Vec<(Id, Typ, Exp)>,
+    env: &Env,
+) -> Result<Vec<(Id, Typ, Exp)>, String> {
+    let exp = Exp::LetRec(bindings, Box::new(Exp::Lit(Lit::Unit)));
+    match solve(session, exp, env, true)? {
+        Exp::LetRec(bindings, _) => Ok(bindings),
+        _ => unreachable!("annotate does not change the shape of a letrec"),
+    }
 }
-pub fn typeinf_options(mut exp: Exp, env: &Env, options: Options) -> Result<Exp, String> {
+
+/// When `export_bindings` is set, `exp` must be a `letrec`, and context
+/// applies to the types of its bindings instead of the type of the whole
+/// expression.
+fn solve(session: &Session, mut exp: Exp, env: &Env, export_bindings: bool) -> Result<Exp, String> {
+    let options = &session.options;
     let cfg = z3::Config::new();
     let cxt = z3::Context::new(&cfg);
     let typ = Z3State::typ(&cxt);
-    let s = State {
-        z3: Z3State::new(&cxt, &typ),
-        vars: Default::default(),
-        solver: Optimize::new(&cxt),
-        options,
-    };
+    let s = State::new(Z3State::new(&cxt, &typ), session);
     let (t, phi) = s.cgen(env, &mut exp);
-    s.solver.assert(&phi);
-    if options.debug {
-        eprintln!("Simplified constraints:");
-        eprintln!("{}", phi.simplify());
-    }
-    if s.options.context {
+    s.assert_constraints(phi);
+    let exports = match &exp {
+        Exp::LetRec(bindings, _) if export_bindings => {
+            bindings.iter().map(|(_, t, _)| t.clone()).collect()
+        }
+        _ => vec![t],
+    };
+    if options.context {
         s.solver.push();
         if options.debug {
             eprintln!("Solver state for precise type:");
             eprintln!("{}", s.solver);
         }
-        match s.solver.check(&[]) {
+        match s.check()? {
             SatResult::Unsat => return Err("unsat (precise type)".to_string()),
             SatResult::Unknown => return Err("unknown from Z3 -- very bad".to_string()),
             SatResult::Sat => (),
         }
         let model = s.solver.get_model().expect("model not available");
         s.solver.pop();
-        let negative_any = s.negative_any(&model, &s.t2z3(&t));
-        s.solver.assert(&negative_any);
+        for t in exports.iter() {
+            let negative_any = s.negative_any(&model, &s.t2z3(t));
+            s.solver.assert(&negative_any);
+        }
         if options.debug {
             let mut exp_precise = exp.clone();
             let result = s.solve_model(model);
@@ -668,7 +879,7 @@ pub fn typeinf_options(mut exp: Exp, env: &Env, options: Options) -> Result<Exp,
         eprintln!("Solver state for final type:");
         eprintln!("{}", s.solver);
     }
-    match s.solver.check(&[]) {
+    match s.check()? {
         SatResult::Unsat => return Err("unsat (context)".to_string()),
         SatResult::Unknown => panic!("unknown from Z3 -- very bad"),
         SatResult::Sat => (),
@@ -682,36 +893,41 @@ pub fn typeinf_options(mut exp: Exp, env: &Env, options: Options) -> Result<Exp,
 #[cfg(test)]
 mod test {
     use super::super::parser::parse;
-    use super::typeinf;
+    use super::{new_optimize, typeinf, typeinf_options};
+    use crate::session::Session;
     use crate::syntax::Typ;
     use crate::tests_631::*;
+    use crate::type_check::type_check;
+    use crate::Options;
+    use z3::ast::Bool;
+    use z3::SatResult;
 
     #[test]
     fn test_typeinf() {
-        typeinf(parse("(fun x . x) 10 ").unwrap()).unwrap();
+        typeinf("(fun x . x) 10 ").unwrap();
     }
 
     #[test]
     fn identity_alone() {
-        println!("{:?}", typeinf(parse("fun x . x").unwrap()).unwrap())
+        println!("{:?}", typeinf("fun x . x").unwrap())
     }
 
     #[test]
     fn occurs_check_fun_any() {
         // In HM, this would be an occurs-check failure
-        println!("{:?}", typeinf(parse("fun f . f f").unwrap()).unwrap())
+        println!("{:?}", typeinf("fun f . f f").unwrap())
     }
 
     #[test]
     fn test_typeinf_add() {
-        typeinf(parse("(fun x . x +? 20) 10 ").unwrap()).unwrap();
+        typeinf("(fun x . x +? 20) 10 ").unwrap();
     }
 
     #[test]
     fn str_add() {
         println!(
             "{:?}",
-            typeinf(parse(r#"(fun x . x +? x) "everything is ""#).unwrap()).unwrap()
+            typeinf(r#"(fun x . x +? x) "everything is ""#).unwrap()
         );
     }
 
@@ -719,23 +935,23 @@ mod test {
     fn add_str_int_any() {
         println!(
             "{:?}",
-            typeinf(parse(r#"(fun x . fun y . x +? y) "everything is " 10"#).unwrap()).unwrap()
+            typeinf(r#"(fun x . fun y . x +? y) "everything is " 10"#).unwrap()
         );
     }
 
     #[test]
     fn infer_arr() {
-        println!("{:?}", typeinf(parse("fun f . f 200").unwrap()));
+        println!("{:?}", typeinf("fun f . f 200"));
     }
 
     #[test]
     fn ambiguous_add() {
-        println!("{:?}", typeinf(parse("fun x . x +? x").unwrap()).unwrap());
+        println!("{:?}", typeinf("fun x . x +? x").unwrap());
     }
 
     #[test]
     fn heterogenous_list() {
-        println!("{:?}", typeinf(parse("true :: 10 :: empty").unwrap()));
+        println!("{:?}", typeinf("true :: 10 :: empty"));
     }
 
     #[test]
@@ -846,6 +1062,90 @@ mod test {
         )
     }
 
+    #[test]
+    fn presolve_agrees() {
+        let progs = [
+            "fun f. fun x. f x",
+            "let id = fun x.x in let tmp0 = id true in id 5",
+            "let f = fun b.fun x. if b then x + 1 else not x in
+             let y = f true 5 in
+             f false false",
+            "fix map . fun f . fun lst .
+               if is_empty(lst) then
+                 empty
+               else
+                 f(head(lst)) :: (map f (tail(lst)))",
+        ];
+        for prog in progs.iter() {
+            let migrate = |options| {
+                let session = Session::new(options);
+                let exp = parse(&session, prog).unwrap();
+                typeinf_options(&session, exp, &Default::default())
+            };
+            let with = migrate(Options::default());
+            let without = migrate(Options {
+                presolve: false,
+                ..Options::default()
+            });
+            let with = with.unwrap();
+            let without = without.unwrap();
+            type_check(&with).unwrap();
+            assert_eq!(with, without, "\n{}\n{}", with, without);
+        }
+    }
+
+    #[test]
+    fn closed_types_only_weaken_with_modular() {
+        // Without modular, weakening a function to any still requires
+        // any -> any, so this program has no precise migration.
+        let migrate = |modular| {
+            let session = Session::new(Options {
+                context: false,
+                modular,
+                ..Options::default()
+            });
+            let exp = parse(&session, "fun f:any -> int. (f : any)").unwrap();
+            typeinf_options(&session, exp, &Default::default())
+        };
+        assert!(migrate(false).is_err());
+        assert!(migrate(true).is_ok());
+    }
+
+    #[test]
+    fn optimizers_keep_their_engine() {
+        // One of the soft constraints must be violated, and the engines
+        // violate different ones. Both optimizers are created before either
+        // one checks, so they only find different models if Z3 read the
+        // engine when they were created.
+        let cfg = z3::Config::new();
+        let cxt = z3::Context::new(&cfg);
+        let xs: Vec<Bool> = (0..5)
+            .map(|i| Bool::new_const(&cxt, format!("x{}", i)))
+            .collect();
+        let optimizers = [Some("maxres"), Some("wmax")]
+            .iter()
+            .map(|engine| new_optimize(&cxt, *engine))
+            .collect::<Vec<_>>();
+        let models = optimizers
+            .iter()
+            .map(|solver| {
+                solver.assert(&Bool::and(&cxt, &xs.iter().collect::<Vec<_>>()).not());
+                for x in xs.iter() {
+                    solver.assert_soft(x, 1, None);
+                }
+                assert_eq!(solver.check(&[]), SatResult::Sat);
+                let model = solver.get_model().unwrap();
+                xs.iter()
+                    .map(|x| model.eval(x).unwrap().as_bool().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for model in models.iter() {
+            assert_eq!(model.iter().filter(|x| !**x).count(), 1);
+        }
+        assert_ne!(models[0], models[1]);
+    }
+
     #[test]
     fn introduction_arr_in_any() {
         assert_eq!(
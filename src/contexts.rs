@@ -0,0 +1,183 @@
+//! Generates contexts that distinguish a program from the result of its
+//! migration.
+//!
+//! The contexts in `benchmarks.yaml` are written by hand. Here, we synthesize
+//! them from the types of the original and migrated programs: a context
+//! applies a function to arguments of every ground type, and to functions
+//! that return every ground type, and then uses the result in the same way.
+//! A context that works with the original program, but not with the result
+//! of migration, witnesses that migration restricted the program.
+use super::syntax::{Exp, Lit, Typ};
+use super::validate::runs_ok;
+
+/// Contexts apply the program to at most this many arguments
+const MAX_DEPTH: usize = 3;
+
+/// Programs that take more steps than this are assumed to diverge
+const FUEL: u64 = 1_000_000;
+
+fn hole() -> Exp {
+    Exp::Var("HOLE".to_string())
+}
+
+/// A value of every base type
+fn base_values() -> Vec<Exp> {
+    vec![
+        Exp::Lit(Lit::Int(0)),
+        Exp::Lit(Lit::Bool(true)),
+        Exp::Lit(Lit::Unit),
+        Exp::Lit(Lit::Float(0.0)),
+        Exp::Lit(Lit::Str(String::new())),
+        Exp::Lit(Lit::Char('a')),
+    ]
+}
+
+/// Values that the original program accepts at type `t`. We do not generate
+/// values of type list, pair, box, or vector, since the evaluator does not
+/// support them.
+fn values(t: &Typ) -> Vec<Exp> {
+    match t {
+        Typ::Any => {
+            let mut vals = base_values();
+            vals.extend(values(&Typ::Arr(Box::new(Typ::Any), Box::new(Typ::Any))));
+            vals
+        }
+        Typ::Arr(_, rng) => {
+            let results = match &**rng {
+                Typ::Any => base_values(),
+                rng => values(rng),
+            };
+            let mut vals: Vec<Exp> = results
+                .into_iter()
+                .map(|v| Exp::Fun("_".to_string(), Typ::Any, Box::new(v)))
+                .collect();
+            if **rng == Typ::Any {
+                vals.push(Exp::Fun(
+                    "x".to_string(),
+                    Typ::Any,
+                    Box::new(Exp::Var("x".to_string())),
+                ));
+            }
+            vals
+        }
+        _ => base_values()
+            .into_iter()
+            .filter(|v| match v {
+                Exp::Lit(l) => &l.typ() == t,
+                _ => false,
+            })
+            .collect(),
+    }
+}
+
+fn fun_typ(t: &Typ) -> Option<(&Typ, &Typ)> {
+    match t {
+        Typ::Arr(dom, rng) => Some((dom, rng)),
+        Typ::Any => Some((&Typ::Any, &Typ::Any)),
+        _ => None,
+    }
+}
+
+/// Contexts that use a value whose type is `original` in the original
+/// program, and `migrated` after migration. When migration did not change
+/// the type of an argument, one argument suffices.
+fn uses(original: &Typ, migrated: &Typ, depth: usize) -> Vec<Exp> {
+    let mut contexts = vec![hole()];
+    if depth == 0 {
+        return contexts;
+    }
+    let (dom, rng) = match fun_typ(original) {
+        Some(parts) => parts,
+        None => return contexts,
+    };
+    let (m_dom, m_rng) = fun_typ(migrated).unwrap_or((&Typ::Any, &Typ::Any));
+    let mut args = values(dom);
+    if dom == m_dom {
+        args.truncate(1);
+    }
+    for arg in args {
+        let app = Exp::App(Box::new(hole()), Box::new(arg));
+        for mut context in uses(rng, m_rng, depth - 1) {
+            context.plug(&app);
+            contexts.push(context);
+        }
+    }
+    contexts
+}
+
+/// Contexts for a program whose type is `original`, and `migrated` after
+/// migration, in the order that we try them
+pub fn contexts(original: &Typ, migrated: &Typ) -> Vec<Exp> {
+    uses(original, migrated, MAX_DEPTH)
+}
+
+/// The first generated context in which the original program runs without
+/// error, but the result of migration fails
+pub fn find_context(
+    original: &Exp,
+    migrated: &Exp,
+    t_original: &Typ,
+    t_migrated: &Typ,
+) -> Option<Exp> {
+    let plug = |context: &Exp, exp: &Exp| {
+        let mut plugged = context.clone();
+        plugged.plug(exp);
+        plugged
+    };
+    contexts(t_original, t_migrated)
+        .into_iter()
+        .find(|context| {
+            runs_ok(plug(context, original), FUEL) == Some(true)
+                && runs_ok(plug(context, migrated), FUEL) == Some(false)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::find_context;
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::syntax::{Exp, Typ};
+
+    fn exp(program: &str) -> Exp {
+        parse(&Session::default(), program).unwrap()
+    }
+
+    fn arr(t1: Typ, t2: Typ) -> Typ {
+        Typ::Arr(Box::new(t1), Box::new(t2))
+    }
+
+    #[test]
+    fn restricted_identity() {
+        let context = find_context(
+            &exp("fun x . x"),
+            &exp("fun x : int . x"),
+            &arr(Typ::Any, Typ::Any),
+            &arr(Typ::Int, Typ::Int),
+        );
+        assert!(context.is_some());
+    }
+
+    #[test]
+    fn compatible_increment() {
+        let context = find_context(
+            &exp("fun x . x + 1"),
+            &exp("fun x : int . x + 1"),
+            &arr(Typ::Any, Typ::Int),
+            &arr(Typ::Int, Typ::Int),
+        );
+        assert!(context.is_none());
+    }
+
+    #[test]
+    fn restricted_higher_order() {
+        // f is never applied, so the original program accepts any value
+        let context = find_context(
+            &exp("fun f . fun y . y"),
+            &exp("fun f : int -> int . fun y : int . y"),
+            &arr(Typ::Any, arr(Typ::Any, Typ::Any)),
+            &arr(arr(Typ::Int, Typ::Int), arr(Typ::Int, Typ::Int)),
+        );
+        assert!(context.is_some());
+    }
+}
@@ -0,0 +1,273 @@
+//! Differential testing of the two inference engines, TypeWhich and
+//! ins-and-outs.
+//!
+//! We migrate each program with both engines and compare the types they give
+//! to every binder, using `precision::typ_lt`. A program where one engine is
+//! more precise on some binders, and the other engine on others, is
+//! incomparable. A program is unsafe for an engine if the original program
+//! runs without error, but the result of migrating it with the engine fails.
+//! The report counts programs by outcome, and groups the binders on which the
+//! engines disagree by the pair of types they infer.
+use super::fuzz::{self, migrate, migrate_ins_and_outs};
+use super::grift;
+use super::precision::typ_lt;
+use super::session::Session;
+use super::syntax::{Exp, Typ};
+use super::test_suite::{self, guard};
+use super::validate::runs_ok;
+use super::{Annot, Options};
+use derive_more::Display;
+use im_rc::HashMap;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+type Env = HashMap<String, Typ>;
+
+/// Programs that take more steps than this are assumed to diverge
+const FUEL: u64 = 1_000_000;
+
+/// How the type that TypeWhich gives a binder compares to the type that
+/// ins-and-outs gives it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Display)]
+pub enum Comparison {
+    #[display(fmt = "same")]
+    Same,
+    #[display(fmt = "TypeWhich more precise")]
+    TypeWhichMorePrecise,
+    #[display(fmt = "ins-and-outs more precise")]
+    InsAndOutsMorePrecise,
+    #[display(fmt = "incomparable")]
+    Incomparable,
+}
+
+/// How the engines compare on a whole program
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Display)]
+pub enum Outcome {
+    #[display(fmt = "agree")]
+    Agree,
+    #[display(fmt = "TypeWhich more precise")]
+    TypeWhichMorePrecise,
+    #[display(fmt = "ins-and-outs more precise")]
+    InsAndOutsMorePrecise,
+    #[display(fmt = "incomparable")]
+    Incomparable,
+    #[display(fmt = "TypeWhich unsafe")]
+    TypeWhichUnsafe,
+    #[display(fmt = "ins-and-outs unsafe")]
+    InsAndOutsUnsafe,
+    #[display(fmt = "both unsafe")]
+    BothUnsafe,
+}
+
+/// The outcome of one program, and the binders on which the engines
+/// disagree, with the pair of types that they infer
+struct Diff {
+    outcome: Outcome,
+    binders: Vec<(Comparison, String)>,
+}
+
+pub fn compare(t_typewhich: &Typ, t_ins_and_outs: &Typ) -> Comparison {
+    match (
+        typ_lt(t_typewhich, t_ins_and_outs),
+        typ_lt(t_ins_and_outs, t_typewhich),
+    ) {
+        (true, true) => Comparison::Same,
+        (true, false) => Comparison::TypeWhichMorePrecise,
+        (false, true) => Comparison::InsAndOutsMorePrecise,
+        (false, false) => Comparison::Incomparable,
+    }
+}
+
+/// Combines the comparisons of every binder of a program
+pub fn outcome(comparisons: &[Comparison]) -> Outcome {
+    let has = |c| comparisons.contains(&c);
+    if has(Comparison::Incomparable)
+        || (has(Comparison::TypeWhichMorePrecise) && has(Comparison::InsAndOutsMorePrecise))
+    {
+        Outcome::Incomparable
+    } else if has(Comparison::TypeWhichMorePrecise) {
+        Outcome::TypeWhichMorePrecise
+    } else if has(Comparison::InsAndOutsMorePrecise) {
+        Outcome::InsAndOutsMorePrecise
+    } else {
+        Outcome::Agree
+    }
+}
+
+/// Migrates `original` with both engines and compares the results. Produces
+/// an error when either engine fails, or when the results do not have the
+/// same binders.
+fn diff(original: &Exp, env: &Env) -> Result<Diff, String> {
+    guard(|| {
+        let session = Session::new(Options {
+            annot: Annot::Ignore,
+            ..Options::default()
+        });
+        let typewhich = migrate(&session, env, original)?;
+        let ins_and_outs = migrate_ins_and_outs(original);
+        let (b1, b2) = (typewhich.binders(), ins_and_outs.binders());
+        if b1.len() != b2.len() {
+            return Err("the engines produced different binders".to_string());
+        }
+        let binders = b1
+            .iter()
+            .zip(b2.iter())
+            .map(|((_, t1), (_, t2))| (compare(t1, t2), format!("{} vs {}", t1, t2)))
+            .filter(|(c, _)| *c != Comparison::Same)
+            .collect::<Vec<_>>();
+        let mut dynamic = original.clone();
+        dynamic.dynamic_metavars();
+        let unsafe_typewhich = runs_ok(typewhich, FUEL) == Some(false);
+        let unsafe_ins_and_outs = runs_ok(ins_and_outs, FUEL) == Some(false);
+        let unsafety = match (unsafe_typewhich, unsafe_ins_and_outs) {
+            (false, false) => None,
+            _ if runs_ok(dynamic, FUEL) != Some(true) => None,
+            (true, false) => Some(Outcome::TypeWhichUnsafe),
+            (false, true) => Some(Outcome::InsAndOutsUnsafe),
+            (true, true) => Some(Outcome::BothUnsafe),
+        };
+        let comparisons = binders.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+        Ok(Diff {
+            outcome: unsafety.unwrap_or_else(|| outcome(&comparisons)),
+            binders,
+        })
+    })
+}
+
+/// The programs in `inputs`, each of which is a program or a directory of
+/// programs
+fn paths(inputs: &[String]) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            test_suite::programs(path, &mut paths)?;
+        } else {
+            paths.push(path.to_path_buf());
+        }
+    }
+    Ok(paths)
+}
+
+fn diff_file(path: &Path) -> Result<Diff, String> {
+    let source = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let env = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("grift") => grift::env(),
+        _ => Env::new(),
+    };
+    let original = guard(|| test_suite::parse(&Session::default(), path, &source))?;
+    diff(&original, &env)
+}
+
+/// Compares the engines on the programs in `inputs` and on `random` programs
+/// from the fuzzer. Prints the outcome of every program, the number of
+/// programs with each outcome, and the binders on which the engines
+/// disagree, grouped by the types they infer.
+pub fn diff_engines_main(
+    inputs: &[String],
+    random: usize,
+    seed: u64,
+    size: usize,
+) -> std::io::Result<()> {
+    // Panics are reported as skipped programs, so the default message is noise.
+    std::panic::set_hook(Box::new(|_| ()));
+    let mut results = Vec::new();
+    for path in paths(inputs)? {
+        results.push((path.to_string_lossy().to_string(), diff_file(&path)));
+    }
+    let mut generator = fuzz::Generator::new(seed, fuzz::Features::default());
+    for i in 0..random {
+        results.push((
+            format!("random #{}", i),
+            diff(&generator.program(size), &Env::new()),
+        ));
+    }
+    let _ = std::panic::take_hook();
+
+    let mut outcomes = BTreeMap::new();
+    let mut skipped = 0;
+    let mut patterns = BTreeMap::new();
+    println!("{:50} outcome", "program");
+    for (name, result) in results {
+        match result {
+            Ok(diff) => {
+                println!("{:50} {}", name, diff.outcome);
+                *outcomes.entry(diff.outcome).or_insert(0) += 1;
+                for pattern in diff.binders {
+                    let (count, _) = patterns.entry(pattern).or_insert((0, name.clone()));
+                    *count += 1;
+                }
+            }
+            Err(msg) => {
+                println!("{:50} skipped: {}", name, msg);
+                skipped += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("Outcomes:");
+    for (outcome, count) in outcomes {
+        println!("  {:30} {}", outcome.to_string(), count);
+    }
+    println!("  {:30} {}", "skipped", skipped);
+    println!();
+    println!("Binder disagreements by pattern:");
+    for ((comparison, types), (count, example)) in patterns {
+        println!(
+            "  {:30} {:40} {:5} (e.g., {})",
+            comparison.to_string(),
+            types,
+            count,
+            example
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compare, outcome, Comparison, Outcome};
+    use crate::syntax::Typ;
+
+    fn arr(t1: Typ, t2: Typ) -> Typ {
+        Typ::Arr(Box::new(t1), Box::new(t2))
+    }
+
+    #[test]
+    fn compare_binder_types() {
+        assert_eq!(compare(&Typ::Int, &Typ::Int), Comparison::Same);
+        assert_eq!(
+            compare(&Typ::Int, &Typ::Any),
+            Comparison::TypeWhichMorePrecise
+        );
+        assert_eq!(
+            compare(&Typ::Any, &arr(Typ::Int, Typ::Any)),
+            Comparison::InsAndOutsMorePrecise
+        );
+        assert_eq!(
+            compare(&arr(Typ::Int, Typ::Any), &arr(Typ::Any, Typ::Bool)),
+            Comparison::Incomparable
+        );
+        assert_eq!(compare(&Typ::Metavar(0), &Typ::Any), Comparison::Same);
+    }
+
+    #[test]
+    fn combine_binder_comparisons() {
+        use Comparison::*;
+        assert_eq!(outcome(&[]), Outcome::Agree);
+        assert_eq!(
+            outcome(&[TypeWhichMorePrecise]),
+            Outcome::TypeWhichMorePrecise
+        );
+        assert_eq!(
+            outcome(&[InsAndOutsMorePrecise, InsAndOutsMorePrecise]),
+            Outcome::InsAndOutsMorePrecise
+        );
+        assert_eq!(
+            outcome(&[TypeWhichMorePrecise, InsAndOutsMorePrecise]),
+            Outcome::Incomparable
+        );
+        assert_eq!(outcome(&[Incomparable]), Outcome::Incomparable);
+    }
+}
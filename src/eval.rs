@@ -1,140 +1,897 @@
 //! An evaluator for GTLC + extensions needed for the comparative evaluation.
+//!
+//! The evaluator is a CESK machine. The continuation is an explicit stack
+//! instead of the Rust stack, so deep recursion does not overflow, and calls
+//! in tail position do not grow the stack. Recursive bindings live in the
+//! store, since their values must be in the environment of the closures that
+//! they bind.
+//!
+//! Grift's I/O primitives read from a string that we supply, instead of
+//! stdin, and their output is captured in a string.
 use super::syntax::*;
 use derive_more::Display;
 use im_rc::HashMap;
+use std::fmt::Write;
 
-type Env<'a> = HashMap<&'a Id, Val<'a>>;
+type Addr = usize;
+
+#[derive(Clone, Debug)]
+enum Binding<'a> {
+    Val(Val<'a>),
+    /// A recursive binding, whose value is in the store
+    Rec(Addr),
+}
+
+type Env<'a> = HashMap<&'a Id, Binding<'a>>;
 
 #[derive(Clone, Debug)]
 enum Val<'a> {
     Lit(Lit),
-    Closure(Env<'a>, &'a Id, &'a Exp, Coerce, Coerce),
+    Closure(Env<'a>, &'a Id, &'a Typ, &'a Exp, Coerce, Coerce),
     Tagged(GroundTyp, Box<Val<'a>>),
 }
 
+/// What to do with the value of the expression under evaluation
+enum Kont<'a> {
+    /// Evaluate the argument of an application
+    AppArg(Env<'a>, &'a Exp),
+    /// Apply the function to the value
+    App(Val<'a>),
+    Coerce(Coerce),
+    /// Evaluate the second operand
+    BinaryOpArg(BinOp, Env<'a>, &'a Exp),
+    BinaryOp(BinOp, Val<'a>),
+    UnaryOp(UnOp),
+    If(Env<'a>, &'a Exp, &'a Exp),
+    Let(Env<'a>, &'a Id, &'a Exp),
+    /// Store the value of the recursive binding at the address
+    Fix(Addr),
+    /// Store the value of the binding at the given index, then evaluate the
+    /// next binding or the body. The binding at index i is at address
+    /// base + i.
+    LetRec {
+        env: Env<'a>,
+        base: Addr,
+        index: usize,
+        bindings: &'a [(Id, Typ, Exp)],
+        body: &'a Exp,
+    },
+}
+
+enum State<'a> {
+    Eval(Env<'a>, &'a Exp),
+    Return(Val<'a>),
+}
+
 pub enum Answer {
     Lit(Lit),
-    Closure,
+    /// The function, without its environment or coercions
+    Closure(Exp),
+}
+
+impl Answer {
+    /// Whether two programs produced the same answer. We cannot compare
+    /// functions, so all functions agree.
+    pub fn agrees(&self, other: &Answer) -> bool {
+        match (self, other) {
+            (Answer::Lit(l1), Answer::Lit(l2)) => l1 == l2,
+            (Answer::Closure(_), Answer::Closure(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Prints the answer the way that Grift does, which does not show the
+    /// body of a function.
+    pub fn to_grift(&self) -> String {
+        match self {
+            Answer::Lit(Lit::Bool(true)) => "#t".to_string(),
+            Answer::Lit(Lit::Bool(false)) => "#f".to_string(),
+            Answer::Lit(Lit::Char('\n')) => "#\\newline".to_string(),
+            Answer::Lit(Lit::Char(' ')) => "#\\space".to_string(),
+            Answer::Lit(Lit::Char('\0')) => "#\\nul".to_string(),
+            Answer::Lit(Lit::Char(c)) => format!("#\\{}", c),
+            Answer::Lit(l) => l.to_string(),
+            Answer::Closure(_) => "#<procedure>".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Answer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Answer::Lit(l) => write!(f, "{}", l),
+            Answer::Closure(e) => write!(f, "{}", e),
+        }
+    }
 }
 
 #[derive(Display)]
 pub enum Error {
     #[display(fmt = "coercion failed: {}", _0)]
     Coercion(String),
+    /// A coercion from a labelled annotation failed
+    #[display(fmt = "blame {}: coercion failed: {}", _0, _1)]
+    Blame(Blame, String),
+    #[display(fmt = "unsupported expression {}", _0)]
+    Unsupported(String),
+    /// The program did not terminate within its fuel
+    #[display(fmt = "out of fuel after {} steps", _0)]
+    OutOfFuel(u64),
+    /// A recursive binding was used before its definition was evaluated
+    #[display(fmt = "{} used before its definition", _0)]
+    Uninitialized(Id),
+    /// A read primitive found the end of the input, or could not parse it
+    #[display(fmt = "input error: {}", _0)]
+    Input(String),
+    #[display(fmt = "division by zero")]
+    DivideByZero,
+}
+
+impl Error {
+    /// Whether the program failed because a coercion failed, as opposed to
+    /// some other error at runtime
+    pub fn is_coercion_failure(&self) -> bool {
+        matches!(self, Error::Coercion(_) | Error::Blame(..))
+    }
+
+    fn coercion(blame: &Option<Blame>, msg: String) -> Error {
+        match blame {
+            Some(blame) => Error::Blame(blame.clone(), msg),
+            None => Error::Coercion(msg),
+        }
+    }
 }
 
-struct Eval {
-    // Eventually, we will probably need an arena here for mutable references.
+struct Machine<'a> {
+    stack: Vec<Kont<'a>>,
+    store: Vec<Option<Val<'a>>>,
+    steps: u64,
+    fuel: u64,
+    /// The input that remains to be read
+    input: &'a str,
+    output: String,
 }
 
 impl<'a> Val<'a> {
     fn ground_typ(&self) -> GroundTyp {
         match self {
             Val::Closure(..) => GroundTyp::Fun,
-            Val::Lit(Lit::Bool(..)) => GroundTyp::Bool,
+            Val::Lit(Lit::Unit) => GroundTyp::Unit,
             Val::Lit(Lit::Int(..)) => GroundTyp::Int,
+            Val::Lit(Lit::Float(..)) => GroundTyp::Float,
+            Val::Lit(Lit::Bool(..)) => GroundTyp::Bool,
+            Val::Lit(Lit::Str(..)) => GroundTyp::Str,
+            Val::Lit(Lit::Char(..)) => GroundTyp::Char,
             // Coercion insertion should ensure this does not occur
             _ => panic!("unsupported type"),
         }
     }
 
+    /// Prints the value the way that Grift's `printf` does with `~a`
+    fn display(&self, out: &mut String) {
+        match self {
+            Val::Lit(Lit::Unit) => out.push_str("()"),
+            Val::Lit(Lit::Int(n)) => write!(out, "{}", n).unwrap(),
+            Val::Lit(Lit::Float(x)) => write!(out, "{}", x).unwrap(),
+            Val::Lit(Lit::Bool(b)) => out.push_str(if *b { "#t" } else { "#f" }),
+            Val::Lit(Lit::Str(s)) => out.push_str(s),
+            Val::Lit(Lit::Char(c)) => out.push(*c),
+            Val::Closure(..) => out.push_str("#<procedure>"),
+            Val::Tagged(_, v) => v.display(out),
+        }
+    }
+
     fn to_answer(self) -> Answer {
         match self {
             Val::Lit(l) => Answer::Lit(l),
             Val::Tagged(_, v) => v.to_answer(),
-            Val::Closure(..) => Answer::Closure,
+            Val::Closure(_, x, t, body, _, _) => {
+                Answer::Closure(Exp::Fun(x.clone(), t.clone(), Box::new(body.clone())))
+            }
         }
     }
 }
 
 type EvalResult<'a> = Result<Val<'a>, Error>;
 
-impl Eval {
-    fn eval_k<'a>(&'a self, k: &Coerce, v: Val<'a>) -> EvalResult<'a> {
-        match k {
-            Coerce::Doomed => Err(Error::Coercion("doomed".to_string())),
-            Coerce::Id => Ok(v),
-            Coerce::Seq(k1, k2) => self.eval_k(k2, self.eval_k(k1, v)?),
-            Coerce::Tag(g) => {
-                let g2 = v.ground_typ();
-                if &g2 == g {
-                    Ok(Val::Tagged(g2, Box::new(v)))
-                } else {
-                    Err(Error::Coercion(format!("tag({:?}) on {:?}", g, v)))
+fn unop_is_supported(op: &UnOp) -> bool {
+    matches!(
+        op,
+        UnOp::Not
+            | UnOp::Print
+            | UnOp::PrintInt
+            | UnOp::PrintBool
+            | UnOp::PrintChar
+            | UnOp::ReadInt
+            | UnOp::ReadBool
+            | UnOp::ReadFloat
+            | UnOp::ReadChar
+    )
+}
+
+fn binop_is_supported(op: &BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::IntEq
+            | BinOp::IntLt
+            | BinOp::IntGt
+            | BinOp::IntLe
+            | BinOp::IntGe
+            | BinOp::IntAdd
+            | BinOp::IntSub
+            | BinOp::IntMul
+            | BinOp::IntQuotient
+            | BinOp::IntRemainder
+            | BinOp::IntShiftLeft
+            | BinOp::IntShiftRight
+            | BinOp::BinaryAnd
+            | BinOp::BinaryOr
+            | BinOp::BinaryXor
+            | BinOp::PrintFloat
+            | BinOp::Printf
+    )
+}
+
+/// Applies an operator on two integers. Arithmetic wraps around on overflow.
+fn int_binop(op: &BinOp, m: i32, n: i32) -> Result<Lit, Error> {
+    let result = match op {
+        BinOp::IntEq => Lit::Bool(m == n),
+        BinOp::IntLt => Lit::Bool(m < n),
+        BinOp::IntGt => Lit::Bool(m > n),
+        BinOp::IntLe => Lit::Bool(m <= n),
+        BinOp::IntGe => Lit::Bool(m >= n),
+        BinOp::IntAdd => Lit::Int(m.wrapping_add(n)),
+        BinOp::IntSub => Lit::Int(m.wrapping_sub(n)),
+        BinOp::IntMul => Lit::Int(m.wrapping_mul(n)),
+        BinOp::IntQuotient | BinOp::IntRemainder if n == 0 => return Err(Error::DivideByZero),
+        BinOp::IntQuotient => Lit::Int(m.wrapping_div(n)),
+        BinOp::IntRemainder => Lit::Int(m.wrapping_rem(n)),
+        BinOp::IntShiftLeft => Lit::Int(m.wrapping_shl(n as u32)),
+        BinOp::IntShiftRight => Lit::Int(m.wrapping_shr(n as u32)),
+        BinOp::BinaryAnd => Lit::Int(m & n),
+        BinOp::BinaryOr => Lit::Int(m | n),
+        BinOp::BinaryXor => Lit::Int(m ^ n),
+        _ => unreachable!("{:?} is not an operator on integers", op),
+    };
+    Ok(result)
+}
+
+/// Supports the directives `~a`, `~s`, `~n`, and `~~`. The parser passes
+/// the single argument of `printf`, so the format may use at most one.
+fn printf(format: &str, arg: &Val, out: &mut String) -> Result<(), Error> {
+    let mut chars = format.chars();
+    let mut arg = Some(arg);
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') | Some('s') => match arg.take() {
+                Some(v) => v.display(out),
+                None => {
+                    return Err(Error::Unsupported(format!(
+                        "printf with more than one argument ({:?})",
+                        format
+                    )))
                 }
+            },
+            Some('n') => out.push('\n'),
+            Some('~') => out.push('~'),
+            d => {
+                return Err(Error::Unsupported(format!(
+                    "printf directive {:?} in {:?}",
+                    d, format
+                )))
             }
-            Coerce::Untag(g) => match v {
-                Val::Tagged(g2, v) => {
-                    if g == &g2 {
-                        Ok(*v)
-                    } else {
-                        Err(Error::Coercion(format!("untag({:?})", g)))
-                    }
+        }
+    }
+    Ok(())
+}
+
+fn eval_k<'a>(k: &Coerce, v: Val<'a>) -> EvalResult<'a> {
+    match k {
+        Coerce::Doomed(blame) => Err(Error::coercion(blame, "doomed".to_string())),
+        Coerce::Id => Ok(v),
+        Coerce::Seq(k1, k2) => eval_k(k2, eval_k(k1, v)?),
+        Coerce::Tag(g) => {
+            let g2 = v.ground_typ();
+            if &g2 == g {
+                Ok(Val::Tagged(g2, Box::new(v)))
+            } else {
+                Err(Error::Coercion(format!("tag({:?}) on {:?}", g, v)))
+            }
+        }
+        Coerce::Untag(g, blame) => match v {
+            Val::Tagged(g2, v) => {
+                if g == &g2 {
+                    Ok(*v)
+                } else {
+                    Err(Error::coercion(
+                        blame,
+                        format!("untag({:?}) on a value tagged {:?}", g, g2),
+                    ))
                 }
-                _ => Err(Error::Coercion(format!("untagged a not-tagged value"))),
-            },
-            Coerce::Wrap(dom, rng) => {
-                match v {
-                    Val::Closure(env, x, body, dom1, rng1) => {
-                        // TODO(arjun): Ordering matters
-                        Ok(Val::Closure(env, x, body, dom.seq(&dom1), rng1.seq(&rng)))
-                    }
-                    _ => Err(Error::Coercion(format!("wrap on a non-function"))),
+            }
+            _ => Err(Error::coercion(
+                blame,
+                format!("untagged a not-tagged value"),
+            )),
+        },
+        Coerce::Wrap(dom, rng, blame) => {
+            match v {
+                Val::Closure(env, x, t, body, dom1, rng1) => {
+                    // The new wrapper runs outside the old one: its domain
+                    // coercion runs first, and its range coercion runs last.
+                    Ok(Val::Closure(
+                        env,
+                        x,
+                        t,
+                        body,
+                        dom.seq(&dom1),
+                        rng1.seq(&rng),
+                    ))
                 }
+                _ => Err(Error::coercion(blame, format!("wrap on a non-function"))),
             }
         }
     }
+}
+
+impl<'a> Machine<'a> {
+    fn run(&mut self, exp: &'a Exp) -> EvalResult<'a> {
+        let mut state = State::Eval(Env::new(), exp);
+        loop {
+            state = match state {
+                State::Eval(env, exp) => {
+                    self.step()?;
+                    self.eval(env, exp)?
+                }
+                State::Return(v) => match self.stack.pop() {
+                    None => return Ok(v),
+                    Some(k) => self.apply_kont(k, v)?,
+                },
+            };
+        }
+    }
 
-    fn eval<'a>(&'a self, env: Env<'a>, exp: &'a Exp) -> EvalResult<'a> {
+    /// Every subexpression that we evaluate is one step.
+    fn step(&mut self) -> Result<(), Error> {
+        if self.steps == self.fuel {
+            return Err(Error::OutOfFuel(self.steps));
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Consecutive coercions are merged into one frame, so that a tail call
+    /// with a coercion on its result does not grow the stack.
+    fn push_coerce(&mut self, k: Coerce) {
+        match (k, self.stack.last_mut()) {
+            (Coerce::Id, _) => (),
+            (k, Some(Kont::Coerce(outer))) => *outer = k.seq(outer),
+            (k, _) => self.stack.push(Kont::Coerce(k)),
+        }
+    }
+
+    /// Reads the next whitespace-separated token from the input.
+    fn read_token(&mut self) -> Result<&'a str, Error> {
+        let rest = self.input.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if end == 0 {
+            return Err(Error::Input("end of input".to_string()));
+        }
+        self.input = &rest[end..];
+        Ok(&rest[..end])
+    }
+
+    fn read<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, Error> {
+        let token = self.read_token()?;
+        token
+            .parse()
+            .map_err(|_| Error::Input(format!("expected {}, got {:?}", what, token)))
+    }
+
+    fn read_bool(&mut self) -> Result<bool, Error> {
+        match self.read_token()? {
+            "#t" | "#true" => Ok(true),
+            "#f" | "#false" => Ok(false),
+            token => Err(Error::Input(format!("expected a boolean, got {:?}", token))),
+        }
+    }
+
+    /// Unlike the other read primitives, `read-char` does not skip
+    /// whitespace.
+    fn read_char(&mut self) -> Result<char, Error> {
+        let mut chars = self.input.chars();
+        let c = chars
+            .next()
+            .ok_or_else(|| Error::Input("end of input".to_string()))?;
+        self.input = chars.as_str();
+        Ok(c)
+    }
+
+    fn alloc(&mut self, n: usize) -> Addr {
+        let base = self.store.len();
+        self.store.resize(base + n, None);
+        base
+    }
+
+    fn eval(&mut self, env: Env<'a>, exp: &'a Exp) -> Result<State<'a>, Error> {
         match exp {
-            Exp::Lit(l) => Ok(Val::Lit(l.clone())),
+            Exp::Lit(l) => Ok(State::Return(Val::Lit(l.clone()))),
             Exp::Var(x) => {
                 // Coercion insertion should ensure this does not occur
-                Ok(env.get(x).cloned().expect("unbound identifier"))
+                match env.get(x).expect("unbound identifier") {
+                    Binding::Val(v) => Ok(State::Return(v.clone())),
+                    Binding::Rec(addr) => match &self.store[*addr] {
+                        Some(v) => Ok(State::Return(v.clone())),
+                        None => Err(Error::Uninitialized(x.clone())),
+                    },
+                }
             }
-            Exp::Fun(x, _, e) => Ok(Val::Closure(env.clone(), x, e, Coerce::Id, Coerce::Id)),
+            Exp::Fun(x, t, e) => Ok(State::Return(Val::Closure(
+                env,
+                x,
+                t,
+                e,
+                Coerce::Id,
+                Coerce::Id,
+            ))),
             Exp::App(e1, e2) => {
-                let v1 = self.eval(env.clone(), e1)?;
-                let v2 = self.eval(env.clone(), e2)?;
-                match v1 {
-                    Val::Closure(mut cl_env, x, body, dom, rng) => {
-                        let v2 = self.eval_k(&dom, v2)?;
-                        cl_env.insert(x, v2);
-                        let r = self.eval(cl_env, body)?;
-                        self.eval_k(&rng, r)
+                self.stack.push(Kont::AppArg(env.clone(), e2));
+                Ok(State::Eval(env, e1))
+            }
+            Exp::Coerce(t1, t2, e, label) => {
+                let k = super::insert_coercions::coerce(&t1, &t2, &Blame::positive(label));
+                self.push_coerce(k);
+                Ok(State::Eval(env, e))
+            }
+            Exp::PrimCoerce(k, e) => {
+                self.push_coerce(k.clone());
+                Ok(State::Eval(env, e))
+            }
+            Exp::BinaryOp(op, e1, e2) if binop_is_supported(op) => {
+                self.stack.push(Kont::BinaryOpArg(*op, env.clone(), e2));
+                Ok(State::Eval(env, e1))
+            }
+            Exp::UnaryOp(op, e) if unop_is_supported(op) => {
+                self.stack.push(Kont::UnaryOp(*op));
+                Ok(State::Eval(env, e))
+            }
+            Exp::If(e1, e2, e3) => {
+                self.stack.push(Kont::If(env.clone(), e2, e3));
+                Ok(State::Eval(env, e1))
+            }
+            Exp::Let(x, e1, e2) => {
+                self.stack.push(Kont::Let(env.clone(), x, e2));
+                Ok(State::Eval(env, e1))
+            }
+            Exp::Fix(x, _, e) => {
+                let addr = self.alloc(1);
+                self.stack.push(Kont::Fix(addr));
+                Ok(State::Eval(env.update(x, Binding::Rec(addr)), e))
+            }
+            Exp::LetRec(bindings, body) => {
+                let base = self.alloc(bindings.len());
+                let mut env = env;
+                for (i, (x, _, _)) in bindings.iter().enumerate() {
+                    env.insert(x, Binding::Rec(base + i));
+                }
+                match bindings.first() {
+                    None => Ok(State::Eval(env, body)),
+                    Some((_, _, e)) => {
+                        self.stack.push(Kont::LetRec {
+                            env: env.clone(),
+                            base,
+                            index: 0,
+                            bindings,
+                            body,
+                        });
+                        Ok(State::Eval(env, e))
                     }
-                    // Coercion insertion should ensure this does not occur
-                    _ => panic!("expected closure value in function position (got {:?})", v1),
                 }
             }
-            Exp::Coerce(t1, t2, e) => {
-                let k = super::insert_coercions::coerce(&t1, &t2);
-                let v = self.eval(env, e)?;
-                self.eval_k(&k, v)
-            }
-            Exp::PrimCoerce(k, e) => self.eval_k(k, self.eval(env, e)?),
-            Exp::BinaryOp(BinOp::IntAdd, e1, e2) => {
-                let v1 = self.eval(env.clone(), e1)?;
-                let v2 = self.eval(env.clone(), e2)?;
-                match (v1, v2) {
-                    (Val::Lit(Lit::Int(m)), Val::Lit(Lit::Int(n))) => Ok(Val::Lit(Lit::Int(m + n))),
-                    // Panic because coercion insertion produced an unsafe program!
-                    _ => panic!("+ received a non-int argument"),
+            _ => Err(Error::Unsupported(format!("{}", exp))),
+        }
+    }
+
+    fn apply_kont(&mut self, k: Kont<'a>, v: Val<'a>) -> Result<State<'a>, Error> {
+        match k {
+            Kont::AppArg(env, e2) => {
+                self.stack.push(Kont::App(v));
+                Ok(State::Eval(env, e2))
+            }
+            Kont::App(v1) => match v1 {
+                Val::Closure(cl_env, x, _, body, dom, rng) => {
+                    let v2 = eval_k(&dom, v)?;
+                    self.push_coerce(rng);
+                    Ok(State::Eval(cl_env.update(x, Binding::Val(v2)), body))
+                }
+                // Coercion insertion should ensure this does not occur
+                _ => panic!("expected closure value in function position (got {:?})", v1),
+            },
+            Kont::Coerce(k) => Ok(State::Return(eval_k(&k, v)?)),
+            Kont::BinaryOpArg(op, env, e2) => {
+                self.stack.push(Kont::BinaryOp(op, v));
+                Ok(State::Eval(env, e2))
+            }
+            Kont::BinaryOp(BinOp::PrintFloat, v1) => match (v1, v) {
+                (Val::Lit(Lit::Float(x)), Val::Lit(Lit::Int(digits))) => {
+                    write!(self.output, "{:.*}", digits.max(0) as usize, x).unwrap();
+                    Ok(State::Return(Val::Lit(Lit::Unit)))
+                }
+                _ => panic!("print-float received a bad argument"),
+            },
+            Kont::BinaryOp(BinOp::Printf, v1) => match v1 {
+                Val::Lit(Lit::Str(format)) => {
+                    printf(&format, &v, &mut self.output)?;
+                    Ok(State::Return(Val::Lit(Lit::Unit)))
                 }
+                _ => panic!("printf received a non-string format"),
+            },
+            Kont::BinaryOp(op, v1) => match (v1, v) {
+                (Val::Lit(Lit::Int(m)), Val::Lit(Lit::Int(n))) => {
+                    Ok(State::Return(Val::Lit(int_binop(&op, m, n)?)))
+                }
+                // Panic because coercion insertion produced an unsafe program!
+                _ => panic!("{:?} received a non-int argument", op),
+            },
+            Kont::UnaryOp(UnOp::Not) => match v {
+                Val::Lit(Lit::Bool(b)) => Ok(State::Return(Val::Lit(Lit::Bool(!b)))),
+                _ => panic!("not received a non-bool argument"),
+            },
+            Kont::UnaryOp(UnOp::Print) => match v {
+                Val::Lit(Lit::Str(s)) => {
+                    self.output.push_str(&s);
+                    Ok(State::Return(Val::Lit(Lit::Unit)))
+                }
+                _ => panic!("print received a non-string argument"),
+            },
+            Kont::UnaryOp(UnOp::PrintInt)
+            | Kont::UnaryOp(UnOp::PrintBool)
+            | Kont::UnaryOp(UnOp::PrintChar) => {
+                v.display(&mut self.output);
+                Ok(State::Return(Val::Lit(Lit::Unit)))
+            }
+            Kont::UnaryOp(UnOp::ReadInt) => {
+                Ok(State::Return(Val::Lit(Lit::Int(self.read("an integer")?))))
+            }
+            Kont::UnaryOp(UnOp::ReadFloat) => {
+                Ok(State::Return(Val::Lit(Lit::Float(self.read("a float")?))))
+            }
+            Kont::UnaryOp(UnOp::ReadBool) => {
+                Ok(State::Return(Val::Lit(Lit::Bool(self.read_bool()?))))
+            }
+            Kont::UnaryOp(UnOp::ReadChar) => {
+                Ok(State::Return(Val::Lit(Lit::Char(self.read_char()?))))
             }
-            Exp::If(e1, e2, e3) => match self.eval(env.clone(), e1)? {
-                Val::Lit(Lit::Bool(true)) => self.eval(env, e2),
-                Val::Lit(Lit::Bool(false)) => self.eval(env, e3),
+            Kont::UnaryOp(op) => unreachable!("{:?} is not supported", op),
+            Kont::If(env, e2, e3) => match v {
+                Val::Lit(Lit::Bool(true)) => Ok(State::Eval(env, e2)),
+                Val::Lit(Lit::Bool(false)) => Ok(State::Eval(env, e3)),
                 _ => panic!("condition is not a boolean"),
             },
-            _ => unimplemented!(),
+            Kont::Let(env, x, e2) => Ok(State::Eval(env.update(x, Binding::Val(v)), e2)),
+            Kont::Fix(addr) => {
+                self.store[addr] = Some(v.clone());
+                Ok(State::Return(v))
+            }
+            Kont::LetRec {
+                env,
+                base,
+                index,
+                bindings,
+                body,
+            } => {
+                self.store[base + index] = Some(v);
+                match bindings.get(index + 1) {
+                    None => Ok(State::Eval(env, body)),
+                    Some((_, _, e)) => {
+                        self.stack.push(Kont::LetRec {
+                            env: env.clone(),
+                            base,
+                            index: index + 1,
+                            bindings,
+                            body,
+                        });
+                        Ok(State::Eval(env, e))
+                    }
+                }
+            }
         }
     }
 }
 
 /// Assumes that the expression has coercions inserted.
+#[cfg(test)]
 pub fn eval(exp: Exp) -> Result<Answer, Error> {
-    let eval = Eval {};
-    let v = eval.eval(Env::new(), &exp)?;
-    return Ok(v.to_answer());
+    eval_fuel(exp, u64::MAX).0
+}
+
+/// Evaluates for at most `fuel` steps, and also returns the number of steps
+/// taken.
+pub fn eval_fuel(exp: Exp, fuel: u64) -> (Result<Answer, Error>, u64) {
+    let (result, steps, _) = eval_io(exp, fuel, "");
+    (result, steps)
+}
+
+/// Like `eval_fuel`, but the program reads from `input`. Also returns what
+/// the program printed, even if it failed.
+pub fn eval_io(exp: Exp, fuel: u64, input: &str) -> (Result<Answer, Error>, u64, String) {
+    let mut machine = Machine {
+        stack: Vec::new(),
+        store: Vec::new(),
+        steps: 0,
+        fuel,
+        input,
+        output: String::new(),
+    };
+    let result = machine.run(&exp).map(Val::to_answer);
+    (result, machine.steps, machine.output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{eval, eval_fuel, eval_io, Answer, Error, Machine};
+    use crate::insert_coercions::{coerce, insert_coercions};
+    use crate::session::Session;
+    use crate::syntax::*;
+
+    fn blame(mut exp: Exp) -> Blame {
+        insert_coercions(&mut exp).unwrap();
+        match eval(exp) {
+            Err(Error::Blame(blame, _)) => blame,
+            Err(err) => panic!("expected blame, got {}", err),
+            Ok(_) => panic!("expected blame"),
+        }
+    }
+
+    #[test]
+    fn positive_blame() {
+        // (: #t Int "Pass")
+        let exp = Exp::Ann(
+            Box::new(Exp::Lit(Lit::Bool(true))),
+            Typ::Int,
+            Some("Pass".to_string()),
+        );
+        assert_eq!(
+            blame(exp),
+            Blame {
+                label: "Pass".to_string(),
+                positive: true
+            }
+        );
+    }
+
+    #[test]
+    fn negative_blame() {
+        // ((: (lambda ((x : Int)) x) (Dyn -> Int) "Wrong") #t)
+        let f = Exp::Fun(
+            "x".to_string(),
+            Typ::Int,
+            Box::new(Exp::Var("x".to_string())),
+        );
+        let exp = Exp::App(
+            Box::new(Exp::Ann(
+                Box::new(f),
+                Typ::Arr(Box::new(Typ::Any), Box::new(Typ::Int)),
+                Some("Wrong".to_string()),
+            )),
+            Box::new(Exp::Lit(Lit::Bool(true))),
+        );
+        assert_eq!(
+            blame(exp),
+            Blame {
+                label: "Wrong".to_string(),
+                positive: false
+            }
+        );
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        // (fun x . x x) (fun x . x x)
+        let omega = Exp::Fun(
+            "x".to_string(),
+            Typ::Any,
+            Box::new(Exp::App(
+                Box::new(Exp::Var("x".to_string())),
+                Box::new(Exp::Var("x".to_string())),
+            )),
+        );
+        let mut exp = Exp::App(Box::new(omega.clone()), Box::new(omega));
+        insert_coercions(&mut exp).unwrap();
+        match eval_fuel(exp, 1000) {
+            (Err(Error::OutOfFuel(1000)), 1000) => (),
+            (Err(err), _) => panic!("expected to run out of fuel, got {}", err),
+            (Ok(_), _) => panic!("expected to run out of fuel"),
+        }
+    }
+
+    #[test]
+    fn counts_steps() {
+        // (1 + 2 : any)
+        let mut exp = Exp::Ann(
+            Box::new(Exp::BinaryOp(
+                BinOp::IntAdd,
+                Box::new(Exp::Lit(Lit::Int(1))),
+                Box::new(Exp::Lit(Lit::Int(2))),
+            )),
+            Typ::Any,
+            None,
+        );
+        insert_coercions(&mut exp).unwrap();
+        let (result, steps) = eval_fuel(exp, 1000);
+        assert!(result.is_ok());
+        // The coercion to any, the addition, and its two arguments
+        assert_eq!(steps, 4);
+    }
+
+    fn int_to_int() -> Typ {
+        Typ::Arr(Box::new(Typ::Int), Box::new(Typ::Int))
+    }
+
+    #[test]
+    fn deep_recursion_does_not_overflow() {
+        // (fix f . fun x : int . 1 + f x) 0
+        let f = Exp::Fix(
+            "f".to_string(),
+            int_to_int(),
+            Box::new(Exp::Fun(
+                "x".to_string(),
+                Typ::Int,
+                Box::new(Exp::BinaryOp(
+                    BinOp::IntAdd,
+                    Box::new(Exp::Lit(Lit::Int(1))),
+                    Box::new(Exp::App(
+                        Box::new(Exp::Var("f".to_string())),
+                        Box::new(Exp::Var("x".to_string())),
+                    )),
+                )),
+            )),
+        );
+        let mut exp = Exp::App(Box::new(f), Box::new(Exp::Lit(Lit::Int(0))));
+        insert_coercions(&mut exp).unwrap();
+        match eval_fuel(exp, 1_000_000).0 {
+            Err(Error::OutOfFuel(_)) => (),
+            Err(err) => panic!("expected to run out of fuel, got {}", err),
+            Ok(_) => panic!("expected to run out of fuel"),
+        }
+    }
+
+    fn run_io(program: &str, input: &str) -> (Result<Answer, Error>, String) {
+        let mut exp = crate::grift::parse(&Session::default(), program);
+        exp.dynamic_metavars();
+        insert_coercions(&mut exp).unwrap();
+        let (result, _, output) = eval_io(exp, 1000, input);
+        (result, output)
+    }
+
+    #[test]
+    fn scripted_io() {
+        let (result, output) = run_io(
+            "(begin (print-char (read-char)) (print-int (+ (read-int) (read-int)))
+                    (display-char #\\newline) (print-bool (read-bool)))",
+            "!  40\n2 #t",
+        );
+        assert!(result.is_ok());
+        assert_eq!(output, "!42\n#t");
+        let (result, output) = run_io("(begin (print-int 1) (print-int (read-int)))", "x");
+        assert!(matches!(result, Err(Error::Input(_))));
+        assert_eq!(output, "1");
+    }
+
+    #[test]
+    fn integer_operators() {
+        let run = |program| {
+            run_io(program, "")
+                .0
+                .unwrap_or_else(|err| panic!("{}", err))
+        };
+        let cases = [
+            ("(- 7 10)", "-3"),
+            ("(* 6 7)", "42"),
+            ("(%/ 7 2)", "3"),
+            ("(quotient 9 3)", "3"),
+            ("(%% 7 2)", "1"),
+            ("(%<< 1 4)", "16"),
+            ("(%>> 16 2)", "4"),
+            ("(binary-and 12 10)", "8"),
+            ("(binary-or 12 10)", "14"),
+            ("(binary-xor 12 10)", "6"),
+            ("(= 3 3)", "#t"),
+            ("(< 1 2)", "#t"),
+            ("(> 1 2)", "#f"),
+            ("(<= 2 2)", "#t"),
+            ("(>= 1 2)", "#f"),
+        ];
+        for (program, answer) in cases.iter() {
+            assert_eq!(run(program).to_grift(), *answer, "{}", program);
+        }
+        assert!(matches!(run_io("(%/ 1 0)", "").0, Err(Error::DivideByZero)));
+    }
+
+    fn run_file(path: &str) -> Answer {
+        let program = std::fs::read_to_string(path).unwrap();
+        let mut exp = crate::grift::parse(&Session::default(), &program);
+        exp.dynamic_metavars();
+        insert_coercions(&mut exp).unwrap();
+        eval(exp).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    #[test]
+    fn large_grift_benchmark() {
+        // Ackermann's function, which compares, adds, and subtracts
+        let answer = run_file("grift-suite/large/ack-3-5-static.grift");
+        assert_eq!(answer.to_grift(), "253");
+    }
+
+    #[test]
+    #[ignore]
+    fn largest_grift_benchmark() {
+        // Takes minutes without optimizations
+        let answer = run_file("grift-suite/large/ack-3-10-static.grift");
+        assert_eq!(answer.to_grift(), "4093");
+    }
+
+    #[test]
+    fn tag_then_untag_cancels() {
+        let b = Blame::positive(&Some("L".to_string()));
+        let int_to_any = Coerce::Tag(GroundTyp::Int);
+        let any_to_int = Coerce::Untag(GroundTyp::Int, b.clone());
+        let any_to_bool = Coerce::Untag(GroundTyp::Bool, b.clone());
+        assert_eq!(int_to_any.seq(&any_to_int), Coerce::Id);
+        assert_eq!(int_to_any.seq(&any_to_bool), Coerce::Doomed(b));
+    }
+
+    #[test]
+    fn repeated_wrapping_takes_constant_space() {
+        // (int -> int) => any => (int -> int), over and over again
+        let arr = Typ::Arr(Box::new(Typ::Int), Box::new(Typ::Int));
+        let there = coerce(&arr, &Typ::Any, &None);
+        let back = coerce(&Typ::Any, &arr, &None);
+        let round_trip = there.seq(&back);
+        let mut k = Coerce::Id;
+        for _ in 0..1000 {
+            k = k.seq(&there).seq(&back);
+        }
+        assert_eq!(k, round_trip);
+        assert!(k.size() <= 5);
+    }
+
+    #[test]
+    fn tail_calls_do_not_grow_the_stack() {
+        // let rec f = fun x : any . f x in f 0
+        let exp = Exp::LetRec(
+            vec![(
+                "f".to_string(),
+                Typ::Arr(Box::new(Typ::Any), Box::new(Typ::Int)),
+                Exp::Fun(
+                    "x".to_string(),
+                    Typ::Any,
+                    Box::new(Exp::App(
+                        Box::new(Exp::Var("f".to_string())),
+                        Box::new(Exp::Var("x".to_string())),
+                    )),
+                ),
+            )],
+            Box::new(Exp::App(
+                Box::new(Exp::Var("f".to_string())),
+                Box::new(Exp::Lit(Lit::Int(0))),
+            )),
+        );
+        let mut exp = exp;
+        insert_coercions(&mut exp).unwrap();
+        let mut machine = Machine {
+            stack: Vec::new(),
+            store: Vec::new(),
+            steps: 0,
+            fuel: 100_000,
+            input: "",
+            output: String::new(),
+        };
+        assert!(machine.run(&exp).is_err());
+        assert!(machine.stack.len() <= 2);
+    }
 }
@@ -0,0 +1,282 @@
+//! Property-based testing of migration with random GTLC programs.
+//!
+//! The generator produces closed programs from a small fragment of the GTLC
+//! that both TypeWhich and ins-and-outs support. For every program, we check
+//! that the result of migration type-checks, and that neither TypeWhich nor
+//! ins-and-outs introduces a runtime error. We shrink programs that violate
+//! a property before reporting them.
+use super::cgen::typeinf_options;
+use super::ins_and_outs::typeinf_portable;
+use super::session::Session;
+use super::shrink;
+use super::syntax::{BinOp, Exp, Id, Lit, Typ};
+use super::test_suite::guard;
+use super::type_check::tcheck;
+use super::validate::runs_ok;
+use super::{Annot, Options};
+use derive_more::Display;
+use im_rc::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+type Env = HashMap<String, Typ>;
+
+/// Programs that take more steps than this are assumed to diverge
+const FUEL: u64 = 100_000;
+
+/// The features that generated programs may use, besides functions,
+/// applications, integers, and addition
+#[derive(Clone, Copy)]
+pub struct Features {
+    pub bools: bool,
+    pub ifs: bool,
+    pub lets: bool,
+    /// Annotate function arguments with random types, instead of any
+    pub annotations: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            bools: true,
+            ifs: true,
+            lets: true,
+            annotations: true,
+        }
+    }
+}
+
+pub struct Generator {
+    rng: StdRng,
+    features: Features,
+    next_var: usize,
+}
+
+enum Form {
+    Fun,
+    App,
+    Add,
+    If,
+    Let,
+}
+
+impl Generator {
+    pub fn new(seed: u64, features: Features) -> Self {
+        Generator {
+            rng: StdRng::seed_from_u64(seed),
+            features,
+            next_var: 0,
+        }
+    }
+
+    /// A closed program with about `size` nodes
+    pub fn program(&mut self, size: usize) -> Exp {
+        self.exp(size, &mut Vec::new())
+    }
+
+    fn fresh(&mut self) -> Id {
+        self.next_var += 1;
+        format!("x{}", self.next_var)
+    }
+
+    fn lit(&mut self) -> Exp {
+        if self.features.bools && self.rng.gen_bool(0.3) {
+            Exp::Lit(Lit::Bool(self.rng.gen()))
+        } else {
+            Exp::Lit(Lit::Int(self.rng.gen_range(0..10)))
+        }
+    }
+
+    fn typ(&mut self, depth: usize) -> Typ {
+        if !self.features.annotations {
+            return Typ::Any;
+        }
+        match self.rng.gen_range(0..5) {
+            0 if depth > 0 => {
+                Typ::Arr(Box::new(self.typ(depth - 1)), Box::new(self.typ(depth - 1)))
+            }
+            1 => Typ::Int,
+            2 if self.features.bools => Typ::Bool,
+            _ => Typ::Any,
+        }
+    }
+
+    /// Splits `size` into two sizes
+    fn split(&mut self, size: usize) -> (usize, usize) {
+        let n = self.rng.gen_range(0..=size);
+        (n, size - n)
+    }
+
+    fn exp(&mut self, size: usize, scope: &mut Vec<Id>) -> Exp {
+        if size <= 1 {
+            return if !scope.is_empty() && self.rng.gen_bool(0.6) {
+                Exp::Var(scope[self.rng.gen_range(0..scope.len())].clone())
+            } else {
+                self.lit()
+            };
+        }
+        let mut forms = vec![Form::Fun, Form::App, Form::App, Form::Add];
+        if self.features.ifs {
+            forms.push(Form::If);
+        }
+        if self.features.lets {
+            forms.push(Form::Let);
+        }
+        let size = size - 1;
+        match forms[self.rng.gen_range(0..forms.len())] {
+            Form::Fun => {
+                let x = self.fresh();
+                let t = self.typ(1);
+                scope.push(x.clone());
+                let body = self.exp(size, scope);
+                scope.pop();
+                Exp::Fun(x, t, Box::new(body))
+            }
+            Form::App => {
+                let (n, m) = self.split(size);
+                Exp::App(Box::new(self.exp(n, scope)), Box::new(self.exp(m, scope)))
+            }
+            Form::Add => {
+                let (n, m) = self.split(size);
+                Exp::BinaryOp(
+                    BinOp::IntAdd,
+                    Box::new(self.exp(n, scope)),
+                    Box::new(self.exp(m, scope)),
+                )
+            }
+            Form::If => {
+                let (n, rest) = self.split(size);
+                let (m, k) = self.split(rest);
+                Exp::If(
+                    Box::new(self.exp(n, scope)),
+                    Box::new(self.exp(m, scope)),
+                    Box::new(self.exp(k, scope)),
+                )
+            }
+            Form::Let => {
+                let x = self.fresh();
+                let (n, m) = self.split(size);
+                let e1 = self.exp(n, scope);
+                scope.push(x.clone());
+                let e2 = self.exp(m, scope);
+                scope.pop();
+                Exp::Let(x, Box::new(e1), Box::new(e2))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Display)]
+pub enum Property {
+    /// The result of migration type-checks
+    #[display(fmt = "migration type-checks")]
+    TypeChecks,
+    /// When the original program runs without error, so does the result of
+    /// migration
+    #[display(fmt = "migration is safe")]
+    Safe,
+    /// Like `Safe`, but for ins-and-outs
+    #[display(fmt = "ins-and-outs is safe")]
+    InsAndOutsSafe,
+}
+
+pub const PROPERTIES: [Property; 3] = [
+    Property::TypeChecks,
+    Property::Safe,
+    Property::InsAndOutsSafe,
+];
+
+/// Migrates with TypeWhich, using the options of the session
+pub fn migrate(session: &Session, env: &Env, original: &Exp) -> Result<Exp, String> {
+    let mut exp = original.clone();
+    if session.options.annot == Annot::Ignore {
+        exp.fresh_types(session);
+    }
+    typeinf_options(session, exp, env).map_err(|err| format!("migration failed: {}", err))
+}
+
+/// Migrates with ins-and-outs, which ignores annotations
+pub fn migrate_ins_and_outs(original: &Exp) -> Exp {
+    let session = Session::default();
+    let mut exp = original.clone();
+    exp.fresh_types(&session);
+    typeinf_portable(&session, exp)
+}
+
+/// Fails when `migrated` fails, but `original` does not
+fn check_safe(original: &Exp, migrated: Exp) -> Result<(), String> {
+    if runs_ok(original.clone(), FUEL) == Some(true) && runs_ok(migrated, FUEL) == Some(false) {
+        Err("the result of migration fails, but the original program does not".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that the property holds for a closed program. Panics are
+/// violations.
+pub fn check(property: Property, original: &Exp) -> Result<(), String> {
+    let session = Session::new(Options {
+        annot: Annot::Ignore,
+        ..Options::default()
+    });
+    let env = Env::new();
+    guard(|| match property {
+        Property::TypeChecks => tcheck(&env, &migrate(&session, &env, original)?).map(|_| ()),
+        Property::Safe => check_safe(original, migrate(&session, &env, original)?),
+        Property::InsAndOutsSafe => check_safe(original, migrate_ins_and_outs(original)),
+    })
+}
+
+/// Checks every property on `count` random programs of about `size` nodes.
+/// Prints every violation, shrunk, and exits with an error if there are any.
+pub fn fuzz_main(seed: u64, count: usize, size: usize, features: Features) {
+    // Panics are reported as violations, so the default message is noise.
+    std::panic::set_hook(Box::new(|_| ()));
+    let mut generator = Generator::new(seed, features);
+    let mut violations = 0;
+    for i in 0..count {
+        let program = generator.program(size);
+        for property in PROPERTIES.iter() {
+            if let Err(msg) = check(*property, &program) {
+                violations += 1;
+                let shrunk = shrink::minimize(program.clone(), |e| check(*property, e).is_err());
+                println!("Program {} violates \"{}\": {}", i, property, msg);
+                println!("{}", program);
+                println!("Shrunk to:");
+                println!("{}", shrunk);
+                println!();
+            }
+        }
+    }
+    let _ = std::panic::take_hook();
+    println!(
+        "Checked {} programs, found {} violations",
+        count, violations
+    );
+    if violations > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Features, Generator};
+    use crate::shrink::is_closed;
+
+    #[test]
+    fn generates_closed_programs() {
+        let mut generator = Generator::new(0, Features::default());
+        for _ in 0..100 {
+            assert!(is_closed(&generator.program(20)));
+        }
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let mut g1 = Generator::new(42, Features::default());
+        let mut g2 = Generator::new(42, Features::default());
+        for _ in 0..10 {
+            assert_eq!(g1.program(20), g2.program(20));
+        }
+    }
+}
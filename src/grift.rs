@@ -1,3 +1,7 @@
+use crate::impl_Display_Pretty;
+use crate::parser::with_session;
+use crate::pretty::Pretty;
+use crate::session::Session;
 use crate::syntax::*;
 use im_rc::HashMap;
 
@@ -41,13 +45,13 @@ pub fn toplevel_exp(tls: Vec<Toplevel>) -> Exp {
     }
 }
 
-pub fn parse_toplevel(input: impl AsRef<str>) -> Vec<Toplevel> {
+pub fn parse_toplevel(session: &Session, input: impl AsRef<str>) -> Vec<Toplevel> {
     let input = input.as_ref();
     let lexerdef = grift_l::lexerdef();
     let lexer = lexerdef.lexer(input);
-    let (res, errs) = grift_y::parse(&lexer);
+    let (res, errs) = with_session(session, || grift_y::parse(&lexer));
     if errs.is_empty() {
-        crate::parser::show_warnings();
+        session.show_warnings();
         return res.unwrap();
     }
     for err in errs.into_iter() {
@@ -56,8 +60,306 @@ pub fn parse_toplevel(input: impl AsRef<str>) -> Vec<Toplevel> {
     panic!("Error parsing expressions");
 }
 
-pub fn parse(input: impl AsRef<str>) -> Exp {
-    toplevel_exp(parse_toplevel(input))
+pub fn parse(session: &Session, input: impl AsRef<str>) -> Exp {
+    toplevel_exp(parse_toplevel(session, input))
+}
+
+/// An S-expression, which is what we print Grift programs as
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn atom(s: impl Into<String>) -> Sexp {
+        Sexp::Atom(s.into())
+    }
+}
+
+impl Pretty for Sexp {
+    fn pretty<'b, D, A>(&'b self, pp: &'b D) -> pretty::DocBuilder<'b, D, A>
+    where
+        D: pretty::DocAllocator<'b, A>,
+        A: std::clone::Clone,
+        <D as pretty::DocAllocator<'b, A>>::Doc: std::clone::Clone,
+    {
+        match self {
+            Sexp::Atom(s) => pp.text(s),
+            Sexp::List(items) => pp
+                .concat(vec![
+                    pp.text("("),
+                    pp.intersperse(items.iter().map(|item| item.pretty(pp)), pp.line())
+                        .nest(1),
+                    pp.text(")"),
+                ])
+                .group(),
+        }
+    }
+}
+
+impl_Display_Pretty!(Sexp);
+
+/// Prints a program in Grift, so that `parse` produces the same program. Coercions to `Dyn`
+/// become ascriptions, and other coercions are left out, since Grift inserts them. The forms
+/// that Grift does not have, such as lists and type tests, are errors.
+pub fn print(e: &Exp) -> Result<String, String> {
+    Ok(format!("{}\n", exp_sexp(e)?))
+}
+
+fn typ_sexp(t: &Typ) -> Result<Sexp, String> {
+    Ok(match t {
+        Typ::Unit => Sexp::atom("()"),
+        Typ::Int => Sexp::atom("Int"),
+        Typ::Float => Sexp::atom("Float"),
+        Typ::Bool => Sexp::atom("Bool"),
+        Typ::Char => Sexp::atom("Char"),
+        // Grift reads unknown types as Dyn, and so does the precision module.
+        Typ::Any | Typ::Metavar(_) => Sexp::atom("Dyn"),
+        Typ::Arr(t1, t2) => Sexp::List(vec![typ_sexp(t1)?, Sexp::atom("->"), typ_sexp(t2)?]),
+        Typ::Box(t) => Sexp::List(vec![Sexp::atom("Ref"), typ_sexp(t)?]),
+        Typ::Vect(t) => Sexp::List(vec![Sexp::atom("Vect"), typ_sexp(t)?]),
+        Typ::List(t) => Sexp::List(vec![Sexp::atom("List"), typ_sexp(t)?]),
+        Typ::Pair(..) => {
+            // Tuples are pairs that end with unit.
+            let mut items = vec![Sexp::atom("Tuple")];
+            let mut t = t;
+            while let Typ::Pair(t1, t2) = t {
+                items.push(typ_sexp(t1)?);
+                t = t2;
+            }
+            if t != &Typ::Unit {
+                return Err(format!("Grift has no pair type {}", t));
+            }
+            Sexp::List(items)
+        }
+        Typ::Str => return Err("Grift has no string type".to_string()),
+    })
+}
+
+fn lit_sexp(l: &Lit) -> Result<Sexp, String> {
+    Ok(match l {
+        Lit::Int(n) => Sexp::atom(n.to_string()),
+        Lit::Float(f) if f.is_finite() => Sexp::atom(format!("#i{:?}", f)),
+        Lit::Float(f) => return Err(format!("Grift has no float {}", f)),
+        Lit::Bool(true) => Sexp::atom("#t"),
+        Lit::Bool(false) => Sexp::atom("#f"),
+        Lit::Str(s) if !s.contains('"') => Sexp::atom(format!("\"{}\"", s)),
+        Lit::Str(s) => return Err(format!("Grift cannot quote the string {}", s)),
+        Lit::Char('\0') => Sexp::atom("#\\nul"),
+        Lit::Char('\n') => Sexp::atom("#\\newline"),
+        Lit::Char(' ') => Sexp::atom("#\\space"),
+        Lit::Char(c) => Sexp::atom(format!("#\\{}", c)),
+        Lit::Unit => Sexp::atom("()"),
+    })
+}
+
+/// The name of a unary operator, and whether its argument is unit, which Grift leaves out
+fn unop_name(op: UnOp) -> (&'static str, bool) {
+    match op {
+        UnOp::Not => ("not", false),
+        UnOp::BinaryNot => ("binary-not", false),
+        UnOp::FloatAbs => ("flabs", false),
+        UnOp::TimerStart => ("timer-start", true),
+        UnOp::Print => ("print", false),
+        UnOp::Exit => ("exit", true),
+        UnOp::ReadInt => ("read-int", true),
+        UnOp::PrintInt => ("print-int", false),
+        UnOp::ReadBool => ("read-bool", true),
+        UnOp::PrintBool => ("print-bool", false),
+        UnOp::ReadFloat => ("read-float", true),
+        UnOp::ReadChar => ("read-char", true),
+        UnOp::PrintChar => ("print-char", false),
+        UnOp::FloatToInt => ("float->int", false),
+        UnOp::IntToFloat => ("int->float", false),
+        UnOp::CharToInt => ("char->int", false),
+        UnOp::IntToChar => ("int->char", false),
+        UnOp::And => ("and", false),
+    }
+}
+
+fn binop_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::IntEq => "=",
+        BinOp::IntLt => "<",
+        BinOp::IntGt => ">",
+        BinOp::IntLe => "<=",
+        BinOp::IntGe => ">=",
+        BinOp::IntAdd => "+",
+        BinOp::IntSub => "-",
+        BinOp::IntMul => "*",
+        BinOp::IntQuotient => "%/",
+        BinOp::IntRemainder => "%%",
+        BinOp::IntShiftLeft => "%<<",
+        BinOp::IntShiftRight => "%>>",
+        BinOp::BinaryAnd => "binary-and",
+        BinOp::BinaryOr => "binary-or",
+        BinOp::BinaryXor => "binary-xor",
+        BinOp::FloatAdd => "fl+",
+        BinOp::FloatEq => "fl=",
+        BinOp::And => "and",
+        BinOp::Printf => "printf",
+        BinOp::PrintFloat => "print-float",
+    }
+}
+
+fn skip_coercions(e: &Exp) -> &Exp {
+    match e {
+        Exp::Coerce(_, t, e, _) if t != &Typ::Any => skip_coercions(e),
+        _ => e,
+    }
+}
+
+/// A binding of `let` or `letrec`, which leaves out the type when it is a metavariable
+fn binding_sexp(x: &str, t: Option<&Typ>, e: &Exp) -> Result<Sexp, String> {
+    let mut binding = vec![Sexp::atom(x)];
+    if let Some(t) = t.filter(|t| !t.is_metavar()) {
+        binding.push(Sexp::atom(":"));
+        binding.push(typ_sexp(t)?);
+    }
+    binding.push(exp_sexp(e)?);
+    Ok(Sexp::List(binding))
+}
+
+fn exp_sexp(e: &Exp) -> Result<Sexp, String> {
+    let list = |head: &str, es: &[&Exp]| -> Result<Sexp, String> {
+        let mut items = vec![Sexp::atom(head)];
+        for e in es {
+            items.push(exp_sexp(e)?);
+        }
+        Ok(Sexp::List(items))
+    };
+    Ok(match e {
+        Exp::Lit(l) => lit_sexp(l)?,
+        Exp::Var(x) => Sexp::atom(x),
+        Exp::Fun(..) => {
+            let mut formals = Vec::new();
+            let mut body = e;
+            while let Exp::Fun(x, t, e) = body {
+                formals.push(if t.is_metavar() {
+                    Sexp::atom(x)
+                } else {
+                    Sexp::List(vec![Sexp::atom(x), Sexp::atom(":"), typ_sexp(t)?])
+                });
+                body = skip_coercions(e);
+            }
+            Sexp::List(vec![
+                Sexp::atom("lambda"),
+                Sexp::List(formals),
+                exp_sexp(body)?,
+            ])
+        }
+        Exp::App(..) => {
+            let mut args = Vec::new();
+            let mut f = e;
+            while let Exp::App(e1, e2) = f {
+                args.push(exp_sexp(e2)?);
+                f = skip_coercions(e1);
+            }
+            args.push(exp_sexp(f)?);
+            args.reverse();
+            Sexp::List(args)
+        }
+        Exp::UnaryOp(op, e) => match (unop_name(*op), &**e) {
+            ((name, true), Exp::Lit(Lit::Unit)) => Sexp::List(vec![Sexp::atom(name)]),
+            ((name, _), e) => list(name, &[e])?,
+        },
+        Exp::BinaryOp(op, e1, e2) => list(binop_name(*op), &[e1, e2])?,
+        // Exp::begin names the expressions that it sequences __begin1, __begin2, ...
+        Exp::Let(x, _, _) if x == "__begin1" => {
+            let mut exps = vec![Sexp::atom("begin")];
+            let mut rest = e;
+            while let Exp::Let(x, e1, e2) = rest {
+                if *x != format!("__begin{}", exps.len()) {
+                    break;
+                }
+                exps.push(exp_sexp(e1)?);
+                rest = e2;
+            }
+            exps.push(exp_sexp(rest)?);
+            Sexp::List(exps)
+        }
+        Exp::Let(x, e1, e2) => {
+            let binding = match &**e1 {
+                Exp::Ann(e1, t, None) => binding_sexp(x, Some(t), e1)?,
+                _ => binding_sexp(x, None, e1)?,
+            };
+            Sexp::List(vec![
+                Sexp::atom("let"),
+                Sexp::List(vec![binding]),
+                exp_sexp(e2)?,
+            ])
+        }
+        Exp::LetRec(bindings, e) => Sexp::List(vec![
+            Sexp::atom("letrec"),
+            Sexp::List(
+                bindings
+                    .iter()
+                    .map(|(x, t, e)| binding_sexp(x, Some(t), e))
+                    .collect::<Result<_, _>>()?,
+            ),
+            exp_sexp(e)?,
+        ]),
+        Exp::Ann(e, t, label) => {
+            let mut items = vec![Sexp::atom(":"), exp_sexp(e)?, typ_sexp(t)?];
+            if let Some(label) = label {
+                items.push(lit_sexp(&Lit::Str(label.clone()))?);
+            }
+            Sexp::List(items)
+        }
+        Exp::If(e1, e2, e3) => list("if", &[e1, e2, e3])?,
+        Exp::Pair(..) => {
+            // Tuples are pairs that end with unit.
+            let mut items = vec![Sexp::atom("tuple")];
+            let mut e = e;
+            while let Exp::Pair(e1, e2) = e {
+                items.push(exp_sexp(e1)?);
+                e = skip_coercions(e2);
+            }
+            if e != &Exp::Lit(Lit::Unit) {
+                return Err(format!("Grift has no pairs, but found {}", e));
+            }
+            Sexp::List(items)
+        }
+        // (tuple-proj e n) is the first element of the nth tail of e.
+        Exp::Fst(e) => {
+            let mut n = 0;
+            let mut e = skip_coercions(e);
+            while let Exp::Snd(tail) = e {
+                n += 1;
+                e = skip_coercions(tail);
+            }
+            Sexp::List(vec![
+                Sexp::atom("tuple-proj"),
+                exp_sexp(e)?,
+                Sexp::atom(n.to_string()),
+            ])
+        }
+        Exp::Box(e) => list("box", &[e])?,
+        Exp::Unbox(e) => list("unbox", &[e])?,
+        Exp::BoxSet(e1, e2) => list("box-set!", &[e1, e2])?,
+        Exp::Vector(e1, e2) => list("vector", &[e1, e2])?,
+        Exp::VectorRef(e1, e2) => list("vector-ref", &[e1, e2])?,
+        Exp::VectorSet(e1, e2, e3) => list("vector-set!", &[e1, e2, e3])?,
+        Exp::VectorLen(e) => list("vector-length", &[e])?,
+        Exp::Coerce(_, Typ::Any, e, _) => {
+            Sexp::List(vec![Sexp::atom(":"), exp_sexp(e)?, Sexp::atom("Dyn")])
+        }
+        Exp::Coerce(_, _, e, _) => exp_sexp(e)?,
+        Exp::Fix(..)
+        | Exp::AddOverload(..)
+        | Exp::Snd(..)
+        | Exp::Cons(..)
+        | Exp::Empty(..)
+        | Exp::IsEmpty(..)
+        | Exp::Head(..)
+        | Exp::Tail(..)
+        | Exp::IsBool(..)
+        | Exp::IsInt(..)
+        | Exp::IsString(..)
+        | Exp::IsList(..)
+        | Exp::IsFun(..)
+        | Exp::PrimCoerce(..) => return Err(format!("Grift has no expression like {}", e)),
+    })
 }
 
 type Env = HashMap<String, Typ>;
@@ -67,32 +369,38 @@ pub fn env() -> Env {
 
 #[cfg(test)]
 mod test {
-    use super::parse;
     use crate::cgen::typeinf_options;
+    use crate::session::Session;
     use crate::syntax::*;
     use crate::tests_631::contains_coercions;
     use crate::type_check::tcheck;
     use crate::Options;
 
-    fn compile_verbose(orig: Exp) -> (Typ, Exp) {
+    fn parse(program: &str) -> Exp {
+        super::parse(&Session::default(), program)
+    }
+
+    fn compile_verbose(program: &str) -> (Typ, Exp) {
         let env = super::env();
-        println!("\nOriginal program:\n{}", &orig);
         let mut options = Options::default();
         options.debug = true;
-        let e = typeinf_options(orig, &env, options).unwrap();
+        let session = Session::new(options);
+        let orig = super::parse(&session, program);
+        println!("\nOriginal program:\n{}", &orig);
+        let e = typeinf_options(&session, orig, &env).unwrap();
         println!("\nAfter type inference:\n{}", e);
         let t = tcheck(&env, &e).expect("failed to typecheck");
         println!("\nProgram type:\n{}", t);
         (t, e)
     }
-    pub fn exp_succeeds(orig: Exp) -> Typ {
-        let (t, e) = compile_verbose(orig);
+    pub fn exp_succeeds(program: &str) -> Typ {
+        let (t, e) = compile_verbose(program);
         let coercions = contains_coercions(e);
         assert!(!coercions.0 && !coercions.1);
         t
     }
-    pub fn exp_coerces(orig: Exp) -> Typ {
-        let (t, e) = compile_verbose(orig);
+    pub fn exp_coerces(program: &str) -> Typ {
+        let (t, e) = compile_verbose(program);
         let coercions = contains_coercions(e);
         assert!(coercions.0 || coercions.1);
         t
@@ -132,7 +440,7 @@ mod test {
     }
     #[test]
     fn fact_grift_concrete() {
-        exp_coerces(parse(
+        exp_coerces(
             "(let ((f (lambda (f n)
                 (if (= n 0)
                     1
@@ -140,12 +448,12 @@ mod test {
                     (* n (f f (+ n 1)))))))
               ; this was : but again, meh
               (f f 6))",
-        ));
+        );
     }
     #[test]
     fn ack_no_rec() {
         // this is supposed to be letrec but meh
-        exp_succeeds(parse(
+        exp_succeeds(
             "(letrec ([ack (lambda ([m : Int] [n : Int]) : Int
                     (if (= m 0)
                         (+ n 1)
@@ -153,11 +461,11 @@ mod test {
                             (ack (+ m -1) 1)
                             (ack (+ m -1) (ack m (+ n -1))))))])
                   (ack 1 2))",
-        ));
+        );
     }
     #[test]
     fn ack() {
-        exp_succeeds(parse(
+        exp_succeeds(
             "(letrec ([ack (lambda (m n) ; this should have : Dyn but we don't annotate returns yet
                              (if (= m 0)
                                  (+ n 1)
@@ -165,56 +473,54 @@ mod test {
                                      (ack (+ m -1) 1)
                                      (ack (+ m -1) (ack m (+ n -1))))))])
                (ack 3 10)) ; should be : / ann",
-        ));
+        );
     }
     #[test]
     fn box_int() {
-        exp_succeeds(parse(
+        exp_succeeds(
             "(let ((my_box (box 5)))
                 (let ((i_set (box-set! my_box 10)))
                   (unbox my_box)))",
-        ));
+        );
     }
     #[test]
     fn box_any() {
-        exp_coerces(parse(
+        exp_coerces(
             "(let ((my_box (box 5)))
                 (let ((i_set (box-set! my_box #t)))
                   (unbox my_box)))",
-        ));
+        );
     }
     #[test]
     fn box_context() {
-        exp_coerces(parse("(box 5)"));
+        exp_coerces("(box 5)");
     }
     #[test]
     fn box_identities() {
         assert_eq!(
-            exp_coerces(parse(
+            exp_coerces(
                 "(let ((id (lambda (x) x)))
                 (let ((h (id (box 5))))
                 (id (unbox h))))"
-            )),
+            ),
             Typ::Any
         );
     }
     #[test]
     fn box_weakens_box_any() {
         assert_eq!(
-            exp_coerces(parse(
+            exp_coerces(
                 "(let ((my_box (box #t)))
                 (let ((h ((lambda (x) x) my_box)))
                 ((lambda (x) (+ 1 (unbox x))) my_box)))"
-            )),
+            ),
             Typ::Int
         );
     }
     #[test]
     fn box_stay_strong() {
         assert_eq!(
-            exp_succeeds(parse(
-                "(let ((id (lambda (x) x))) (let ((h (id (box 5)))) 5))"
-            )),
+            exp_succeeds("(let ((id (lambda (x) x))) (let ((h (id (box 5)))) 5))"),
             Typ::Int
         );
     }
@@ -224,54 +530,146 @@ mod test {
     }
     #[test]
     fn basic_toplevel() {
+        assert_eq!(exp_succeeds("(define x 5) (define y 10) (+ x y)"), Typ::Int);
         assert_eq!(
-            exp_succeeds(parse("(define x 5) (define y 10) (+ x y)")),
-            Typ::Int
-        );
-        assert_eq!(
-            exp_succeeds(parse(
-                "(define (f) 10) (define x 5) (define y 10) (* (f) (+ x y))"
-            )),
+            exp_succeeds("(define (f) 10) (define x 5) (define y 10) (* (f) (+ x y))"),
             Typ::Int
         );
     }
     #[test]
     fn float_constants() {
         assert_eq!(
-            exp_succeeds(parse(
+            exp_succeeds(
                 "(define x #i8.34336671824457987) (define y #i2.30417297573763929e-5) (define z 0.1) y"
-            )),
+            ),
             Typ::Float
         )
     }
     #[test]
     fn scheme_varnames() {
         assert_eq!(
-            exp_succeeds(parse("(define days-per-year : Float #i365.24) (define *saturn* #i-4.03523417114321381e-1) *saturn*")),
+            exp_succeeds("(define days-per-year : Float #i365.24) (define *saturn* #i-4.03523417114321381e-1) *saturn*"),
             Typ::Float
         );
     }
     #[test]
     fn tuples() {
         assert_eq!(
-            exp_succeeds(parse("(tuple 1 #f \"hi\")")),
+            exp_succeeds("(tuple 1 #f \"hi\")"),
             Typ::tuples(vec![Typ::Int, Typ::Bool, Typ::Str])
         );
+        assert_eq!(exp_succeeds("(tuple-proj (tuple 1 #f \"hi\") 2)"), Typ::Str);
+    }
+    #[test]
+    fn int_ops() {
+        assert_eq!(exp_succeeds("(< (* 1 2) (+ 3 4))"), Typ::Bool);
+    }
+
+    /// Every integer operator of Grift, and the operator that it parses to
+    const INT_OPS: [(&str, BinOp); 16] = [
+        ("=", BinOp::IntEq),
+        ("<", BinOp::IntLt),
+        (">", BinOp::IntGt),
+        ("<=", BinOp::IntLe),
+        (">=", BinOp::IntGe),
+        ("+", BinOp::IntAdd),
+        ("-", BinOp::IntSub),
+        ("*", BinOp::IntMul),
+        ("%/", BinOp::IntQuotient),
+        ("quotient", BinOp::IntQuotient),
+        ("%%", BinOp::IntRemainder),
+        ("%<<", BinOp::IntShiftLeft),
+        ("%>>", BinOp::IntShiftRight),
+        ("binary-and", BinOp::BinaryAnd),
+        ("binary-or", BinOp::BinaryOr),
+        ("binary-xor", BinOp::BinaryXor),
+    ];
+
+    /// Parses the printed program, with unknown types as Dyn, since the printer prints them as
+    /// Dyn.
+    fn round_trip(e: &Exp) -> Exp {
+        let printed = super::print(e).unwrap();
+        let mut reparsed = parse(&printed);
+        reparsed.dynamic_metavars();
+        reparsed
+    }
+
+    #[test]
+    fn print_round_trips() {
+        for entry in std::fs::read_dir("grift-suite/core").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some(std::ffi::OsStr::new("grift")) {
+                continue;
+            }
+            let mut e = parse(&std::fs::read_to_string(&path).unwrap());
+            e.dynamic_metavars();
+            assert_eq!(round_trip(&e), e, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn print_migrated_programs() {
+        let session = Session::default();
+        let mut e = super::parse(&session, "(lambda ([x : Int]) (tuple x #\\space 2.5))");
+        e.fresh_types(&session);
+        let e = typeinf_options(&session, e, &super::env()).unwrap();
         assert_eq!(
-            exp_succeeds(parse("(tuple-proj (tuple 1 #f \"hi\") 2)")),
-            Typ::Str
+            super::print(&e).unwrap(),
+            "(lambda ((x : Dyn)) (tuple x #\\space #i2.5))\n"
         );
+        let coerced = Exp::Coerce(Typ::Int, Typ::Any, Box::new(Exp::Lit(Lit::Int(1))), None);
+        assert_eq!(super::print(&coerced).unwrap(), "(: 1 Dyn)\n");
+        assert_eq!(
+            round_trip(&coerced),
+            Exp::Ann(Box::new(Exp::Lit(Lit::Int(1))), Typ::Any, None)
+        );
+        let list = Exp::Cons(
+            Box::new(Exp::Lit(Lit::Int(1))),
+            Box::new(Exp::Empty(Typ::Int)),
+        );
+        assert!(super::print(&list).is_err());
     }
+
     #[test]
-    fn int_ops() {
-        assert_eq!(exp_succeeds(parse("(< (* 1 2) (+ 3 4))")), Typ::Bool);
+    fn int_ops_parse() {
+        for (name, op) in INT_OPS.iter() {
+            let e = parse(&format!("({} 7 2)", name));
+            assert_eq!(
+                e,
+                Exp::BinaryOp(
+                    *op,
+                    Box::new(Exp::Lit(Lit::Int(7))),
+                    Box::new(Exp::Lit(Lit::Int(2)))
+                ),
+                "{}",
+                name
+            );
+            assert_eq!(round_trip(&e), e, "{}", name);
+        }
+    }
+
+    #[test]
+    fn int_ops_migrate() {
+        for (name, op) in INT_OPS.iter() {
+            let (_, _, ret) = op.typ();
+            assert_eq!(
+                exp_succeeds(&format!("((lambda (x y) ({} x y)) 7 2)", name)),
+                ret,
+                "{}",
+                name
+            );
+            assert_eq!(
+                exp_coerces(&format!("((lambda (x) ({} x 2)) #t)", name)),
+                ret,
+                "{}",
+                name
+            );
+        }
     }
     #[test]
     fn scheme_cond() {
         assert_eq!(
-            exp_coerces(parse(
-                "(lambda (n) (cond [(> n 0) 1] [(< n 0) -1] [else 0]))"
-            )),
+            exp_coerces("(lambda (n) (cond [(> n 0) 1] [(< n 0) -1] [else 0]))"),
             Typ::arrs(vec![Typ::Any, Typ::Int])
         );
     }
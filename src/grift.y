@@ -8,12 +8,12 @@ program -> Vec<Toplevel> :
 
 tl -> Toplevel :
     exp { Toplevel::Exp($1) }
-  | '(' 'define' id         exp ')' { Toplevel::Define($3, next_metavar(), $4) }  
+  | '(' 'define' id         exp ')' { Toplevel::Define($3, session().next_metavar(), $4) }  
   | '(' 'define' id ':' typ exp ')' { Toplevel::Define($3, $5, $6) }  
-  | '(' 'define' '(' id                  ')'         exps ')' { Toplevel::Define($4, next_metavar(), Exp::Fun("__ignored".to_string(), Typ::Unit, Box::new(Exp::begin($6)))) }
-  | '(' 'define' '(' id                  ')' ':' typ exps ')' { Toplevel::Define($4, next_metavar(), Exp::Fun("__ignored".to_string(), Typ::Unit, Box::new(Exp::Ann(Box::new(Exp::begin($8)), $7)))) }
-  | '(' 'define' '(' id nonempty_formals ')'         exps ')' { Toplevel::Define($4, next_metavar(), Exp::funs($5, Exp::begin($7))) } 
-  | '(' 'define' '(' id nonempty_formals ')' ':' typ exps ')' { Toplevel::Define($4, next_metavar(), Exp::funs($5, Exp::Ann(Box::new(Exp::begin($9)), $8))) } 
+  | '(' 'define' '(' id                  ')'         exps ')' { Toplevel::Define($4, session().next_metavar(), Exp::Fun("__ignored".to_string(), Typ::Unit, Box::new(Exp::begin($6)))) }
+  | '(' 'define' '(' id                  ')' ':' typ exps ')' { Toplevel::Define($4, session().next_metavar(), Exp::Fun("__ignored".to_string(), Typ::Unit, Box::new(Exp::Ann(Box::new(Exp::begin($8)), $7, None)))) }
+  | '(' 'define' '(' id nonempty_formals ')'         exps ')' { Toplevel::Define($4, session().next_metavar(), Exp::funs($5, Exp::begin($7))) } 
+  | '(' 'define' '(' id nonempty_formals ')' ':' typ exps ')' { Toplevel::Define($4, session().next_metavar(), Exp::funs($5, Exp::Ann(Box::new(Exp::begin($9)), $8, None))) } 
 ;
 
 exps -> Vec<Exp> :
@@ -25,34 +25,46 @@ exp -> Exp :
       lit { Exp::Lit($1) }
     | id  { Exp::Var($1) }
 
-    | '(' ':' exp typ ')'     { Exp::Ann(Box::new($3), $4) }
-    | '(' 'ann' exp typ ')'   { Exp::Ann(Box::new($3), $4) }
-    | '(' ':' exp typ str ')' { Exp::Ann(Box::new($3), $4) } // TODO(mmg): store blame label somewhere?
+    | '(' ':' exp typ ')'     { Exp::Ann(Box::new($3), $4, None) }
+    | '(' 'ann' exp typ ')'   { Exp::Ann(Box::new($3), $4, None) }
+    | '(' ':' exp typ str ')' { Exp::Ann(Box::new($3), $4, Some($5)) }
 
     | '(' 'let'    bindings exps ')' { Exp::lets($3, Exp::begin($4)) }
     | '(' 'letrec' bindings exps ')' { 
       Exp::LetRec(
-        $3.into_iter().map(|(x,to,e)| (x, to.unwrap_or_else(|| next_metavar()), e)).collect(), 
+        $3.into_iter().map(|(x,to,e)| (x, to.unwrap_or_else(|| session().next_metavar()), e)).collect(), 
         Box::new(Exp::begin($4)),
       )
     }
 
-    | '(' 'lambda' formals ':' typ exps ')' { Exp::funs($3, Exp::Ann(Box::new(Exp::begin($6)), $5)) }
+    | '(' 'lambda' formals ':' typ exps ')' { Exp::funs($3, Exp::Ann(Box::new(Exp::begin($6)), $5, None)) }
     | '(' 'lambda' formals         exps ')' { Exp::funs($3, Exp::begin($4)) }
 
     | '(' 'repeat' '(' id exp exp ')' exp exp ')' { 
       match $8 {
           Exp::App(e_id, e_rest) => {
             match *e_id {
-              Exp::Var(id) => Exp::repeat($4, $5, $6, id, next_metavar(), *e_rest, $9) ,
+              Exp::Var(id) => Exp::repeat(&session(), Repeat {
+                var: $4,
+                lo: $5,
+                hi: $6,
+                acc: id,
+                acc_typ: session().next_metavar(),
+                acc_init: *e_rest,
+                body: $9,
+              }),
               _ => panic!("repeat accumulator should be of the form '(id [: typ] exp)', found {} instead of id", e_id),
             }
           },
           _ => panic!("repeat accumulator should be of the form '(id [: typ] exp)'"),
         }
       }
-    | '(' 'repeat' '(' id exp exp ')' '(' id ':' typ exp ')' exp ')'   { Exp::repeat($4, $5, $6, $9, $11, $12, $14) }
-    | '(' 'repeat' '(' id exp exp ')' exp ')'                          { Exp::repeat($4, $5, $6, "_".to_string(), next_metavar(), Exp::Lit(Lit::Unit), $8) }
+    | '(' 'repeat' '(' id exp exp ')' '(' id ':' typ exp ')' exp ')'   {
+        Exp::repeat(&session(), Repeat { var: $4, lo: $5, hi: $6, acc: $9, acc_typ: $11, acc_init: $12, body: $14 })
+      }
+    | '(' 'repeat' '(' id exp exp ')' exp ')'                          {
+        Exp::repeat(&session(), Repeat { var: $4, lo: $5, hi: $6, acc: "_".to_string(), acc_typ: session().next_metavar(), acc_init: Exp::Lit(Lit::Unit), body: $8 })
+      }
 
     | '(' 'switch' exp switch_cases '(' 'else' exp ')' ')' { Exp::switch($3, $4, $7) }
     | '(' 'switch' exp              '(' 'else' exp ')' ')' { Exp::switch($3, Vec::new(), $6) }
@@ -131,7 +143,7 @@ binding -> (String, Option<Typ>, Exp) :
 ;
 
 formals -> Vec<(String, Typ)> :
-      '(' ')'                  { vec![("_".to_string(), next_metavar())] }
+      '(' ')'                  { vec![("_".to_string(), session().next_metavar())] }
     | '(' nonempty_formals ')' { $2 }
 ;
 
@@ -141,13 +153,13 @@ nonempty_formals -> Vec<(String, Typ)> :
 ;
 
 formal -> (String, Typ) :
-        id             { ($1, next_metavar()) }
+        id             { ($1, session().next_metavar()) }
   | '(' id ':' typ ')' { ($2, $4) }
 ;
 
 repeat_acc -> (Typ, Exp) :
     ':' typ exp { ($2, $3) }
-  | exp         { (next_metavar(), $1) }
+  | exp         { (session().next_metavar(), $1) }
 ;
 
 switch_cases -> Vec<(Vec<i32>, Exp)> :
@@ -191,7 +203,7 @@ typ -> Typ :
   | 'Char'                { Typ::Char }
   | '(' ')'               { Typ::Unit }
   | 'Unit'                { Typ::Unit }
-  | id                    { parser_warning(format!("Treating type variable {} as Dyn.", $1)); Typ::Any }
+  | id                    { session().warning(format!("Treating type variable {} as Dyn.", $1)); Typ::Any }
   | '(' 'Rec' id typ ')'  { $4 }
   ;
 
@@ -251,24 +263,24 @@ id -> String :
   ;
 
 binop_nonid -> BinOp :
-    '<' { BinOp::IntEq }
-  | '>' { BinOp::IntEq }
+    '<' { BinOp::IntLt }
+  | '>' { BinOp::IntGt }
   | '=' { BinOp::IntEq }
-  | '>=' { BinOp::IntEq }
-  | '<=' { BinOp::IntEq }
+  | '>=' { BinOp::IntGe }
+  | '<=' { BinOp::IntLe }
   | '+' { BinOp::IntAdd }
-  | '-' { BinOp::IntMul }
+  | '-' { BinOp::IntSub }
   | '*' { BinOp::IntMul }
-  | '%/' { BinOp::IntMul }
-  | '%>>' { BinOp::IntMul }
-  | '%<<' { BinOp::IntMul }
-  | '%%' { BinOp::IntMul }
+  | '%/' { BinOp::IntQuotient }
+  | '%>>' { BinOp::IntShiftRight }
+  | '%<<' { BinOp::IntShiftLeft }
+  | '%%' { BinOp::IntRemainder }
   ;
 
 %%
 
 use crate::syntax::*;
-use crate::parser::{next_metavar, parser_warning};
+use crate::parser::session;
 
 fn as_var(e: &Exp) -> Option<&str> {
     if let Exp::Var(id) = e {
@@ -281,10 +293,10 @@ fn as_var(e: &Exp) -> Option<&str> {
 fn binop(s: &str) -> Option<BinOp> {
     use BinOp::*;
     match &s[..] {
-        "quotient" => Some(IntMul),
-        "binary-and" => Some(IntMul),
-        "binary-or" => Some(IntMul),
-        "binary-xor" => Some(IntMul),
+        "quotient" => Some(IntQuotient),
+        "binary-and" => Some(BinaryAnd),
+        "binary-or" => Some(BinaryOr),
+        "binary-xor" => Some(BinaryXor),
         "and" => Some(And),
         "or" => Some(And),
         "fl<" => Some(FloatEq),
@@ -0,0 +1,301 @@
+//! Incremental re-migration, for editors and other tools that migrate the
+//! same program over and over as it is edited.
+//!
+//! The program is a list of top-level definitions and a body, and each of
+//! them is a separate group of constraints in one long-lived solver. Every
+//! group is guarded by an assumption literal: the solver only enforces the
+//! constraints of the groups whose literals we pass to `check`. When a
+//! definition changes, we retire its old literal for good and generate
+//! constraints for the new definition. Every definition has a metavariable
+//! that stands for its type, which persists across edits, so that the
+//! constraints of the other definitions remain valid.
+//!
+//! The pre-solver is disabled here, since unification cannot be undone when
+//! a definition is retired.
+use super::cgen::{annotate, State};
+use super::modular::free_vars;
+use super::presolve::Phi;
+use super::session::Session;
+use super::syntax::*;
+use super::z3_state::Z3State;
+use super::Options;
+use im_rc::HashMap;
+use std::collections::HashSet;
+use z3::ast::Bool;
+use z3::{Context, DatatypeSort, SatResult};
+
+type Env = HashMap<String, Typ>;
+
+struct Group<'a> {
+    /// The source of the group, with metavariables erased
+    source: Exp,
+    /// The variables that the group refers to
+    free: HashSet<Id>,
+    lit: Bool<'a>,
+    /// The group after constraint generation, which still has metavariables
+    migrated: Exp,
+    typ: Typ,
+}
+
+pub struct Incremental<'a> {
+    s: State<'a>,
+    cxt: &'a Context,
+    session: Session,
+    env: Env,
+    /// The type of every top-level definition
+    binders: std::collections::HashMap<Id, Typ>,
+    defs: Vec<(Id, Group<'a>)>,
+    body: Option<Group<'a>>,
+}
+
+impl<'a> Incremental<'a> {
+    /// Definitions passed to `update` must be parsed with `session`.
+    pub fn new(cxt: &'a Context, typ: &'a DatatypeSort<'a>, session: &Session, env: &Env) -> Self {
+        let session = session.with_options(Options {
+            presolve: false,
+            ..session.options
+        });
+        Incremental {
+            s: State::new(Z3State::new(cxt, typ), &session),
+            cxt,
+            session,
+            env: env.clone(),
+            binders: Default::default(),
+            defs: Vec::new(),
+            body: None,
+        }
+    }
+
+    /// Replaces the program with the given definitions and body. Constraints
+    /// are only generated again for the definitions that changed, and for the
+    /// ones that refer to a definition that was added or removed. Returns the
+    /// number of groups that were generated.
+    pub fn update(&mut self, bindings: Vec<(Id, Typ, Exp)>, body: Exp) -> usize {
+        let names: HashSet<Id> = bindings.iter().map(|(x, _, _)| x.clone()).collect();
+        let mut old_defs: std::collections::HashMap<Id, Group<'a>> = self.defs.drain(..).collect();
+        let mut renamed: HashSet<Id> = old_defs
+            .keys()
+            .filter(|x| !names.contains(*x))
+            .cloned()
+            .collect();
+        renamed.extend(names.iter().filter(|x| !old_defs.contains_key(*x)).cloned());
+        for x in renamed.iter().filter(|x| !names.contains(*x)) {
+            self.binders.remove(x);
+        }
+
+        let mut env = self.env.clone();
+        for (x, _, _) in bindings.iter() {
+            let session = &self.session;
+            let binder = self
+                .binders
+                .entry(x.clone())
+                .or_insert_with(|| session.next_metavar())
+                .clone();
+            env.insert(x.clone(), binder);
+        }
+
+        let mut generated = 0;
+        for (x, t, e) in bindings.into_iter() {
+            let source = Exp::Ann(Box::new(e.clone()), t.clone(), None);
+            let group = match old_defs.remove(&x) {
+                Some(group) if self.unchanged(&group, &source, &renamed) => group,
+                old => {
+                    if let Some(group) = old {
+                        self.retire(&group);
+                    }
+                    generated += 1;
+                    let binder = &self.binders[&x];
+                    self.generate(&env, source, e, |t_e| {
+                        Phi::eq(binder, &t_e) & Phi::eq(binder, &t)
+                    })
+                }
+            };
+            self.defs.push((x, group));
+        }
+        for group in old_defs.values() {
+            self.retire(group);
+        }
+
+        let source = body.clone();
+        self.body = match self.body.take() {
+            Some(group) if self.unchanged(&group, &source, &renamed) => Some(group),
+            old => {
+                if let Some(group) = old {
+                    self.retire(&group);
+                }
+                generated += 1;
+                Some(self.generate(&env, source, body, |_| Phi::True))
+            }
+        };
+        generated
+    }
+
+    /// Solves the constraints of the current program. The result is the
+    /// same as migrating the `letrec` of the definitions and the body from
+    /// scratch.
+    pub fn solve(&self) -> Result<Exp, String> {
+        let body = self.body.as_ref().expect("update before solve");
+        let lits: Vec<Bool<'a>> = self
+            .defs
+            .iter()
+            .map(|(_, group)| group.lit.clone())
+            .chain(std::iter::once(body.lit.clone()))
+            .collect();
+        match self.s.solver.check(&lits) {
+            SatResult::Unsat => return Err("unsat (precise type)".to_string()),
+            SatResult::Unknown => return Err("unknown from Z3 -- very bad".to_string()),
+            SatResult::Sat => (),
+        }
+        let mut model = self.s.solver.get_model().expect("model not available");
+        if self.session.options.context {
+            self.s.solver.push();
+            let negative_any = self.s.negative_any(&model, &self.s.t2z3(&body.typ));
+            self.s.solver.assert(&negative_any);
+            let result = self.s.solver.check(&lits);
+            if result == SatResult::Sat {
+                model = self.s.solver.get_model().expect("model not available");
+            }
+            self.s.solver.pop();
+            match result {
+                SatResult::Unsat => return Err("unsat (context)".to_string()),
+                SatResult::Unknown => return Err("unknown from Z3 -- very bad".to_string()),
+                SatResult::Sat => (),
+            }
+        }
+        let result = self.s.solve_model(model);
+        let mut exp = if self.defs.is_empty() {
+            body.migrated.clone()
+        } else {
+            Exp::LetRec(
+                self.defs
+                    .iter()
+                    .map(|(x, group)| (x.clone(), self.binders[x].clone(), group.migrated.clone()))
+                    .collect(),
+                Box::new(body.migrated.clone()),
+            )
+        };
+        annotate(&result, &mut exp);
+        Ok(exp)
+    }
+
+    fn unchanged(&self, group: &Group<'a>, source: &Exp, renamed: &HashSet<Id>) -> bool {
+        let mut source = source.clone();
+        source.erase_metavars();
+        group.source == source && group.free.is_disjoint(renamed)
+    }
+
+    fn generate(
+        &self,
+        env: &Env,
+        mut source: Exp,
+        mut exp: Exp,
+        phi: impl FnOnce(Typ) -> Phi,
+    ) -> Group<'a> {
+        let mut free = HashSet::new();
+        free_vars(&Default::default(), &exp, &mut free);
+        source.erase_metavars();
+        let lit = Bool::fresh_const(self.cxt, "group");
+        let (typ, phi_exp) = self.s.cgen(env, &mut exp);
+        self.s.assert_group(&lit, phi_exp & phi(typ.clone()));
+        Group {
+            source,
+            free,
+            lit,
+            migrated: exp,
+            typ,
+        }
+    }
+
+    /// The literal of a retired group is never passed to `check` again, so
+    /// its constraints are ignored. Asserting its negation also lets Z3
+    /// simplify them away.
+    fn retire(&self, group: &Group<'a>) {
+        self.s.solver.assert(&group.lit.not());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Incremental;
+    use crate::cgen::typeinf_options;
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::syntax::*;
+    use crate::z3_state::Z3State;
+    use crate::Options;
+
+    /// Parses with fresh metavariables on every binder, as `migrate` does
+    fn parse_fresh(session: &Session, prog: &str) -> Exp {
+        let mut exp = parse(session, prog).unwrap();
+        exp.fresh_types(session);
+        exp
+    }
+
+    fn split(session: &Session, prog: &str) -> (Vec<(Id, Typ, Exp)>, Exp) {
+        match parse_fresh(session, prog) {
+            Exp::LetRec(bindings, body) => (bindings, *body),
+            e => (Vec::new(), e),
+        }
+    }
+
+    fn binder_typs(exp: &Exp) -> Vec<Typ> {
+        match exp {
+            Exp::LetRec(bindings, _) => bindings.iter().map(|(_, t, _)| t.clone()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn from_scratch(prog: &str) -> Vec<Typ> {
+        let session = Session::new(Options::default());
+        let exp = parse_fresh(&session, prog);
+        binder_typs(&typeinf_options(&session, exp, &Default::default()).unwrap())
+    }
+
+    #[test]
+    fn matches_from_scratch() {
+        let before = "let rec inc = fun x . x + 1
+                      and twice = fun f . fun y . f (f y)
+                      in twice inc 10";
+        let after = "let rec inc = fun x . not x
+                     and twice = fun f . fun y . f (f y)
+                     in twice inc true";
+        let cfg = z3::Config::new();
+        let cxt = z3::Context::new(&cfg);
+        let typ = Z3State::typ(&cxt);
+        let session = Session::new(Options::default());
+        let mut inc = Incremental::new(&cxt, &typ, &session, &Default::default());
+
+        let (bindings, body) = split(&session, before);
+        assert_eq!(inc.update(bindings, body), 3);
+        assert_eq!(binder_typs(&inc.solve().unwrap()), from_scratch(before));
+
+        let (bindings, body) = split(&session, after);
+        assert_eq!(inc.update(bindings, body), 2);
+        assert_eq!(binder_typs(&inc.solve().unwrap()), from_scratch(after));
+
+        let (bindings, body) = split(&session, before);
+        assert_eq!(inc.update(bindings, body), 2);
+        assert_eq!(binder_typs(&inc.solve().unwrap()), from_scratch(before));
+    }
+
+    #[test]
+    fn shadowing_definitions_are_regenerated() {
+        let cfg = z3::Config::new();
+        let cxt = z3::Context::new(&cfg);
+        let typ = Z3State::typ(&cxt);
+        let mut env = im_rc::HashMap::new();
+        env.insert(
+            "f".to_string(),
+            Typ::Arr(Box::new(Typ::Int), Box::new(Typ::Int)),
+        );
+        let session = Session::new(Options::default());
+        let mut inc = Incremental::new(&cxt, &typ, &session, &env);
+        let (bindings, body) = split(&session, "let rec g = fun y . f y in g 1");
+        assert_eq!(inc.update(bindings, body), 2);
+        inc.solve().unwrap();
+        // g is unchanged, but now refers to the new definition of f
+        let (bindings, body) = split(&session, "let rec f = fun x . x and g = fun y . f y in g 1");
+        assert_eq!(inc.update(bindings, body), 2);
+        inc.solve().unwrap();
+    }
+}
@@ -1,10 +1,11 @@
 use super::syntax::*;
+use crate::session::Session;
 use im_rc::{HashMap, HashSet};
 
 /// This also performs Base and Comp of the closure computation
-pub fn compile_coercions(x: Exp) -> (Exp, Typ, HashSet<(Typ, Typ)>) {
+pub fn compile_coercions(session: &Session, x: Exp) -> (Exp, Typ, HashSet<(Typ, Typ)>) {
     // Base
-    let (e, t, mut c) = compile(x, &HashMap::new());
+    let (e, t, mut c) = compile(session, x, &HashMap::new());
     c = c.union(comp(t.clone(), true));
     (e, t, c)
 }
@@ -27,7 +28,7 @@ fn comp(t: Typ, pos: bool) -> HashSet<(Typ, Typ)> {
 /// this matches Figure 3: Compilation judgment, except that we collect
 /// coercions into a HashSet so as to not have to collect them for closure
 /// computation later
-fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>) {
+fn compile(session: &Session, exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>) {
     match exp {
         // -------------------------
         // Γ ⊢ Lit(l) ↪ Lit(l) :: l.typ()
@@ -47,7 +48,7 @@ fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>)
         // Γ ⊢ x = e ↪ ⟨t2 ▷ t1⟩ e' :: t1
         Exp::Assign(id, e) => {
             let t1 = env.get(&id).expect("unknown identifier").clone();
-            let (ep, t2, c1) = compile(*e, env);
+            let (ep, t2, c1) = compile(session, *e, env);
             let (coerced, c2) = coerce(t2, t1.clone(), ep);
             (Exp::Assign(id, Box::new(coerced)), t1, c1.union(c2))
         }
@@ -58,7 +59,7 @@ fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>)
         Exp::Fun(x, t1, e2, t2) => {
             let mut env = env.clone();
             env.insert(x.clone(), t1.clone());
-            let (e2p, t2p, c1) = compile(*e2, &env);
+            let (e2p, t2p, c1) = compile(session, *e2, &env);
             let (e2pp, c2) = coerce(t2p, t2.clone(), e2p);
             let e = Exp::Fun(x, t1.clone(), Box::new(e2pp), t2.clone());
             let t = Typ::Arr(Box::new(t1), Box::new(t2));
@@ -71,8 +72,8 @@ fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>)
         // ----------------------
         // Γ ⊢ e e1 ↪ (⟨t ▷ t1 -> t2⟩ e') e1'' :: t2
         Exp::App(e, e1) => {
-            let (ep, t, c1) = compile(*e, env);
-            let (e1p, t1p, c2) = compile(*e1, env);
+            let (ep, t, c1) = compile(session, *e, env);
+            let (e1p, t1p, c2) = compile(session, *e1, env);
             let t1 = t.get_arg();
             let (e1pp, c3) = coerce(t1p, t1.clone(), e1p);
             let t2 = t.get_ret();
@@ -96,10 +97,10 @@ fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>)
             // this is the ActionScript talking, although eliminating
             // null/undefined is one part of why we're doing type inference for
             // Jankscripten, so i'm sticking with "i don't like it"
-            let (ep, _t, c1) = compile(*e, env);
-            let (e1p, t1, c2) = compile(*e1, env);
-            let (e2p, t2, c3) = compile(*e2, env);
-            let alpha = Typ::Metavar(crate::parser::inc_metavar());
+            let (ep, _t, c1) = compile(session, *e, env);
+            let (e1p, t1, c2) = compile(session, *e1, env);
+            let (e2p, t2, c3) = compile(session, *e2, env);
+            let alpha = Typ::Metavar(session.inc_metavar());
             let (e1pp, c4) = coerce(t1, alpha.clone(), e1p);
             let (e2pp, c5) = coerce(t2, alpha.clone(), e2p);
             let res_e = Exp::If(Box::new(ep), Box::new(e1pp), Box::new(e2pp));
@@ -112,8 +113,8 @@ fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>)
         // ----------------------------
         // Γ ⊢ e1 + e2 ↪ e1'' + e2'' :: int
         Exp::Add(e1, e2) => {
-            let (e1p, t1, c1) = compile(*e1, env);
-            let (e2p, t2, c2) = compile(*e2, env);
+            let (e1p, t1, c1) = compile(session, *e1, env);
+            let (e2p, t2, c2) = compile(session, *e2, env);
             let (e1pp, c3) = coerce(t1, Typ::Int, e1p);
             let (e2pp, c4) = coerce(t2, Typ::Int, e2p);
             let res_e = Exp::Add(Box::new(e1pp), Box::new(e2pp));
@@ -124,8 +125,8 @@ fn compile(exp: Exp, env: &HashMap<Id, Typ>) -> (Exp, Typ, HashSet<(Typ, Typ)>)
         // ----------------------------
         // Γ ⊢ e1; e2 ↪ e1'; e2' :: t2
         Exp::Seq(e1, e2) => {
-            let (e1p, _, c1) = compile(*e1, env);
-            let (e2p, t2, c2) = compile(*e2, env);
+            let (e1p, _, c1) = compile(session, *e1, env);
+            let (e2p, t2, c2) = compile(session, *e2, env);
             let res_e = Exp::Seq(Box::new(e1p), Box::new(e2p));
             (res_e, t2, c1.union(c2))
         }
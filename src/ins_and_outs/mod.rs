@@ -11,18 +11,20 @@ const DEBUG: bool = false;
 #[cfg(test)]
 const DEBUG: bool = true;
 
+use crate::session::Session;
+
 type Closure = im_rc::HashSet<(syntax::Typ, syntax::Typ)>;
 
-pub fn typeinf_portable(exp: crate::syntax::Exp) -> crate::syntax::Exp {
-    let internal_e =
-        to_from_portable_ast::from_exp(&exp).expect("unsupported features in input program");
-    let (checked_e, _) = typeinf(internal_e);
+pub fn typeinf_portable(session: &Session, exp: crate::syntax::Exp) -> crate::syntax::Exp {
+    let internal_e = to_from_portable_ast::from_exp(session, &exp)
+        .expect("unsupported features in input program");
+    let (checked_e, _) = typeinf(session, internal_e);
     return to_from_portable_ast::to_exp(checked_e)
         .expect("migrated program has unsupported features");
 }
 
-pub fn typeinf(parsed: syntax::Exp) -> (syntax::Exp, syntax::Typ) {
-    let (mut coerced, mut typ, coercions) = collect_coercions::compile_coercions(parsed);
+pub fn typeinf(session: &Session, parsed: syntax::Exp) -> (syntax::Exp, syntax::Typ) {
+    let (mut coerced, mut typ, coercions) = collect_coercions::compile_coercions(session, parsed);
     if DEBUG {
         println!(
             "{}\ncoercions:\n{}",
@@ -49,13 +51,16 @@ pub fn typeinf(parsed: syntax::Exp) -> (syntax::Exp, syntax::Typ) {
 #[cfg(test)]
 mod tests {
     use super::syntax::*;
-    use super::typeinf;
+    use crate::session::Session;
 
-    fn parse(s: &str) -> Exp {
-        let mut portable_input_e = crate::parser::parse(s).unwrap();
-        portable_input_e.fresh_types();
-        return super::to_from_portable_ast::from_exp(&portable_input_e)
+    fn typeinf(s: &str) -> (Exp, Typ) {
+        let session = Session::default();
+        let mut portable_input_e = crate::parser::parse(&session, s).unwrap();
+        portable_input_e.fresh_types(&session);
+        let orig = super::to_from_portable_ast::from_exp(&session, &portable_input_e)
             .expect("unsupported features");
+        println!("\nOriginal program:\n{}", &orig);
+        super::typeinf(&session, orig)
     }
 
     pub fn contains_coercions(e: Exp) -> bool {
@@ -72,22 +77,51 @@ mod tests {
         }
     }
     pub fn succeeds(program: &str) {
-        let orig = parse(program);
-        println!("\nOriginal program:\n{}", &orig);
-        let e = typeinf(orig);
+        let e = typeinf(program);
         println!("\nAfter type inference:\n{}", e.1);
         let coercions = contains_coercions(e.0);
         assert!(!coercions);
     }
     pub fn coerces(program: &str) {
-        let orig = parse(program);
-        println!("\nOriginal program:\n{}", &orig);
-        let e = typeinf(orig);
+        let e = typeinf(program);
         println!("\nAfter type inference:\n{}", e.1);
         let coercions = contains_coercions(e.0);
         assert!(coercions);
     }
     #[test]
+    fn only_add_of_the_int_ops() {
+        // Ins and outs only knows +, so it must refuse the other operators,
+        // rather than migrate them as +
+        let ops = [
+            "=",
+            "<",
+            ">",
+            "<=",
+            ">=",
+            "-",
+            "*",
+            "%/",
+            "quotient",
+            "%%",
+            "%<<",
+            "%>>",
+            "binary-and",
+            "binary-or",
+            "binary-xor",
+        ];
+        let session = Session::default();
+        let add = crate::grift::parse(&session, "(+ 7 2)");
+        assert!(super::to_from_portable_ast::from_exp(&session, &add).is_ok());
+        for op in ops.iter() {
+            let e = crate::grift::parse(&session, format!("({} 7 2)", op));
+            assert!(
+                super::to_from_portable_ast::from_exp(&session, &e).is_err(),
+                "{}",
+                op
+            );
+        }
+    }
+    #[test]
     fn an_int() {
         succeeds("5");
     }
@@ -183,7 +217,7 @@ mod tests {
     }
     #[test]
     fn identity_public() {
-        let e = typeinf(parse("(fun i . i)")).0;
+        let e = typeinf("(fun i . i)").0;
         match e {
             Exp::Fun(_, Typ::Any, _, Typ::Any) => (),
             _ => panic!(),
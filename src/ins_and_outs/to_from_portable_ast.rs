@@ -1,10 +1,11 @@
 use super::syntax::*;
+use crate::session::Session;
 use crate::syntax::{BinOp, Exp as SrcExp, Lit as SrcLit, Typ as SrcTyp};
 
 type Result<T> = std::result::Result<T, ()>;
 
-fn next_metavar_typ() -> Typ {
-    Typ::Metavar(crate::parser::inc_metavar())
+fn next_metavar_typ(session: &Session) -> Typ {
+    Typ::Metavar(session.inc_metavar())
 }
 
 pub fn from_typ(t: &SrcTyp) -> Result<Typ> {
@@ -29,7 +30,7 @@ pub fn from_lit(l: &SrcLit) -> Result<Lit> {
     }
 }
 
-pub fn from_exp(e: &SrcExp) -> Result<Exp> {
+pub fn from_exp(session: &Session, e: &SrcExp) -> Result<Exp> {
     match e {
         // skips assign and seq
         SrcExp::Lit(l) => Ok(Exp::Lit(from_lit(l)?)),
@@ -37,26 +38,30 @@ pub fn from_exp(e: &SrcExp) -> Result<Exp> {
         SrcExp::Fun(x, t, e) => Ok(Exp::Fun(
             x.clone(),
             from_typ(t)?,
-            Box::new(from_exp(e)?),
-            next_metavar_typ(),
+            Box::new(from_exp(session, e)?),
+            next_metavar_typ(session),
+        )),
+        SrcExp::App(e1, e2) => Ok(Exp::App(
+            Box::new(from_exp(session, e1)?),
+            Box::new(from_exp(session, e2)?),
         )),
-        SrcExp::App(e1, e2) => Ok(Exp::App(Box::new(from_exp(e1)?), Box::new(from_exp(e2)?))),
         SrcExp::If(e1, e2, e3) => Ok(Exp::If(
-            Box::new(from_exp(e1)?),
-            Box::new(from_exp(e2)?),
-            Box::new(from_exp(e3)?),
+            Box::new(from_exp(session, e1)?),
+            Box::new(from_exp(session, e2)?),
+            Box::new(from_exp(session, e3)?),
+        )),
+        SrcExp::BinaryOp(BinOp::IntAdd, e1, e2) => Ok(Exp::Add(
+            Box::new(from_exp(session, e1)?),
+            Box::new(from_exp(session, e2)?),
         )),
-        SrcExp::BinaryOp(BinOp::IntAdd, e1, e2) => {
-            Ok(Exp::Add(Box::new(from_exp(e1)?), Box::new(from_exp(e2)?)))
-        }
         SrcExp::Let(x, e1, e2) => Ok(Exp::App(
             Box::new(Exp::Fun(
                 x.to_string(),
-                next_metavar_typ(),
-                Box::new(from_exp(e2)?),
-                next_metavar_typ(),
+                next_metavar_typ(session),
+                Box::new(from_exp(session, e2)?),
+                next_metavar_typ(session),
             )),
-            Box::new(from_exp(e1)?),
+            Box::new(from_exp(session, e1)?),
         )),
         _ => Err(()),
     }
@@ -94,6 +99,7 @@ pub fn to_exp(e: Exp) -> Result<SrcExp> {
             to_typ(t1)?,
             to_typ(t2)?,
             Box::new(to_exp(*e)?),
+            None,
         )),
         Exp::If(e1, e2, e3) => Ok(SrcExp::If(
             Box::new(to_exp(*e1)?),
@@ -6,21 +6,13 @@
 //!
 //! At the moment, it only supports the fragment of the language we need for
 //! the comparative evaluation.
-use super::syntax::{Coerce, Exp, GroundTyp, Id, Lit, Typ};
+use super::syntax::{BinOp, Blame, Coerce, Exp, GroundTyp, Id, Typ};
 use im_rc::HashMap;
 
 type Env = HashMap<Id, Typ>;
 
 type R = Result<Typ, String>;
 
-fn lit_typ(lit: &Lit) -> Typ {
-    match lit {
-        Lit::Int(_) => Typ::Int,
-        Lit::Bool(_) => Typ::Bool,
-        _ => panic!("unsupported literal {:?}", lit),
-    }
-}
-
 fn fun_typ(t: Typ) -> Result<(Typ, Typ), String> {
     match t {
         Typ::Arr(dom, rng) => Ok((*dom, *rng)),
@@ -29,29 +21,46 @@ fn fun_typ(t: Typ) -> Result<(Typ, Typ), String> {
     }
 }
 
-pub fn coerce(src: &Typ, dst: &Typ) -> Coerce {
+/// The coercion from `src` to `dst`. When the coercion fails, it blames
+/// `blame`. Coercions on the domain of a function blame the other party.
+pub fn coerce(src: &Typ, dst: &Typ, blame: &Option<Blame>) -> Coerce {
+    let neg = blame.as_ref().map(Blame::negate);
     match (src, dst) {
+        (Typ::Unit, Typ::Any) => Coerce::Tag(GroundTyp::Unit),
         (Typ::Int, Typ::Any) => Coerce::Tag(GroundTyp::Int),
+        (Typ::Float, Typ::Any) => Coerce::Tag(GroundTyp::Float),
         (Typ::Bool, Typ::Any) => Coerce::Tag(GroundTyp::Bool),
+        (Typ::Str, Typ::Any) => Coerce::Tag(GroundTyp::Str),
+        (Typ::Char, Typ::Any) => Coerce::Tag(GroundTyp::Char),
         (Typ::Arr(dom, rng), Typ::Any) => Coerce::Wrap(
-            Box::new(coerce(&Typ::Any, dom)),
-            Box::new(coerce(rng, &Typ::Any)),
+            Box::new(coerce(&Typ::Any, dom, &neg)),
+            Box::new(coerce(rng, &Typ::Any, blame)),
+            blame.clone(),
         )
         .seq(&Coerce::Tag(GroundTyp::Fun)),
-        (Typ::Arr(dom1, rng1), Typ::Arr(dom2, rng2)) => {
-            Coerce::Wrap(Box::new(coerce(dom2, dom1)), Box::new(coerce(rng1, rng2)))
+        (Typ::Arr(dom1, rng1), Typ::Arr(dom2, rng2)) => Coerce::Wrap(
+            Box::new(coerce(dom2, dom1, &neg)),
+            Box::new(coerce(rng1, rng2, blame)),
+            blame.clone(),
+        ),
+        (Typ::Any, Typ::Unit) => Coerce::Untag(GroundTyp::Unit, blame.clone()),
+        (Typ::Any, Typ::Int) => Coerce::Untag(GroundTyp::Int, blame.clone()),
+        (Typ::Any, Typ::Float) => Coerce::Untag(GroundTyp::Float, blame.clone()),
+        (Typ::Any, Typ::Bool) => Coerce::Untag(GroundTyp::Bool, blame.clone()),
+        (Typ::Any, Typ::Str) => Coerce::Untag(GroundTyp::Str, blame.clone()),
+        (Typ::Any, Typ::Char) => Coerce::Untag(GroundTyp::Char, blame.clone()),
+        (Typ::Any, Typ::Arr(dom, rng)) => {
+            Coerce::Untag(GroundTyp::Fun, blame.clone()).seq(&Coerce::Wrap(
+                Box::new(coerce(dom, &Typ::Any, &neg)),
+                Box::new(coerce(&Typ::Any, rng, blame)),
+                blame.clone(),
+            ))
         }
-        (Typ::Any, Typ::Int) => Coerce::Untag(GroundTyp::Int),
-        (Typ::Any, Typ::Bool) => Coerce::Untag(GroundTyp::Bool),
-        (Typ::Any, Typ::Arr(dom, rng)) => Coerce::Untag(GroundTyp::Fun).seq(&Coerce::Wrap(
-            Box::new(coerce(dom, &Typ::Any)),
-            Box::new(coerce(&Typ::Any, rng)),
-        )),
         _ => {
             if src == dst {
                 Coerce::Id
             } else {
-                Coerce::Doomed
+                Coerce::Doomed(blame.clone())
             }
         }
     }
@@ -63,7 +72,7 @@ fn ins(mut env: Env, exp: &mut Exp) -> R {
             let t = env.get(x).ok_or("unbound identifier".to_string())?.clone();
             Ok(t)
         }
-        Exp::Lit(l) => Ok(lit_typ(&l)),
+        Exp::Lit(l) => Ok(l.typ()),
         Exp::Fun(x, t1, e) => {
             env.insert(x.clone(), t1.clone());
             let t2 = ins(env, e)?;
@@ -71,51 +80,79 @@ fn ins(mut env: Env, exp: &mut Exp) -> R {
         }
         Exp::BinaryOp(op, e1, e2) => {
             let (op1, op2, op_res) = op.typ();
+            // The parser passes the single argument of printf, not a list
+            let op2 = match op {
+                BinOp::Printf => Typ::Any,
+                _ => op2,
+            };
             let t1 = ins(env.clone(), e1)?;
             let t2 = ins(env.clone(), e2)?;
-            let k2 = coerce(&t2, &op1);
-            let k1 = coerce(&t1, &op2);
-            let e1_inner = std::mem::replace(&mut **e1, Exp::Lit(Lit::Unit));
-            **e1 = Exp::PrimCoerce(k1, Box::new(e1_inner));
-            let e2_inner = std::mem::replace(&mut **e2, Exp::Lit(Lit::Unit));
-            **e2 = Exp::PrimCoerce(k2, Box::new(e2_inner));
+            let k1 = coerce(&t1, &op1, &None);
+            let k2 = coerce(&t2, &op2, &None);
+            **e1 = e1.take().coerce(k1);
+            **e2 = e2.take().coerce(k2);
             Ok(op_res)
         }
         Exp::App(e1, e2) => {
             let t1 = ins(env.clone(), e1)?;
             let t2 = ins(env.clone(), e2)?;
             let (t11, t12) = fun_typ(t1.clone())?;
-            let k2 = coerce(&t2, &t11);
-            let k1 = coerce(&t1, &Typ::Arr(Box::new(t11), Box::new(t12.clone())));
-            let e1_inner = std::mem::replace(&mut **e1, Exp::Lit(Lit::Unit));
-            **e1 = Exp::PrimCoerce(k1, Box::new(e1_inner));
-            let e2_inner = std::mem::replace(&mut **e2, Exp::Lit(Lit::Unit));
-            **e2 = Exp::PrimCoerce(k2, Box::new(e2_inner));
+            let k2 = coerce(&t2, &t11, &None);
+            let k1 = coerce(&t1, &Typ::Arr(Box::new(t11), Box::new(t12.clone())), &None);
+            **e1 = e1.take().coerce(k1);
+            **e2 = e2.take().coerce(k2);
             Ok(t12)
         }
-        Exp::Coerce(_, t2, e) => {
+        Exp::Coerce(_, t2, e, _) => {
             ins(env.clone(), e)?;
             Ok(t2.clone())
         }
         Exp::If(e1, e2, e3) => {
             let t1 = ins(env.clone(), e1)?;
-            let k1 = coerce(&t1, &Typ::Bool);
-            **e1 = Exp::PrimCoerce(k1, Box::new(e1.take()));
+            let k1 = coerce(&t1, &Typ::Bool, &None);
+            **e1 = e1.take().coerce(k1);
             let t2 = ins(env.clone(), e2)?;
             let t3 = ins(env.clone(), e3)?;
             let t_joined = t2.join(&t3);
-            **e2 = e2.take().coerce(coerce(&t2, &t_joined));
-            **e3 = e3.take().coerce(coerce(&t3, &t_joined));
+            **e2 = e2.take().coerce(coerce(&t2, &t_joined, &None));
+            **e3 = e3.take().coerce(coerce(&t3, &t_joined, &None));
             Ok(t_joined)
         }
-        Exp::Ann(e1, t1) => {
+        Exp::UnaryOp(op, e) => {
+            let (t1, t2) = op.typ();
+            let t = ins(env, e)?;
+            **e = e.take().coerce(coerce(&t, &t1, &None));
+            Ok(t2)
+        }
+        Exp::Let(x, e1, e2) => {
+            let t1 = ins(env.clone(), e1)?;
+            env.insert(x.clone(), t1);
+            ins(env, e2)
+        }
+        Exp::Fix(x, t, e) => {
+            env.insert(x.clone(), t.clone());
+            let t_e = ins(env, e)?;
+            **e = e.take().coerce(coerce(&t_e, t, &None));
+            Ok(t.clone())
+        }
+        Exp::LetRec(bindings, body) => {
+            for (x, t, _) in bindings.iter() {
+                env.insert(x.clone(), t.clone());
+            }
+            for (_, t, e) in bindings.iter_mut() {
+                let t_e = ins(env.clone(), e)?;
+                *e = e.take().coerce(coerce(&t_e, t, &None));
+            }
+            ins(env, body)
+        }
+        Exp::Ann(e1, t1, label) => {
             let t2 = ins(env.clone(), e1)?;
-            let k = coerce(&t2, &t1);
+            let k = coerce(&t2, &t1, &Blame::positive(label));
             let t_result = t1.take();
-            *exp = Exp::PrimCoerce(k, Box::new(e1.take()));
+            *exp = e1.take().coerce(k);
             Ok(t_result)
         }
-        _ => unimplemented!("{:?}", exp),
+        _ => Err(format!("unsupported expression {}", exp)),
     }
 }
 
@@ -1,17 +1,30 @@
 mod benchmark;
+mod cache;
 mod cgen;
+mod contexts;
+mod diff_engines;
 mod eval;
+mod fuzz;
 mod grift;
+mod incremental;
 mod ins_and_outs;
 mod insert_coercions;
+mod modular;
 mod parser;
+mod portfolio;
 mod precision;
+mod presolve;
 mod pretty;
+mod reduce;
+mod session;
+mod shrink;
 mod syntax;
+mod test_suite;
 mod type_check;
+mod validate;
 mod z3_state;
 
-use clap::Clap;
+use clap::{ArgGroup, Clap};
 use std::io::*;
 use std::path::Path;
 
@@ -60,19 +73,77 @@ struct TopLevel {
 
 #[derive(Clap)]
 enum SubCommand {
-    Migrate(Opts),
+    Migrate(MigrateOpts),
     Eval(EvalOpts),
     Benchmark(BenchmarkOpts),
     LatexBenchmarks(BenchmarkOpts),
     LatexBenchmarkSummary(BenchmarkOpts),
     LatexConciseSummary(BenchmarkOpts),
+    Report(ReportOpts),
+    TestSuite(TestSuiteOpts),
+    Fuzz(FuzzOpts),
+    Reduce(ReduceOpts),
+    DiffEngines(DiffEnginesOpts),
 }
 
+/// The options of `Opts` that only affect migration, which eval accepts only
+/// with '--migrate'
+const MIGRATION_ONLY: [&str; 9] = [
+    "disable-optimizer",
+    "disable-presolve",
+    "unsafe-mode",
+    "rigid-variables",
+    "modular",
+    "report-precision-loss",
+    "portfolio",
+    "skip-type-check",
+    "ins-and-outs",
+];
+
+/// Runs a program and prints its value. Exits with status 1 when a coercion
+/// fails, and with status 2 on any other error at runtime.
 #[derive(Clap)]
+#[clap(group = ArgGroup::new("migration")
+    .args(&MIGRATION_ONLY)
+    .multiple(true)
+    .requires("migrate"))]
 struct EvalOpts {
-    input: String,
+    #[clap(flatten)]
+    opts: Opts,
     #[clap(short = 'c', long)]
     show_inserted_coercions: bool,
+    /// Migrate the program before running it
+    #[clap(long)]
+    migrate: bool,
+    /// The file that the program's read primitives read from
+    #[clap(long)]
+    stdin: Option<String>,
+}
+
+/// Shrinks a program for as long as it exhibits a migration bug, and prints
+/// the smallest program found
+#[derive(Clap)]
+struct ReduceOpts {
+    #[clap(flatten)]
+    opts: Opts,
+    /// The bug to preserve: panics, tcheck, new-error, or disagree
+    #[clap(long)]
+    predicate: reduce::Predicate,
+}
+
+/// Compares the types that TypeWhich and ins-and-outs give to binders
+#[derive(Clap)]
+struct DiffEnginesOpts {
+    /// Programs, or directories of .grift and .gtlc programs
+    inputs: Vec<String>,
+    /// The number of random programs to compare on
+    #[clap(long, default_value = "0")]
+    random: usize,
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    /// The approximate number of nodes in each random program
+    #[clap(long, default_value = "20")]
+    size: usize,
 }
 
 #[derive(Clap)]
@@ -81,8 +152,104 @@ struct BenchmarkOpts {
     // Tools to ignore from the benchmark set
     #[clap(long)]
     ignore: Vec<String>,
+    /// Compare the results to the expected results in this file and print
+    /// the differences, instead of printing the results
+    #[clap(long)]
+    expect: Option<String>,
+    /// The number of migrations to run at once
+    #[clap(long, default_value = "1")]
+    jobs: usize,
+    /// The directory where results are cached
+    #[clap(long, default_value = ".benchmark-cache")]
+    cache: String,
+    /// Run every tool on every benchmark, instead of reusing cached results
+    #[clap(long)]
+    no_cache: bool,
+}
+
+/// Renders benchmark results as Markdown or as a self-contained HTML page
+#[derive(Clap)]
+struct ReportOpts {
+    input: String,
+    /// markdown or html
+    #[clap(long, default_value = "markdown")]
+    format: benchmark::ReportFormat,
+}
+
+#[derive(Clap)]
+struct TestSuiteOpts {
+    /// Directory of .grift and .gtlc programs
+    input: String,
+    /// Only run the programs whose paths contain one of these strings
+    filter: Vec<String>,
+    /// Write the JSON summary to this file, instead of printing it
+    #[clap(long)]
+    summary: Option<String>,
+}
+
+/// Checks migration on random programs
+#[derive(Clap)]
+struct FuzzOpts {
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    /// The number of programs to generate
+    #[clap(long, default_value = "100")]
+    count: usize,
+    /// The approximate number of nodes in each program
+    #[clap(long, default_value = "20")]
+    size: usize,
+    #[clap(long)]
+    no_bools: bool,
+    #[clap(long)]
+    no_ifs: bool,
+    #[clap(long)]
+    no_lets: bool,
+    /// Do not annotate function arguments
+    #[clap(long)]
+    no_annotations: bool,
 }
 
+/// Migrates a program and prints the result
+#[derive(Clap)]
+struct MigrateOpts {
+    #[clap(flatten)]
+    opts: Opts,
+    /// Keep running, and migrate the input file again whenever it changes,
+    /// only generating constraints for the definitions that changed
+    #[clap(long)]
+    watch: bool,
+    /// Run the original program and the result of migration, and fail
+    /// instead of printing the result if migration changed what the program
+    /// does
+    #[clap(long)]
+    validate: bool,
+    /// With --validate, run both programs in this context, which refers to
+    /// the program as 'HOLE'
+    #[clap(long, requires = "validate")]
+    context: Option<String>,
+    /// Generate contexts from the types of the original and migrated
+    /// programs, and report the first context in which the migrated program
+    /// fails but the original does not
+    #[clap(long)]
+    find_context: bool,
+    /// Report the precision of the original program and the result of
+    /// migration on standard error
+    #[clap(long)]
+    stats: bool,
+    /// When a file is provided, inferred types are corresponded to the
+    /// provided file's type annotations and whether they match (ignoring
+    /// annotations, coercions, and unannotated identifiers) is printed
+    #[clap(long)]
+    compare: Option<String>,
+    /// With a Grift program, print the migrated program in Grift, rather
+    /// than the types of its identifiers
+    #[clap(long)]
+    print_program: bool,
+}
+
+// The options of the parser and of migration, which every subcommand that
+// migrates a program accepts. A doc comment here would replace the
+// descriptions of those subcommands.
 #[derive(Clap)]
 pub struct Opts {
     /// Input file (defaults to '-', meaning STDIN)
@@ -95,12 +262,28 @@ pub struct Opts {
     /// coercions.
     #[clap(long = "no-optimize")]
     disable_optimizer: bool,
+    /// Send every constraint to Z3, instead of first solving equalities with
+    /// union-find
+    #[clap(long = "no-presolve")]
+    disable_presolve: bool,
     /// Produce an exact type that may not be safe in all contexts
     #[clap(long = "precise")]
     unsafe_mode: bool,
     /// All uses of a variable have the same type (by default, variables can be weakened)
     #[clap(long = "rigid-vars")]
     rigid_variables: bool,
+    /// Migrate the strongly connected components of top-level definitions one
+    /// at a time, in dependency order
+    #[clap(long)]
+    modular: bool,
+    /// Also migrate the whole program at once, and report the definitions
+    /// that lost precision with --modular
+    #[clap(long, requires = "modular")]
+    report_precision_loss: bool,
+    /// Migrate with several solver configurations in parallel, and report
+    /// the one that finishes first
+    #[clap(long)]
+    portfolio: bool,
     /// Do not type-check the final result of migration
     #[clap(long)]
     skip_type_check: bool,
@@ -128,19 +311,18 @@ pub struct Opts {
     /// Use ins and outs. Lots of features unsupported in this mode.
     #[clap(long)]
     ins_and_outs: bool,
-    /// When a file is provided, inferred types are corresponded to the
-    /// provided file's type annotations and whether they match (ignoring
-    /// annotations, coercions, and unannotated identifiers) is printed
-    #[clap(long)]
-    compare: Option<String>,
 }
 
 #[derive(Clone, Copy)]
 pub struct Options {
     optimizer: bool,
+    presolve: bool,
     context: bool,
     debug: bool,
     rigid_vars: bool,
+    modular: bool,
+    maxsat_engine: portfolio::MaxSatEngine,
+    seed: u32,
     annot: Annot,
 }
 
@@ -148,9 +330,13 @@ impl Default for Options {
     fn default() -> Self {
         Options {
             optimizer: true,
+            presolve: true,
             context: true,
             debug: false,
             rigid_vars: false,
+            modular: false,
+            maxsat_engine: portfolio::MaxSatEngine::Default,
+            seed: 0,
             annot: Annot::Hard,
         }
     }
@@ -161,27 +347,103 @@ fn main() -> Result<()> {
     match top_level.sub_command {
         SubCommand::Migrate(opts) => migrate_main(opts),
         SubCommand::Eval(opts) => eval_main(opts),
-        SubCommand::Benchmark(opts) => {
-            benchmark::benchmark_main(&opts.input, opts.ignore.as_slice())
-        }
+        SubCommand::Benchmark(opts) => benchmark::benchmark_main(
+            &opts.input,
+            opts.ignore.as_slice(),
+            opts.expect.as_deref(),
+            opts.jobs,
+            if opts.no_cache {
+                None
+            } else {
+                Some(opts.cache.as_str())
+            },
+        ),
         SubCommand::LatexBenchmarks(opts) => benchmark::details_latex(&opts.input),
         SubCommand::LatexBenchmarkSummary(opts) => benchmark::summarize_latex(&opts.input),
         SubCommand::LatexConciseSummary(opts) => benchmark::summarize_latex_concise(&opts.input),
+        SubCommand::Report(opts) => benchmark::report_main(&opts.input, opts.format),
+        SubCommand::TestSuite(opts) => {
+            test_suite::test_suite_main(&opts.input, &opts.filter, opts.summary.as_deref())
+        }
+        SubCommand::Fuzz(opts) => {
+            let features = fuzz::Features {
+                bools: !opts.no_bools,
+                ifs: !opts.no_ifs,
+                lets: !opts.no_lets,
+                annotations: !opts.no_annotations,
+            };
+            fuzz::fuzz_main(opts.seed, opts.count, opts.size, features);
+            Ok(())
+        }
+        SubCommand::Reduce(opts) => {
+            let config = &opts.opts;
+            let session = session::Session::new(config.options());
+            let language = config.language();
+            let env = config.env(&language);
+            let parsed = parse_program(&session, &language, &config.read_input()?);
+            reduce::reduce_main(&session, &env, opts.predicate, parsed)
+                .map_err(|e| Error::new(ErrorKind::Other, e))
+        }
+        SubCommand::DiffEngines(opts) => {
+            diff_engines::diff_engines_main(&opts.inputs, opts.random, opts.seed, opts.size)
+        }
     }
 }
 
 fn eval_main(opts: EvalOpts) -> Result<()> {
-    let src_txt = std::fs::read_to_string(opts.input)?;
-    let mut src_ast = parser::parse(&src_txt).expect("parse error");
-    insert_coercions::insert_coercions(&mut src_ast).unwrap();
+    let language = opts.opts.language();
+    let source = opts.opts.read_input()?;
+    let input = match &opts.stdin {
+        Some(file) => std::fs::read_to_string(file)?,
+        None => String::new(),
+    };
+    let (result, output) = eval_program(&opts, &language, &source, &input)?;
+    print!("{}", output);
+    match result {
+        Ok(answer) => match language {
+            Parser::Empty => println!("{}", answer),
+            Parser::Grift => println!("{}", answer.to_grift()),
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(eval_exit_code(&err));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the program, after migrating it with '--migrate'. Without it, the
+/// annotations of the program are kept, and unannotated positions are any.
+fn eval_program(
+    opts: &EvalOpts,
+    language: &Parser,
+    source: &str,
+    input: &str,
+) -> Result<(std::result::Result<eval::Answer, eval::Error>, String)> {
+    let config = &opts.opts;
+    let mut src_ast = if opts.migrate {
+        let session = session::Session::new(config.options());
+        let env = config.env(language);
+        let parsed = config.parse_input(&session, language, source);
+        migrate(config, &session, &env, parsed)?
+    } else {
+        parse_dynamic(language, source)
+    };
+    insert_coercions::insert_coercions(&mut src_ast)
+        .map_err(|e| Error::new(ErrorKind::Other, e))?;
     if opts.show_inserted_coercions {
         println!("With coercions:\n{}", &src_ast);
     }
-    match eval::eval(src_ast) {
-        Ok(_) => println!("OK"),
-        Err(s) => println!("{}", s),
-    };
-    Ok(())
+    let (result, _, output) = eval::eval_io(src_ast, u64::MAX, input);
+    Ok((result, output))
+}
+
+fn eval_exit_code(err: &eval::Error) -> i32 {
+    if err.is_coercion_failure() {
+        1
+    } else {
+        2
+    }
 }
 
 fn language_or_override<'a>(language: &'a Parser, an_override: &'a Parser) -> &'a Parser {
@@ -191,82 +453,286 @@ fn language_or_override<'a>(language: &'a Parser, an_override: &'a Parser) -> &'
     }
 }
 
-fn migrate_main(config: Opts) -> Result<()> {
-    let options = Options {
-        optimizer: !config.disable_optimizer,
-        context: !config.unsafe_mode,
-        debug: config.debug,
-        rigid_vars: config.rigid_variables,
-        annot: config.annot,
+fn watch_main(
+    config: &Opts,
+    print_program: bool,
+    language: &Parser,
+    env: &im_rc::HashMap<String, syntax::Typ>,
+    session: &session::Session,
+) -> Result<()> {
+    let cfg = z3::Config::new();
+    let cxt = z3::Context::new(&cfg);
+    let typ = z3_state::Z3State::typ(&cxt);
+    let mut inc = incremental::Incremental::new(&cxt, &typ, session, env);
+    let mut last_modified = None;
+    loop {
+        match read_if_modified(&config.input, &mut last_modified) {
+            Ok(Some(source)) => {
+                let printed = migrate_changed(config, language, env, session, &mut inc, &source)
+                    .and_then(|inferred| print_migrated(language, print_program, &inferred));
+                if let Err(e) = printed {
+                    println!("{}", e);
+                }
+            }
+            Ok(None) => (),
+            // Editors that save atomically remove the file for a moment.
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+}
+
+/// The contents of the file, if it changed since `last_modified`
+fn read_if_modified(
+    file: &str,
+    last_modified: &mut Option<std::time::SystemTime>,
+) -> Result<Option<String>> {
+    let modified = std::fs::metadata(file)?.modified()?;
+    if *last_modified == Some(modified) {
+        return Ok(None);
+    }
+    let source = std::fs::read_to_string(file)?;
+    *last_modified = Some(modified);
+    Ok(Some(source))
+}
+
+/// Migrates the new version of a watched program. Only the default engine
+/// can migrate incrementally, so the others migrate the program from scratch,
+/// exactly like a one-shot 'migrate'.
+fn migrate_changed(
+    config: &Opts,
+    language: &Parser,
+    env: &im_rc::HashMap<String, syntax::Typ>,
+    session: &session::Session,
+    inc: &mut incremental::Incremental,
+    source: &str,
+) -> std::result::Result<syntax::Exp, String> {
+    let mut parsed = match language {
+        Parser::Empty => parser::parse(session, source)?,
+        // The Grift parser panics on syntax errors.
+        Parser::Grift => test_suite::guard(|| Ok(grift::parse(session, source)))?,
+    };
+    if session.options.annot == Annot::Ignore {
+        parsed.fresh_types(session);
+    }
+    if config.ins_and_outs || config.portfolio || config.modular {
+        // The engines panic when migration fails.
+        return test_suite::guard(|| {
+            migrate(config, session, env, parsed).map_err(|e| e.to_string())
+        });
+    }
+    let (bindings, body) = match parsed {
+        syntax::Exp::LetRec(bindings, body) => (bindings, *body),
+        e => (Vec::new(), e),
     };
+    let generated = inc.update(bindings, body);
+    if session.options.debug {
+        eprintln!("Generated constraints for {} groups", generated);
+    }
+    let inferred = inc.solve()?;
+    check_migrated(config, env, inferred).map_err(|e| e.to_string())
+}
+
+impl Opts {
+    fn options(&self) -> Options {
+        Options {
+            optimizer: !self.disable_optimizer,
+            presolve: !self.disable_presolve,
+            context: !self.unsafe_mode,
+            debug: self.debug,
+            rigid_vars: self.rigid_variables,
+            modular: self.modular,
+            maxsat_engine: portfolio::MaxSatEngine::Default,
+            seed: 0,
+            annot: self.annot,
+        }
+    }
 
-    let language = match config.parser {
-        Parser::Grift => Parser::Grift,
-        Parser::Empty => match &config.input[..] {
-            "-" => Parser::Empty,
-            _ => match Path::new(&config.input).extension() {
-                Some(ext) => match ext.to_str().expect("non utf-8") {
-                    "grift" => Parser::Grift,
-                    _ => Parser::Empty,
+    /// The language of the input file, which '-p' overrides
+    fn language(&self) -> Parser {
+        match self.parser {
+            Parser::Grift => Parser::Grift,
+            Parser::Empty => match &self.input[..] {
+                "-" => Parser::Empty,
+                _ => match Path::new(&self.input).extension() {
+                    Some(ext) => match ext.to_str().expect("non utf-8") {
+                        "grift" => Parser::Grift,
+                        _ => Parser::Empty,
+                    },
+                    None => Parser::Empty,
                 },
-                None => Parser::Empty,
             },
-        },
-    };
+        }
+    }
 
-    let env = match language_or_override(&language, &config.env) {
-        Parser::Grift => grift::env(),
-        _ => Default::default(),
-    };
-    let source = match config.input.as_str() {
-        "-" => {
-            let mut out = String::new();
-            stdin().read_to_string(&mut out)?;
-            out
+    fn env(&self, language: &Parser) -> im_rc::HashMap<String, syntax::Typ> {
+        match language_or_override(language, &self.env) {
+            Parser::Grift => grift::env(),
+            _ => Default::default(),
         }
-        file => std::fs::read_to_string(file)?,
-    };
+    }
 
-    let mut parsed = match language {
-        Parser::Empty => parser::parse(&source).unwrap(),
-        Parser::Grift => grift::parse(&source),
-    };
+    fn read_input(&self) -> Result<String> {
+        match self.input.as_str() {
+            "-" => {
+                let mut out = String::new();
+                stdin().read_to_string(&mut out)?;
+                Ok(out)
+            }
+            file => std::fs::read_to_string(file),
+        }
+    }
+
+    /// Parses the input, and replaces its annotations with fresh metavariables
+    /// if annotations are ignored
+    fn parse_input(
+        &self,
+        session: &session::Session,
+        language: &Parser,
+        source: &str,
+    ) -> syntax::Exp {
+        let mut parsed = parse_program(session, language, source);
+        if session.options.annot == Annot::Ignore {
+            parsed.fresh_types(session);
+        }
+        parsed
+    }
+}
 
-    if options.annot == Annot::Ignore {
-        parsed.fresh_types();
+fn parse_program(session: &session::Session, language: &Parser, source: &str) -> syntax::Exp {
+    match language {
+        Parser::Empty => parser::parse(session, source).unwrap(),
+        Parser::Grift => grift::parse(session, source),
     }
+}
 
+/// Parses a program to run as is, so unannotated positions have type any
+fn parse_dynamic(language: &Parser, source: &str) -> syntax::Exp {
+    let mut exp = parse_program(&session::Session::default(), language, source);
+    exp.dynamic_metavars();
+    exp
+}
+
+/// Migrates a parsed program, and type-checks the result unless
+/// '--skip-type-check' is set.
+fn migrate(
+    config: &Opts,
+    session: &session::Session,
+    env: &im_rc::HashMap<String, syntax::Typ>,
+    parsed: syntax::Exp,
+) -> Result<syntax::Exp> {
+    let options = session.options;
     if options.debug {
         eprintln!("Parsed program:");
         eprintln!("{}", parsed);
     }
     let inferred = if config.ins_and_outs {
-        parsed.fresh_types();
-        ins_and_outs::typeinf_portable(parsed)
+        let mut parsed = parsed;
+        parsed.fresh_types(session);
+        ins_and_outs::typeinf_portable(session, parsed)
+    } else if config.portfolio {
+        portfolio::typeinf_portfolio(session, parsed, env).unwrap()
+    } else if config.report_precision_loss {
+        let global = session.with_options(Options {
+            modular: false,
+            ..options
+        });
+        let global = cgen::typeinf_options(&global, parsed.clone(), env).unwrap();
+        let inferred = cgen::typeinf_options(session, parsed, env).unwrap();
+        modular::report_precision_loss(&inferred, &global);
+        inferred
     } else {
-        cgen::typeinf_options(parsed, &env, options).unwrap()
+        cgen::typeinf_options(session, parsed, env).unwrap()
     };
+    check_migrated(config, env, inferred)
+}
 
+/// Type-checks the result of migration, unless '--skip-type-check' is set.
+fn check_migrated(
+    config: &Opts,
+    env: &im_rc::HashMap<String, syntax::Typ>,
+    inferred: syntax::Exp,
+) -> Result<syntax::Exp> {
     if !config.skip_type_check {
-        let typ = type_check::tcheck(&env, &inferred)
+        let typ = type_check::tcheck(env, &inferred)
             .map_err(|e| Error::new(ErrorKind::Other, format!("{}", e)))?;
-        if options.debug {
+        if config.debug {
             eprintln!("Inferred type:");
             eprintln!("{}", typ);
         }
     }
+    Ok(inferred)
+}
 
-    match config.compare {
-        None => {
-            match language {
-                Parser::Empty => println!("{}", &inferred),
-                Parser::Grift => inferred.print_id_types(),
-            }
-            Ok(())
+fn print_migrated(
+    language: &Parser,
+    print_program: bool,
+    inferred: &syntax::Exp,
+) -> std::result::Result<(), String> {
+    match language {
+        Parser::Empty => println!("{}", inferred),
+        Parser::Grift if print_program => print!("{}", grift::print(inferred)?),
+        Parser::Grift => inferred.print_id_types(),
+    }
+    Ok(())
+}
+
+fn migrate_main(opts: MigrateOpts) -> Result<()> {
+    let config = &opts.opts;
+    let session = session::Session::new(config.options());
+    let language = config.language();
+    let env = config.env(&language);
+    if opts.watch {
+        return watch_main(config, opts.print_program, &language, &env, &session);
+    }
+    let source = config.read_input()?;
+    let parsed = config.parse_input(&session, &language, &source);
+    let inferred = migrate(config, &session, &env, parsed)?;
+
+    if opts.validate {
+        let original = parse_dynamic(&language, &source);
+        let context = match &opts.context {
+            Some(file) => Some(parse_dynamic(&language, &std::fs::read_to_string(file)?)),
+            None => None,
+        };
+        validate::validate(&original, &inferred, context.as_ref())
+            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+    }
+
+    if opts.stats {
+        let original = parse_dynamic(&language, &source);
+        eprintln!(
+            "Precision before migration: {}",
+            precision::exp_score(&original)
+        );
+        eprintln!(
+            "Precision after migration: {}",
+            precision::exp_score(&inferred)
+        );
+    }
+
+    if opts.find_context {
+        let original = parse_dynamic(&language, &source);
+        let t_original =
+            type_check::tcheck(&env, &original).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let t_migrated =
+            type_check::tcheck(&env, &inferred).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        match contexts::find_context(&original, &inferred, &t_original, &t_migrated) {
+            Some(context) => eprintln!(
+                "The migrated program fails in this context, but the original does not:\n{}",
+                context
+            ),
+            None => eprintln!("No generated context distinguishes the migrated program"),
         }
+    }
+
+    match opts.compare {
+        None => print_migrated(&language, opts.print_program, &inferred)
+            .map_err(|e| Error::new(ErrorKind::Other, e)),
         Some(f) => {
             let compare_to_str = std::fs::read_to_string(f)?;
-            let compare_to = grift::parse(&compare_to_str);
+            let compare_to = grift::parse(&session, &compare_to_str);
             match inferred.matches_roughly(&compare_to) {
                 Ok(()) => {
                     println!("MATCHES");
@@ -285,6 +751,7 @@ fn migrate_main(config: Opts) -> Result<()> {
 mod tests_631 {
     use super::cgen::typeinf_options;
     use super::parser::parse;
+    use super::session::Session;
     use super::syntax::{Coerce, Exp, Typ};
     use super::type_check::type_check;
     use super::Options;
@@ -298,11 +765,11 @@ mod tests_631 {
     }
     fn coerce_contains_coercions(c: Coerce) -> (bool, bool) {
         match c {
-            Coerce::Doomed => (false, true),
+            Coerce::Doomed(_) => (false, true),
             Coerce::Id => (false, false),
             Coerce::Seq(a, b) => coerce_contains_coercions(*a).or(coerce_contains_coercions(*b)),
             Coerce::Tag(_) => (true, false),
-            Coerce::Untag(_) => (false, true),
+            Coerce::Untag(..) => (false, true),
             Coerce::Wrap(..) => panic!("wrap shouldn't happen in TypeWhich"),
         }
     }
@@ -310,7 +777,7 @@ mod tests_631 {
     pub fn contains_coercions(e: Exp) -> (bool, bool) {
         match e {
             Exp::PrimCoerce(c, e) => contains_coercions(*e).or(coerce_contains_coercions(c)),
-            Exp::Coerce(t1, t2, e) => {
+            Exp::Coerce(t1, t2, e, _) => {
                 let cts = contains_coercions(*e);
                 if t1 == t2 {
                     // this probably shouldn't happen after proper annotation
@@ -325,7 +792,7 @@ mod tests_631 {
             Exp::Lit(..) | Exp::Var(..) | Exp::Empty(..) => (false, false),
             Exp::Fun(_, _, e)
             | Exp::Fix(_, _, e)
-            | Exp::Ann(e, _)
+            | Exp::Ann(e, ..)
             | Exp::Fst(e)
             | Exp::Snd(e)
             | Exp::Head(e)
@@ -360,36 +827,37 @@ mod tests_631 {
         }
     }
     pub fn succeeds(program: &str) -> Typ {
-        exp_succeeds(parse(program).unwrap())
+        exp_succeeds(program)
     }
     pub fn no_from_any(program: &str) {
-        let orig = parse(program).unwrap();
-        let (_, e) = compile_verbose(orig);
+        let (_, e) = compile_verbose(program);
         let coercions = contains_coercions(e);
         assert!(!coercions.1);
     }
     pub fn coerces(program: &str) -> Typ {
-        exp_coerces(parse(program).unwrap())
+        exp_coerces(program)
     }
-    fn compile_verbose(mut orig: Exp) -> (Typ, Exp) {
-        orig.fresh_types();
-        println!("\nOriginal program:\n{}", &orig);
+    fn compile_verbose(program: &str) -> (Typ, Exp) {
         let mut options = Options::default();
         options.debug = true;
-        let e = typeinf_options(orig, &Default::default(), options).unwrap();
+        let session = Session::new(options);
+        let mut orig = parse(&session, program).unwrap();
+        orig.fresh_types(&session);
+        println!("\nOriginal program:\n{}", &orig);
+        let e = typeinf_options(&session, orig, &Default::default()).unwrap();
         println!("\nAfter type inference:\n{}", e);
         let t = type_check(&e).expect("failed to typecheck");
         println!("\nProgram type:\n{}", t);
         (t, e)
     }
-    pub fn exp_succeeds(orig: Exp) -> Typ {
-        let (t, e) = compile_verbose(orig);
+    pub fn exp_succeeds(program: &str) -> Typ {
+        let (t, e) = compile_verbose(program);
         let coercions = contains_coercions(e);
         assert!(!coercions.0 && !coercions.1);
         t
     }
-    pub fn exp_coerces(orig: Exp) -> Typ {
-        let (t, e) = compile_verbose(orig);
+    pub fn exp_coerces(program: &str) -> Typ {
+        let (t, e) = compile_verbose(program);
         let coercions = contains_coercions(e);
         assert!(coercions.0 || coercions.1);
         t
@@ -547,20 +1015,25 @@ mod tests_631 {
 
 #[cfg(test)]
 mod tests_migeed_and_parsberg {
-    use super::cgen::typeinf;
+    use super::cgen::typeinf_options;
     use super::parser::parse;
+    use super::session::Session;
     use super::tests_631::coerces;
     use super::type_check::type_check;
 
     // TODO(arjun): _maximal in the name is not accurate. Alternative name:
     // assert_ti_ok
     fn assert_maximal(program: &str, annotated: &str) {
-        let mut orig = parse(program).unwrap();
-        orig.fresh_types();
+        let session = Session::default();
+        let mut orig = parse(&session, program).unwrap();
+        orig.fresh_types(&session);
         println!("\nOriginal program:\n{}", &orig);
-        let e = typeinf(orig).expect("type inference failed on the original program");
+        let e = typeinf_options(&session, orig, &Default::default())
+            .expect("type inference failed on the original program");
         println!("\nAfter type inference:\n{}", e);
-        let correct = typeinf(parse(annotated).unwrap())
+        let session = Session::default();
+        let annotated = parse(&session, annotated).unwrap();
+        let correct = typeinf_options(&session, annotated, &Default::default())
             .expect("type inference failed on the expected program");
         println!(
             "\nProgram type:\n{}",
@@ -722,3 +1195,89 @@ mod tests_misc {
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{eval_exit_code, eval_program, EvalOpts, Parser, SubCommand, TopLevel};
+    use super::{incremental, migrate, migrate_changed, read_if_modified, session, z3_state};
+    use clap::Clap;
+
+    fn eval_opts(args: &[&str]) -> EvalOpts {
+        let args = ["typeinf-playground", "eval"].iter().chain(args);
+        match TopLevel::try_parse_from(args).unwrap().sub_command {
+            SubCommand::Eval(opts) => opts,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn eval_keeps_annotations() {
+        let opts = eval_opts(&[]);
+        let source = "(fun x:int. x) true";
+        let (result, _) = eval_program(&opts, &Parser::Empty, source, "").unwrap();
+        match result {
+            Err(err) => assert_eq!(eval_exit_code(&err), 1),
+            Ok(answer) => panic!("expected a coercion failure, got {}", answer),
+        }
+    }
+
+    #[test]
+    fn eval_rejects_migrate_only_flags() {
+        assert!(TopLevel::try_parse_from(["typeinf-playground", "eval", "--watch"]).is_err());
+        assert!(TopLevel::try_parse_from(["typeinf-playground", "migrate", "--watch"]).is_ok());
+    }
+
+    #[test]
+    fn eval_requires_migrate_for_migration_flags() {
+        for flag in &["--no-optimize", "--portfolio", "--modular", "--precise"] {
+            let eval = |args: &[&str]| {
+                let flags = ["typeinf-playground", "eval", flag];
+                TopLevel::try_parse_from(flags.iter().chain(args)).is_ok()
+            };
+            assert!(!eval(&[]), "eval accepted {} without --migrate", flag);
+            assert!(eval(&["--migrate"]));
+        }
+        assert!(TopLevel::try_parse_from(["typeinf-playground", "migrate", "--portfolio"]).is_ok());
+    }
+
+    #[test]
+    fn watch_migrates_like_migrate() {
+        let source = "let rec f = fun x. x + 1 in let rec g = fun y. f y in g 5";
+        for flags in &[&[][..], &["--modular"], &["--portfolio"]] {
+            let args = ["typeinf-playground", "migrate"].iter().chain(flags.iter());
+            let opts = match TopLevel::try_parse_from(args).unwrap().sub_command {
+                SubCommand::Migrate(opts) => opts.opts,
+                _ => unreachable!(),
+            };
+            let session = session::Session::new(opts.options());
+            let env = Default::default();
+            let parsed = opts.parse_input(&session, &Parser::Empty, source);
+            let expected = migrate(&opts, &session, &env, parsed).unwrap();
+
+            let session = session::Session::new(opts.options());
+            let cfg = z3::Config::new();
+            let cxt = z3::Context::new(&cfg);
+            let typ = z3_state::Z3State::typ(&cxt);
+            let mut inc = incremental::Incremental::new(&cxt, &typ, &session, &env);
+            let watched = migrate_changed(&opts, &Parser::Empty, &env, &session, &mut inc, source);
+            assert_eq!(watched.unwrap().to_string(), expected.to_string());
+        }
+    }
+
+    #[test]
+    fn watch_waits_for_missing_files() {
+        let file = std::env::temp_dir().join(format!("typewhich-watch-{}", std::process::id()));
+        let file = file.to_str().unwrap();
+        let mut last_modified = None;
+        let err = read_if_modified(file, &mut last_modified).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(last_modified, None);
+        std::fs::write(file, "5").unwrap();
+        assert_eq!(
+            read_if_modified(file, &mut last_modified).unwrap().unwrap(),
+            "5"
+        );
+        assert_eq!(read_if_modified(file, &mut last_modified).unwrap(), None);
+        std::fs::remove_file(file).unwrap();
+    }
+}
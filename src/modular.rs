@@ -0,0 +1,302 @@
+//! Modular migration of a top-level `letrec`.
+//!
+//! Instead of generating one constraint problem for the whole program, we
+//! split the bindings of the `letrec` into strongly connected components of
+//! the reference graph, and migrate the components in dependency order. The
+//! solved types of a component are added to the environment of the
+//! components that follow it. Each component is migrated on its own, so that
+//! solving time grows with the size of the largest component instead of the
+//! size of the program. The price is precision: a component cannot see how
+//! later components use it, so its types must be safe in any context.
+use super::cgen::{typeinf_bindings, typeinf_options};
+use super::precision::typ_lt;
+use super::session::Session;
+use super::syntax::*;
+use im_rc::{HashMap, HashSet};
+
+type Env = HashMap<String, Typ>;
+
+pub fn typeinf_modular(session: &Session, exp: Exp, env: &Env) -> Result<Exp, String> {
+    let (bindings, body) = match exp {
+        Exp::LetRec(bindings, body) => (bindings, body),
+        _ => panic!("typeinf_modular expects a letrec"),
+    };
+    let components = components(&bindings);
+    if session.options.debug {
+        eprintln!(
+            "Migrating {} bindings in {} components",
+            bindings.len(),
+            components.len()
+        );
+    }
+    let mut bindings: Vec<Option<(Id, Typ, Exp)>> = bindings.into_iter().map(Some).collect();
+    let mut env = env.clone();
+    for component in components {
+        let group: Vec<_> = component
+            .iter()
+            .map(|i| bindings[*i].take().unwrap())
+            .collect();
+        let names = group
+            .iter()
+            .map(|(x, _, _)| x.clone())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let group = typeinf_bindings(session, group, &env)
+            .map_err(|e| format!("{} (component {})", e, names))?;
+        for (i, (x, t, e)) in component.into_iter().zip(group) {
+            env.insert(x.clone(), t.clone());
+            bindings[i] = Some((x, t, e));
+        }
+    }
+    let body = typeinf_options(session, *body, &env)?;
+    Ok(Exp::LetRec(
+        bindings.into_iter().map(Option::unwrap).collect(),
+        Box::new(body),
+    ))
+}
+
+/// Reports the bindings of a top-level `letrec` that have a less precise
+/// type after modular migration than after global migration.
+pub fn report_precision_loss(modular: &Exp, global: &Exp) {
+    let (modular, global) = match (modular, global) {
+        (Exp::LetRec(modular, _), Exp::LetRec(global, _)) => (modular, global),
+        _ => return,
+    };
+    let mut lost = 0;
+    for ((x, t_modular, _), (_, t_global, _)) in modular.iter().zip(global.iter()) {
+        if t_modular == t_global {
+            continue;
+        }
+        lost += 1;
+        if typ_lt(t_global, t_modular) {
+            eprintln!("{}: {} (global: {})", x, t_modular, t_global);
+        } else {
+            eprintln!("{}: {} (global: {}, incomparable)", x, t_modular, t_global);
+        }
+    }
+    eprintln!(
+        "{} of {} bindings lost precision with modular migration",
+        lost,
+        modular.len()
+    );
+}
+
+/// The strongly connected components of the bindings, as indices into
+/// `bindings`. Every component appears after the components it refers to.
+fn components(bindings: &[(Id, Typ, Exp)]) -> Vec<Vec<usize>> {
+    let index: std::collections::HashMap<&str, usize> = bindings
+        .iter()
+        .enumerate()
+        .map(|(i, (x, _, _))| (x.as_str(), i))
+        .collect();
+    let edges = bindings
+        .iter()
+        .map(|(_, _, e)| {
+            let mut refs = std::collections::HashSet::new();
+            free_vars(&HashSet::new(), e, &mut refs);
+            let mut succs: Vec<usize> = refs
+                .iter()
+                .filter_map(|x| index.get(x.as_str()).cloned())
+                .collect();
+            succs.sort();
+            succs
+        })
+        .collect();
+    let mut tarjan = Tarjan {
+        edges,
+        next_index: 0,
+        indices: vec![None; bindings.len()],
+        lowlinks: vec![0; bindings.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; bindings.len()],
+        components: Vec::new(),
+    };
+    for v in 0..bindings.len() {
+        if tarjan.indices[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.components
+}
+
+/// Tarjan's algorithm, which produces components in reverse topological
+/// order, i.e., dependencies first.
+struct Tarjan {
+    edges: Vec<Vec<usize>>,
+    next_index: usize,
+    indices: Vec<Option<usize>>,
+    lowlinks: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan {
+    fn visit(&mut self, v: usize) {
+        self.indices[v] = Some(self.next_index);
+        self.lowlinks[v] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        for w in self.edges[v].clone() {
+            match self.indices[w] {
+                None => {
+                    self.visit(w);
+                    self.lowlinks[v] = self.lowlinks[v].min(self.lowlinks[w]);
+                }
+                Some(w_index) if self.on_stack[w] => {
+                    self.lowlinks[v] = self.lowlinks[v].min(w_index);
+                }
+                Some(_) => (),
+            }
+        }
+        if Some(self.lowlinks[v]) == self.indices[v] {
+            let mut component = Vec::new();
+            loop {
+                let w = self.stack.pop().unwrap();
+                self.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            component.sort();
+            self.components.push(component);
+        }
+    }
+}
+
+/// Adds the free variables of `exp` that are not in `bound` to `out`.
+pub fn free_vars(bound: &HashSet<Id>, exp: &Exp, out: &mut std::collections::HashSet<Id>) {
+    match exp {
+        Exp::Var(x) => {
+            if !bound.contains(x) {
+                out.insert(x.clone());
+            }
+        }
+        Exp::Lit(..) | Exp::Empty(..) => (),
+        Exp::Fun(x, _, e) | Exp::Fix(x, _, e) => {
+            free_vars(&bound.update(x.clone()), e, out);
+        }
+        Exp::Let(x, e1, e2) => {
+            free_vars(bound, e1, out);
+            free_vars(&bound.update(x.clone()), e2, out);
+        }
+        Exp::LetRec(bindings, e) => {
+            let mut bound = bound.clone();
+            for (x, _, _) in bindings.iter() {
+                bound.insert(x.clone());
+            }
+            for (_, _, ei) in bindings.iter() {
+                free_vars(&bound, ei, out);
+            }
+            free_vars(&bound, e, out);
+        }
+        Exp::Ann(e, ..)
+        | Exp::Coerce(_, _, e, _)
+        | Exp::PrimCoerce(_, e)
+        | Exp::UnaryOp(_, e)
+        | Exp::Fst(e)
+        | Exp::Snd(e)
+        | Exp::IsEmpty(e)
+        | Exp::Head(e)
+        | Exp::Tail(e)
+        | Exp::Box(e)
+        | Exp::Unbox(e)
+        | Exp::VectorLen(e)
+        | Exp::IsBool(e)
+        | Exp::IsInt(e)
+        | Exp::IsString(e)
+        | Exp::IsList(e)
+        | Exp::IsFun(e) => free_vars(bound, e, out),
+        Exp::App(e1, e2)
+        | Exp::BinaryOp(_, e1, e2)
+        | Exp::AddOverload(e1, e2)
+        | Exp::Pair(e1, e2)
+        | Exp::Cons(e1, e2)
+        | Exp::BoxSet(e1, e2)
+        | Exp::Vector(e1, e2)
+        | Exp::VectorRef(e1, e2) => {
+            free_vars(bound, e1, out);
+            free_vars(bound, e2, out);
+        }
+        Exp::If(e1, e2, e3) | Exp::VectorSet(e1, e2, e3) => {
+            free_vars(bound, e1, out);
+            free_vars(bound, e2, out);
+            free_vars(bound, e3, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::components;
+    use crate::cgen::typeinf_options;
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::syntax::Exp;
+    use crate::type_check::type_check;
+    use crate::Options;
+
+    fn bindings(prog: &str) -> Vec<Vec<usize>> {
+        match parse(&Session::default(), prog).unwrap() {
+            Exp::LetRec(bindings, _) => components(&bindings),
+            _ => panic!("expected a letrec"),
+        }
+    }
+
+    #[test]
+    fn dependency_order() {
+        assert_eq!(
+            bindings(
+                "let rec f = fun x . g x
+                 and g = fun x . h x
+                 and h = fun x . x + 1
+                 in f 10"
+            ),
+            vec![vec![2], vec![1], vec![0]]
+        );
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        assert_eq!(
+            bindings(
+                "let rec even = fun n . if n = 0 then true else odd (n + 1)
+                 and odd = fun n . if n = 0 then false else even (n + 1)
+                 and main = fun n . even n
+                 in main 10"
+            ),
+            vec![vec![0, 1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(
+            bindings(
+                "let rec f = fun g . g 10
+                 and g = fun x . x
+                 in f g"
+            ),
+            vec![vec![0], vec![1]]
+        );
+    }
+
+    #[test]
+    fn modular_type_checks() {
+        let session = Session::new(Options {
+            modular: true,
+            ..Options::default()
+        });
+        let exp = parse(
+            &session,
+            "let rec inc = fun x . x + 1
+             and twice = fun f . fun x . f (f x)
+             in twice inc 10",
+        )
+        .unwrap();
+        let e = typeinf_options(&session, exp, &Default::default()).unwrap();
+        type_check(&e).unwrap();
+    }
+}
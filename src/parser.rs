@@ -1,50 +1,46 @@
-use super::syntax::{Exp, Typ};
+use super::session::Session;
+use super::syntax::Exp;
 use std::cell::RefCell;
-use std::collections::hash_set::HashSet;
 
 lrlex::lrlex_mod!("lexer.l"); // effectively mod `lexer_l`
 lrpar::lrpar_mod!("parser.y"); // effectively mod `parser_y`
 
-thread_local!(static NEXT_METAVAR: RefCell<u32> = RefCell::new(0));
-thread_local!(static PARSER_WARNINGS: RefCell<HashSet<String>> = RefCell::new(HashSet::new()));
+thread_local!(static SESSION: RefCell<Option<Session>> = RefCell::new(None));
 
-pub fn next_metavar() -> Typ {
-    Typ::Metavar(inc_metavar())
-}
-
-pub fn inc_metavar() -> u32 {
-    NEXT_METAVAR.with(|mv| {
-        let mut mv = mv.borrow_mut();
-        let i = *mv;
-        *mv = i + 1;
-        i
+/// The session of the parse in progress. lrpar cannot pass state to grammar
+/// actions, so `with_session` makes the session available to them while
+/// parsing.
+pub fn session() -> Session {
+    SESSION.with(|s| {
+        s.borrow()
+            .clone()
+            .expect("session() called outside of a parse")
     })
 }
 
-pub fn parser_warning(msg: impl AsRef<str>) {
-    let msg = msg.as_ref().to_string();
-    PARSER_WARNINGS.with(|s| {
-        let mut s = s.borrow_mut();
-        s.insert(msg);
-    });
+/// Restores the session of the enclosing parse when dropped, even if the
+/// parse panics.
+struct RestoreSession(Option<Session>);
+
+impl Drop for RestoreSession {
+    fn drop(&mut self) {
+        SESSION.with(|s| s.replace(self.0.take()));
+    }
 }
 
-pub fn show_warnings() {
-    PARSER_WARNINGS.with(|ws| {
-        for w in ws.replace(HashSet::new()).into_iter() {
-            eprintln!("Warning: {}", w);
-        }
-    });
+pub fn with_session<T>(session: &Session, f: impl FnOnce() -> T) -> T {
+    let _restore = RestoreSession(SESSION.with(|s| s.replace(Some(session.clone()))));
+    f()
 }
 
 /// Parses the input string, producing an `Exp` where very type annotation
 /// is set to `Typ::Metavar`. Each `Typ::Metavar` is numbered sequentially,
-/// starting with `0`.
-pub fn parse(input: impl AsRef<str>) -> Result<Exp, String> {
+/// starting with the next metavariable of the session.
+pub fn parse(session: &Session, input: impl AsRef<str>) -> Result<Exp, String> {
     let input = input.as_ref();
     let lexerdef = lexer_l::lexerdef();
     let lexer = lexerdef.lexer(input);
-    let (res, errs) = parser_y::parse(&lexer);
+    let (res, errs) = with_session(session, || parser_y::parse(&lexer));
     let mut errors = String::new();
     let did_err = errs.is_empty() == false;
     for err in errs.into_iter() {
@@ -62,3 +58,22 @@ pub fn parse(input: impl AsRef<str>) -> Result<Exp, String> {
         Some(Err(_)) | None => Err(errors),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{session, with_session, Session, SESSION};
+    use crate::Options;
+
+    #[test]
+    fn panics_restore_the_session() {
+        let outer = Session::starting_at(100, Options::default());
+        with_session(&outer, || {
+            let result = std::panic::catch_unwind(|| {
+                with_session(&Session::default(), || panic!("parse failed"))
+            });
+            assert!(result.is_err());
+            assert_eq!(session().metavar_counter(), 100);
+        });
+        assert!(SESSION.with(|s| s.borrow().is_none()));
+    }
+}
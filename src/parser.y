@@ -48,7 +48,7 @@ atom -> R<Exp> :
     '(' exp ')'     { $2 }
   | lit             { Ok(Exp::Lit($1?)) }
   | id              { Ok(Exp::Var($1?)) }
-  | 'empty'         { Ok(Exp::Empty(next_metavar())) }
+  | 'empty'         { Ok(Exp::Empty(session().next_metavar())) }
 //  | 'empty' ':' typ { Exp::Empty($3) }
   ;
 
@@ -62,9 +62,9 @@ funExp -> R<Exp> :
   | 'is_string' atom { Ok(Exp::IsString(Box::new($2?))) }
   | 'is_list' atom { Ok(Exp::IsList(Box::new($2?))) }
   | 'is_fun' atom { Ok(Exp::IsFun(Box::new($2?))) }
-  | 'to_any' atom { Ok(Exp::Coerce(next_metavar(), Typ::Any, Box::new($2?))) }
-  | 'from_any' ':' typ atom { Ok(Exp::Coerce(Typ::Any, $3?, Box::new($4?))) }
-  | 'from_any' atom { Ok(Exp::Coerce(Typ::Any, next_metavar(), Box::new($2?))) }
+  | 'to_any' atom { Ok(Exp::Coerce(session().next_metavar(), Typ::Any, Box::new($2?), None)) }
+  | 'from_any' ':' typ atom { Ok(Exp::Coerce(Typ::Any, $3?, Box::new($4?), None)) }
+  | 'from_any' atom { Ok(Exp::Coerce(Typ::Any, session().next_metavar(), Box::new($2?), None)) }
   | atom        { $1 }
   ;
 
@@ -89,7 +89,7 @@ pair -> R<Exp> :
 exp -> R<Exp> :
     'fun' id '.' exp { Ok(Exp::Fun($2?, Typ::Any, Box::new($4?))) }
   | 'fun' id ':' typ '.' exp { Ok(Exp::Fun($2?, $4?, Box::new($6?))) }
-  | 'fix' id '.' exp { Ok(Exp::Fix($2?, next_metavar(), Box::new($4?))) }
+  | 'fix' id '.' exp { Ok(Exp::Fix($2?, session().next_metavar(), Box::new($4?))) }
   | pair             { $1 }
   | 'if' exp 'then' exp 'else' exp {
         Ok(Exp::If(Box::new($2?), Box::new($4?), Box::new($6?)))
@@ -97,7 +97,7 @@ exp -> R<Exp> :
   | 'let' id '=' exp 'in' exp { Ok(Exp::Let($2?, Box::new($4?), Box::new($6?))) }
   | 'let' 'rec' bindings 'in' exp { Ok(Exp::LetRec($3?, Box::new($5?))) }
   | pair '::' exp { Ok(Exp::Cons(Box::new($1?), Box::new($3?))) }
-  | pair ':' typ  { Ok(Exp::Ann(Box::new($1?), $3?)) }
+  | pair ':' typ  { Ok(Exp::Ann(Box::new($1?), $3?, None)) }
   ;
 
 bindings -> R<Vec<(String, Typ, Exp)>> :
@@ -107,11 +107,11 @@ bindings -> R<Vec<(String, Typ, Exp)>> :
 
 binding -> R<(String, Typ, Exp)> :
     id ':' typ '=' exp    { Ok(($1?, $3?, $5?)) }
-  | id '=' exp            { Ok(($1?, next_metavar(), $3?)) }
+  | id '=' exp            { Ok(($1?, session().next_metavar(), $3?)) }
 ;
 %%
 
 use crate::syntax::{Exp, Lit, Typ, BinOp, UnOp};
-use crate::parser::next_metavar;
+use crate::parser::session;
 
 type R<T> = Result<T, ()>;
@@ -0,0 +1,209 @@
+//! Portfolio solving: migrates the same program with several solver
+//! configurations in parallel, and takes the first optimal answer.
+//!
+//! Every strategy runs in its own thread with its own Z3 context. Z3 finds
+//! some MaxSMT problems much faster with one engine than with another, and
+//! it is hard to predict which one in advance.
+use super::cgen::typeinf_options;
+use super::session::{Interrupt, Session};
+use super::syntax::{Exp, Typ};
+use super::Options;
+use im_rc::HashMap;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+type Env = HashMap<String, Typ>;
+
+/// The algorithm that Z3 uses to solve the soft constraints.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MaxSatEngine {
+    /// Whatever Z3 picks by default
+    Default,
+    /// Core-guided search ('maxres')
+    CoreGuided,
+    /// Linear search over the cost of solutions ('wmax')
+    Linear,
+}
+
+impl MaxSatEngine {
+    /// The value of Z3's `maxsat_engine` parameter
+    pub fn param(&self) -> Option<&'static str> {
+        match self {
+            MaxSatEngine::Default => None,
+            MaxSatEngine::CoreGuided => Some("maxres"),
+            MaxSatEngine::Linear => Some("wmax"),
+        }
+    }
+}
+
+struct Strategy {
+    name: &'static str,
+    engine: MaxSatEngine,
+    /// Shuffles the order of the soft constraints, when non-zero
+    seed: u32,
+    optimizer: bool,
+}
+
+const STRATEGIES: &[Strategy] = &[
+    Strategy {
+        name: "core-guided",
+        engine: MaxSatEngine::CoreGuided,
+        seed: 0,
+        optimizer: true,
+    },
+    Strategy {
+        name: "linear",
+        engine: MaxSatEngine::Linear,
+        seed: 0,
+        optimizer: true,
+    },
+    Strategy {
+        name: "core-guided (seed 1)",
+        engine: MaxSatEngine::CoreGuided,
+        seed: 1,
+        optimizer: true,
+    },
+    Strategy {
+        name: "core-guided (seed 2)",
+        engine: MaxSatEngine::CoreGuided,
+        seed: 2,
+        optimizer: true,
+    },
+    Strategy {
+        name: "no optimizer",
+        engine: MaxSatEngine::Default,
+        seed: 0,
+        optimizer: false,
+    },
+];
+
+/// Constraint generation recurses over the program, so workers need a stack
+/// as large as the main thread's.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Runs every strategy, and returns the answer of the first strategy that
+/// minimizes coercions. The answer without the optimizer is not optimal, so
+/// it is only used when every other strategy fails.
+pub fn typeinf_portfolio(session: &Session, exp: Exp, env: &Env) -> Result<Exp, String> {
+    let options = session.options;
+    let (tx, rx) = mpsc::channel();
+    // im_rc maps cannot be sent to other threads
+    let env: Vec<(String, Typ)> = env.clone().into_iter().collect();
+    let next_metavar = session.metavar_counter();
+    let interrupts: Vec<Interrupt> = STRATEGIES.iter().map(|_| Interrupt::default()).collect();
+    for (i, strategy) in STRATEGIES.iter().enumerate() {
+        let tx = tx.clone();
+        let interrupt = interrupts[i].clone();
+        let exp = exp.clone();
+        let env = env.clone();
+        let options = Options {
+            maxsat_engine: strategy.engine,
+            seed: strategy.seed,
+            optimizer: options.optimizer && strategy.optimizer,
+            ..options
+        };
+        std::thread::Builder::new()
+            .name(strategy.name.to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let mut session = Session::starting_at(next_metavar, options);
+                session.interrupt = interrupt;
+                let env = env.into_iter().collect();
+                let start = Instant::now();
+                let result = typeinf_options(&session, exp, &env);
+                // The receiver hangs up once it has a winner
+                let _ = tx.send((i, start.elapsed(), result));
+            })
+            .expect("spawning portfolio thread");
+    }
+    drop(tx);
+
+    let mut fallback: Option<(usize, Duration, Exp)> = None;
+    let mut errors = Vec::new();
+    for (i, elapsed, result) in rx {
+        let strategy = &STRATEGIES[i];
+        match result {
+            Ok(exp) if strategy.optimizer => {
+                eprintln!("Portfolio: {} won in {:.2?}", strategy.name, elapsed);
+                // The other strategies stop soon, and release their solvers
+                for interrupt in interrupts.iter() {
+                    interrupt.interrupt();
+                }
+                return Ok(exp);
+            }
+            Ok(exp) => {
+                if fallback.is_none() {
+                    fallback = Some((i, elapsed, exp));
+                }
+            }
+            Err(err) => {
+                if options.debug {
+                    eprintln!("Portfolio: {} failed: {}", strategy.name, err);
+                }
+                errors.push(err);
+            }
+        }
+    }
+    match fallback {
+        Some((i, elapsed, exp)) => {
+            eprintln!("Portfolio: {} won in {:.2?}", STRATEGIES[i].name, elapsed);
+            Ok(exp)
+        }
+        // Every strategy generates the same hard constraints, so they all
+        // fail the same way
+        None => Err(errors
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "every strategy panicked".to_string())),
+    }
+}
+
+/// Shuffles `items` with a small xorshift generator, so that the same seed
+/// always produces the same order.
+pub fn shuffle<T>(items: &mut [T], seed: u32) {
+    let mut state = seed;
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        items.swap(i, state as usize % (i + 1));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{shuffle, typeinf_portfolio};
+    use crate::cgen::typeinf_options;
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::type_check::type_check;
+    use crate::Options;
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut items: Vec<u32> = (0..20).collect();
+        shuffle(&mut items, 7);
+        assert_ne!(items, (0..20).collect::<Vec<_>>());
+        items.sort();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn portfolio_migrates() {
+        let session = Session::new(Options::default());
+        let exp = parse(&session, "(fun f. (fun y. f) (f 5)) (fun x. 10 + x)").unwrap();
+        let e = typeinf_portfolio(&session, exp, &Default::default()).unwrap();
+        type_check(&e).unwrap();
+    }
+
+    #[test]
+    fn interrupted_strategies_stop() {
+        let session = Session::new(Options::default());
+        let exp = parse(&session, "(fun x. x + 1) 2").unwrap();
+        session.interrupt.interrupt();
+        match typeinf_options(&session, exp, &Default::default()) {
+            Err(err) => assert_eq!(err, "interrupted"),
+            Ok(e) => panic!("expected an interruption, got {}", e),
+        }
+    }
+}
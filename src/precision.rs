@@ -3,13 +3,21 @@
 //!
 //! In addition to the usual precision check, these predicates:
 //!
-//! 1. Account for alpha-renaming, e.g., it considers `fun x . x` and `fun y . y` to be 
+//! 1. Account for alpha-renaming, e.g., it considers `fun x . x` and `fun y . y` to be
 //!    identical; and
 //!
 //! 2. Treat type metavariables as `any`, e.g., it treats `fun x : 'a . x` and `fun x : any . x` as
+//!    identical expressions; and
+//!
+//! 3. Ignore ascriptions and coercions that only appear in one of the expressions, since
+//!    migration inserts them, e.g., it treats `fun x : int . x` and `fun x : int . (x : any)` as
 //!    identical expressions.
+//!
+//! This module also measures how precise a program is, by counting the type constructors in the
+//! types of its binders that are not `any`.
 
-use super::syntax::{Exp, Typ, Id};
+use super::syntax::{Exp, Id, Typ};
+use serde::{Deserialize, Serialize};
 
 type Env = im_rc::HashMap<Id, Id>;
 
@@ -26,38 +34,235 @@ pub fn typ_lt(t1: &Typ, t2: &Typ) -> bool {
         (Char, Char) => true,
         (Arr(t11, t12), Arr(t21, t22)) => typ_lt(t11, t21) && typ_lt(t12, t22),
         (Pair(t11, t12), Pair(t21, t22)) => typ_lt(t11, t21) && typ_lt(t12, t22),
+        (List(t11), List(t21)) => typ_lt(t11, t21),
+        (Box(t11), Box(t21)) => typ_lt(t11, t21),
         (Vect(t11), Vect(t21)) => typ_lt(t11, t21),
         _ => false,
     }
 }
 
-
+/// `env` maps the variables bound in `e1` to the corresponding variables bound in `e2`
 fn exp_lt_rec(env: &Env, e1: &Exp, e2: &Exp) -> bool {
     use Exp::*;
     match (e1, e2) {
+        (Ann(e1, t1, _), Ann(e2, t2, _)) => typ_lt(t1, t2) && exp_lt_rec(env, e1, e2),
+        (Coerce(_, t1, e1, _), Coerce(_, t2, e2, _)) => typ_lt(t1, t2) && exp_lt_rec(env, e1, e2),
+        (PrimCoerce(k1, e1), PrimCoerce(k2, e2)) => k1 == k2 && exp_lt_rec(env, e1, e2),
+        (Ann(e1, ..), _) | (Coerce(_, _, e1, _), _) | (PrimCoerce(_, e1), _) => {
+            exp_lt_rec(env, e1, e2)
+        }
+        (_, Ann(e2, ..)) | (_, Coerce(_, _, e2, _)) | (_, PrimCoerce(_, e2)) => {
+            exp_lt_rec(env, e1, e2)
+        }
         (Lit(l1), Lit(l2)) => l1 == l2,
         (Var(x), Var(y1)) => match env.get(x) {
-            None => false,
-            Some(y2) => y1 == y2
+            None => x == y1, // free variables must be identical
+            Some(y2) => y1 == y2,
         },
-        (Fun(x, t1, e1), Fun(y, t2, e2)) => {
-            if typ_lt(t1, t2) == false {
-                false
+        (Empty(t1), Empty(t2)) => typ_lt(t1, t2),
+        (Fun(x, t1, e1), Fun(y, t2, e2)) | (Fix(x, t1, e1), Fix(y, t2, e2)) => {
+            let mut env = env.clone();
+            env.insert(x.clone(), y.clone());
+            typ_lt(t1, t2) && exp_lt_rec(&env, e1, e2)
+        }
+        (Let(x, e11, e12), Let(y, e21, e22)) => {
+            let mut env_body = env.clone();
+            env_body.insert(x.clone(), y.clone());
+            exp_lt_rec(env, e11, e21) && exp_lt_rec(&env_body, e12, e22)
+        }
+        (LetRec(bindings1, e1), LetRec(bindings2, e2)) => {
+            if bindings1.len() != bindings2.len() {
+                return false;
             }
-            else {
-                let mut env = env.clone();
+            let mut env = env.clone();
+            for ((x, _, _), (y, _, _)) in bindings1.iter().zip(bindings2.iter()) {
                 env.insert(x.clone(), y.clone());
-                exp_lt_rec(&env, e1, e2)
             }
+            bindings1
+                .iter()
+                .zip(bindings2.iter())
+                .all(|((_, t1, e1), (_, t2, e2))| typ_lt(t1, t2) && exp_lt_rec(&env, e1, e2))
+                && exp_lt_rec(&env, e1, e2)
+        }
+        (UnaryOp(op1, _), UnaryOp(op2, _)) if op1 != op2 => false,
+        (BinaryOp(op1, ..), BinaryOp(op2, ..)) if op1 != op2 => false,
+        // Every other form of expression binds no variables, so the expressions are related
+        // if they have the same form and their subexpressions are related.
+        _ => {
+            std::mem::discriminant(e1) == std::mem::discriminant(e2)
+                && e1
+                    .children()
+                    .into_iter()
+                    .zip(e2.children())
+                    .all(|(e1, e2)| exp_lt_rec(env, e1, e2))
         }
-        (App(e11, e12), App(e21, e22)) => exp_lt_rec(env, e11, e21) && exp_lt_rec(env, e12, e22),
-        (BinaryOp(op1, e11, e12), BinaryOp(op2, e21, e22)) => op1 == op2 && exp_lt_rec(env, e11, e21) && exp_lt_rec(env, e12, e22),
-        (If(e11, e12, e13), If(e21, e22, e23)) =>exp_lt_rec(env, e11, e21) && exp_lt_rec(env, e12, e22) && exp_lt_rec(env, e13, e23),
-        _ => false
     }
 }
 
 pub fn exp_lt(e1: &Exp, e2: &Exp) -> bool {
-  let env = Env::default();
-  exp_lt_rec(&env, e1, e2)
-}
\ No newline at end of file
+    let env = Env::default();
+    exp_lt_rec(&env, e1, e2)
+}
+
+/// The number of type constructors other than `any` in some types, out of the number of type
+/// constructors in them. Unlike counting the annotations that are `any`, this distinguishes
+/// `any -> any` from `any`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Score {
+    pub precise: usize,
+    pub total: usize,
+}
+
+impl Score {
+    /// The percentage of type constructors that are not `any`. A program without annotations is
+    /// fully precise.
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            100.0
+        } else {
+            100.0 * self.precise as f64 / self.total as f64
+        }
+    }
+}
+
+impl std::ops::Add for Score {
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score {
+        Score {
+            precise: self.precise + other.precise,
+            total: self.total + other.total,
+        }
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} ({:.1}%)",
+            self.precise,
+            self.total,
+            self.percent()
+        )
+    }
+}
+
+/// Metavariables count as `any` (see module-level note)
+pub fn typ_score(t: &Typ) -> Score {
+    let one = Score {
+        precise: 1,
+        total: 1,
+    };
+    match t {
+        Typ::Any | Typ::Metavar(_) => Score {
+            precise: 0,
+            total: 1,
+        },
+        Typ::Unit | Typ::Int | Typ::Float | Typ::Bool | Typ::Str | Typ::Char => one,
+        Typ::Arr(t1, t2) | Typ::Pair(t1, t2) => one + typ_score(t1) + typ_score(t2),
+        Typ::List(t) | Typ::Box(t) | Typ::Vect(t) => one + typ_score(t),
+    }
+}
+
+/// The score of the types of every binder in the expression. Migration ignores ascriptions, and
+/// the ascriptions in its result are coercions, so ascriptions do not count, and the scores of a
+/// program and the result of migrating it measure the same binders.
+pub fn exp_score(e: &Exp) -> Score {
+    let here = match e {
+        Exp::Fun(_, t, _) | Exp::Fix(_, t, _) => typ_score(t),
+        Exp::LetRec(bindings, _) => bindings
+            .iter()
+            .fold(Score::default(), |score, (_, t, _)| score + typ_score(t)),
+        _ => Score::default(),
+    };
+    e.children()
+        .into_iter()
+        .fold(here, |score, e| score + exp_score(e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{exp_lt, exp_score, typ_lt, Score};
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::syntax::Typ;
+
+    fn lt(e1: &str, e2: &str) -> bool {
+        let session = Session::default();
+        exp_lt(&parse(&session, e1).unwrap(), &parse(&session, e2).unwrap())
+    }
+
+    #[test]
+    fn structured_types() {
+        let list = |t| Typ::List(Box::new(t));
+        let boxed = |t| Typ::Box(Box::new(t));
+        assert!(typ_lt(&list(Typ::Int), &list(Typ::Any)));
+        assert!(!typ_lt(&list(Typ::Any), &list(Typ::Int)));
+        assert!(typ_lt(&boxed(Typ::Bool), &boxed(Typ::Bool)));
+        assert!(!typ_lt(&boxed(Typ::Bool), &list(Typ::Bool)));
+    }
+
+    #[test]
+    fn binders_are_alpha_renamed() {
+        assert!(lt("let x = 1 in x + 1", "let y = 1 in y + 1"));
+        assert!(!lt("let x = 1 in x + 1", "let y = 1 in 1 + y"));
+        assert!(lt(
+            "let rec f : int -> int = fun x : int . g x and g : int -> int = fun y : int . f y in f 0",
+            "let rec g : any = fun z : any . h z and h : any = fun y : any . g y in g 0"
+        ));
+        assert!(!lt(
+            "let rec f : any = fun x : any . f x in f 0",
+            "let rec f : int -> int = fun x : int . f x in f 0"
+        ));
+    }
+
+    #[test]
+    fn ascriptions_are_ignored() {
+        assert!(lt("fun x : int . x + 1", "fun x : int . (x : any) + 1"));
+        assert!(lt("(1 : int)", "(1 : any)"));
+        assert!(!lt("(1 : any)", "(1 : int)"));
+    }
+
+    #[test]
+    fn scores() {
+        let session = Session::default();
+        let score = |e| exp_score(&parse(&session, e).unwrap());
+        assert_eq!(score("fun x . x").precise, 0);
+        assert_eq!(
+            score("fun x : any -> any . x"),
+            Score {
+                precise: 1,
+                total: 3
+            }
+        );
+        assert_eq!(
+            score("(fun x : int . (x : any)) 5"),
+            Score {
+                precise: 1,
+                total: 1
+            }
+        );
+        assert_eq!(
+            score("let rec f : int -> int = fun x . (x : int) in f 0"),
+            Score {
+                precise: 3,
+                total: 4
+            }
+        );
+        assert_eq!(score("1 + 2"), Score::default());
+        let score = Score {
+            precise: 1,
+            total: 4,
+        };
+        assert_eq!(score.to_string(), "1/4 (25.0%)");
+    }
+
+    #[test]
+    fn compound_expressions() {
+        assert!(lt("(1, true)", "(1, true)"));
+        assert!(!lt("(1, true)", "(true, 1)"));
+        assert!(lt("head (1 :: empty)", "head (1 :: empty)"));
+        assert!(!lt("head (1 :: empty)", "tail (1 :: empty)"));
+    }
+}
@@ -0,0 +1,229 @@
+//! Constraints produced by `cgen`, and a union-find pre-solver that shrinks
+//! them before they are sent to Z3.
+//!
+//! Many of the constraints that `cgen` generates are plain equalities between
+//! types, e.g., the argument type of an application, or the type of a
+//! `letrec`-bound identifier and the type of its definition. These equalities
+//! are conjuncts of the whole formula, so every solution must satisfy them.
+//! Instead of asking Z3 to discover them, we unify them here and substitute
+//! representatives, so that only the genuinely disjunctive constraints (from
+//! weakening, strengthening, and friends) reach the `Optimize` solver.
+use super::syntax::Typ;
+use std::collections::HashMap;
+
+/// A type constructor that we can test for with a Z3 datatype tester.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ctor {
+    Arr,
+    List,
+    Pair,
+    Box,
+    Vect,
+}
+
+/// A constraint over types, which is translated to a Z3 formula after
+/// pre-solving.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Phi {
+    True,
+    Eq(Typ, Typ),
+    /// The type is built with the given constructor
+    Is(Ctor, Typ),
+    And(Vec<Phi>),
+    Or(Vec<Phi>),
+    Implies(Box<Phi>, Box<Phi>),
+}
+
+impl Phi {
+    pub fn eq(t1: &Typ, t2: &Typ) -> Phi {
+        Phi::Eq(t1.clone(), t2.clone())
+    }
+
+    pub fn is(ctor: Ctor, t: &Typ) -> Phi {
+        Phi::Is(ctor, t.clone())
+    }
+
+    pub fn implies(self, other: Phi) -> Phi {
+        Phi::Implies(Box::new(self), Box::new(other))
+    }
+
+    /// Unifies every equality that is a conjunct of the formula, and returns
+    /// the rest of the formula.
+    ///
+    /// When an equality cannot be unified (e.g., `int = bool`, or a failed
+    /// occurs check), it is left in the formula so that Z3 reports the
+    /// constraints as unsatisfiable. Any bindings made before the failure are
+    /// still implied by the equality, so they do not lose solutions.
+    pub fn presolve(self, uf: &mut UnionFind) -> Phi {
+        match self {
+            Phi::Eq(t1, t2) => {
+                if uf.unify(&t1, &t2) {
+                    Phi::True
+                } else {
+                    Phi::Eq(t1, t2)
+                }
+            }
+            Phi::And(phis) => phis
+                .into_iter()
+                .fold(Phi::True, |acc, phi| acc & phi.presolve(uf)),
+            phi => phi,
+        }
+    }
+}
+
+impl std::ops::BitAnd for Phi {
+    type Output = Phi;
+
+    fn bitand(self, other: Phi) -> Phi {
+        match (self, other) {
+            (Phi::True, phi) | (phi, Phi::True) => phi,
+            (Phi::And(mut phis1), Phi::And(phis2)) => {
+                phis1.extend(phis2);
+                Phi::And(phis1)
+            }
+            (Phi::And(mut phis), phi) | (phi, Phi::And(mut phis)) => {
+                phis.push(phi);
+                Phi::And(phis)
+            }
+            (phi1, phi2) => Phi::And(vec![phi1, phi2]),
+        }
+    }
+}
+
+impl std::ops::BitOr for Phi {
+    type Output = Phi;
+
+    fn bitor(self, other: Phi) -> Phi {
+        match (self, other) {
+            (Phi::True, _) | (_, Phi::True) => Phi::True,
+            (Phi::Or(mut phis1), Phi::Or(phis2)) => {
+                phis1.extend(phis2);
+                Phi::Or(phis1)
+            }
+            (Phi::Or(mut phis), phi) | (phi, Phi::Or(mut phis)) => {
+                phis.push(phi);
+                Phi::Or(phis)
+            }
+            (phi1, phi2) => Phi::Or(vec![phi1, phi2]),
+        }
+    }
+}
+
+/// Union-find over type metavariables. A metavariable is either unbound (a
+/// representative), or bound to another type, which may itself be a
+/// metavariable.
+#[derive(Default)]
+pub struct UnionFind {
+    parent: HashMap<u32, Typ>,
+}
+
+impl UnionFind {
+    /// Follows bindings until reaching an unbound metavariable or a type
+    /// constructor, with path compression.
+    pub fn find(&mut self, t: &Typ) -> Typ {
+        match t {
+            Typ::Metavar(n) => match self.parent.get(n).cloned() {
+                None => t.clone(),
+                Some(parent) => {
+                    let root = self.find(&parent);
+                    self.parent.insert(*n, root.clone());
+                    root
+                }
+            },
+            _ => t.clone(),
+        }
+    }
+
+    /// Substitutes representatives throughout the type.
+    pub fn resolve(&mut self, t: &Typ) -> Typ {
+        match self.find(t) {
+            Typ::Arr(t1, t2) => Typ::Arr(Box::new(self.resolve(&t1)), Box::new(self.resolve(&t2))),
+            Typ::Pair(t1, t2) => {
+                Typ::Pair(Box::new(self.resolve(&t1)), Box::new(self.resolve(&t2)))
+            }
+            Typ::List(t) => Typ::List(Box::new(self.resolve(&t))),
+            Typ::Box(t) => Typ::Box(Box::new(self.resolve(&t))),
+            Typ::Vect(t) => Typ::Vect(Box::new(self.resolve(&t))),
+            t => t,
+        }
+    }
+
+    pub fn unify(&mut self, t1: &Typ, t2: &Typ) -> bool {
+        let t1 = self.find(t1);
+        let t2 = self.find(t2);
+        match (&t1, &t2) {
+            (Typ::Metavar(m), Typ::Metavar(n)) if m == n => true,
+            (Typ::Metavar(n), t) | (t, Typ::Metavar(n)) => {
+                if self.occurs(*n, t) {
+                    false
+                } else {
+                    self.parent.insert(*n, t.clone());
+                    true
+                }
+            }
+            (Typ::Arr(t11, t12), Typ::Arr(t21, t22))
+            | (Typ::Pair(t11, t12), Typ::Pair(t21, t22)) => {
+                self.unify(t11, t21) && self.unify(t12, t22)
+            }
+            (Typ::List(t1), Typ::List(t2))
+            | (Typ::Box(t1), Typ::Box(t2))
+            | (Typ::Vect(t1), Typ::Vect(t2)) => self.unify(t1, t2),
+            _ => t1 == t2,
+        }
+    }
+
+    fn occurs(&mut self, n: u32, t: &Typ) -> bool {
+        match self.find(t) {
+            Typ::Metavar(m) => m == n,
+            Typ::Arr(t1, t2) | Typ::Pair(t1, t2) => self.occurs(n, &t1) || self.occurs(n, &t2),
+            Typ::List(t) | Typ::Box(t) | Typ::Vect(t) => self.occurs(n, &t),
+            Typ::Unit | Typ::Int | Typ::Float | Typ::Bool | Typ::Str | Typ::Char | Typ::Any => {
+                false
+            }
+        }
+    }
+
+    /// The metavariables that have been eliminated, in increasing order, so
+    /// that the solver sees them in the same order on every run
+    pub fn bound(&self) -> Vec<u32> {
+        let mut bound = self.parent.keys().cloned().collect::<Vec<_>>();
+        bound.sort_unstable();
+        bound
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn arr(t1: Typ, t2: Typ) -> Typ {
+        Typ::Arr(Box::new(t1), Box::new(t2))
+    }
+
+    #[test]
+    fn transitive_equalities() {
+        let mut uf = UnionFind::default();
+        let phi = Phi::eq(&Typ::Metavar(0), &Typ::Metavar(1))
+            & Phi::eq(&Typ::Metavar(1), &arr(Typ::Metavar(2), Typ::Int))
+            & Phi::eq(&Typ::Metavar(2), &Typ::Bool);
+        assert_eq!(phi.presolve(&mut uf), Phi::True);
+        assert_eq!(uf.resolve(&Typ::Metavar(0)), arr(Typ::Bool, Typ::Int));
+    }
+
+    #[test]
+    fn disjunctions_are_left_alone() {
+        let mut uf = UnionFind::default();
+        let phi = Phi::eq(&Typ::Metavar(0), &Typ::Int) | Phi::eq(&Typ::Metavar(0), &Typ::Any);
+        assert_eq!(phi.clone().presolve(&mut uf), phi);
+        assert!(uf.bound().is_empty());
+    }
+
+    #[test]
+    fn clashes_are_left_to_z3() {
+        let mut uf = UnionFind::default();
+        let clash = Phi::eq(&Typ::Int, &Typ::Bool);
+        assert_eq!(clash.clone().presolve(&mut uf), clash);
+        let occurs = Phi::eq(&Typ::Metavar(0), &arr(Typ::Metavar(0), Typ::Int));
+        assert_eq!(occurs.clone().presolve(&mut uf), occurs);
+    }
+}
@@ -34,7 +34,7 @@ macro_rules! impl_Display_Pretty {
 
 fn skip_coercion(e: &Exp) -> &Exp {
     match e {
-        Exp::Coerce(_, _, e) => e,
+        Exp::Coerce(_, _, e, _) => e,
         _ => e,
     }
 }
@@ -205,7 +205,7 @@ impl Pretty for Exp {
                 pp.line(),
                 e.pretty(pp),
             ]),
-            Exp::Ann(e, typ) => pp.intersperse(
+            Exp::Ann(e, typ, _) => pp.intersperse(
                 vec![
                     e.pretty(pp),
                     pp.space(),
@@ -260,8 +260,13 @@ impl Pretty for Exp {
                 pp.space(),
                 match op {
                     BinOp::IntAdd => pp.text("+"),
+                    BinOp::IntSub => pp.text("-"),
                     BinOp::IntMul => pp.text("*"),
                     BinOp::IntEq => pp.text("="),
+                    BinOp::IntLt => pp.text("<"),
+                    BinOp::IntGt => pp.text(">"),
+                    BinOp::IntLe => pp.text("<="),
+                    BinOp::IntGe => pp.text(">="),
                     _ => pp.text("[op]"),
                 },
                 pp.space(),
@@ -365,10 +370,10 @@ impl Pretty for Exp {
             }
             Exp::IsList(e) => pp.concat(vec![pp.text("is_list"), pp.space(), e.pretty(pp).nest(2)]),
             Exp::IsFun(e) => pp.concat(vec![pp.text("is_fun"), pp.space(), e.pretty(pp).nest(2)]),
-            Exp::Coerce(_, Typ::Any, e) if e.is_atom() => {
+            Exp::Coerce(_, Typ::Any, e, _) if e.is_atom() => {
                 pp.concat(vec![pp.text("("), e.pretty(pp), pp.text(" : any)")])
             }
-            Exp::Coerce(from, to, e) if PRINT_COERCIONS => pp.concat(vec![
+            Exp::Coerce(from, to, e, _) if PRINT_COERCIONS => pp.concat(vec![
                 pp.text("coerce("),
                 from.pretty(pp),
                 pp.text(", "),
@@ -377,7 +382,7 @@ impl Pretty for Exp {
                 pp.space(),
                 e.pretty(pp).nest(2),
             ]),
-            Exp::Coerce(_, _, e) => e.pretty(pp),
+            Exp::Coerce(_, _, e, _) => e.pretty(pp),
             Exp::PrimCoerce(k, e) => {
                 pp.concat(vec![pp.text(format!("[{:?}]", k)), e.pretty(pp).nest(2)])
             }
@@ -0,0 +1,131 @@
+//! Reduces a program that exhibits a migration bug to a small program that
+//! still exhibits it, using the shrinker that the fuzzer uses.
+use super::fuzz::{migrate, migrate_ins_and_outs};
+use super::session::Session;
+use super::shrink;
+use super::syntax::{Exp, Typ};
+use super::test_suite::guard;
+use super::type_check::tcheck;
+use super::validate::runs_ok;
+use im_rc::HashMap;
+
+type Env = HashMap<String, Typ>;
+
+/// Programs that take more steps than this are assumed to diverge
+const FUEL: u64 = 1_000_000;
+
+/// The bug that the reduced program must still exhibit
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Predicate {
+    /// Migration panics
+    Panics,
+    /// Migration succeeds, but the result does not type-check
+    TypeCheckFails,
+    /// The result of migration fails at runtime, but the original program
+    /// does not
+    NewRuntimeError,
+    /// Migrating with TypeWhich and with ins-and-outs produces one program
+    /// that fails at runtime and one that does not
+    ToolsDisagree,
+}
+
+impl std::str::FromStr for Predicate {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "panics" => Ok(Predicate::Panics),
+            "tcheck" => Ok(Predicate::TypeCheckFails),
+            "new-error" => Ok(Predicate::NewRuntimeError),
+            "disagree" => Ok(Predicate::ToolsDisagree),
+            _ => Err("invalid predicate"),
+        }
+    }
+}
+
+impl Predicate {
+    /// Whether the program exhibits the bug. The program is migrated with
+    /// `session`, which must be the session that parsed it.
+    pub fn holds(&self, session: &Session, env: &Env, exp: &Exp) -> bool {
+        match self {
+            Predicate::Panics => match guard(|| migrate(session, env, exp)) {
+                Err(msg) => msg.starts_with("panicked"),
+                Ok(_) => false,
+            },
+            Predicate::TypeCheckFails => guard(|| {
+                let migrated = migrate(session, env, exp)?;
+                Ok(tcheck(env, &migrated).is_err())
+            })
+            .unwrap_or(false),
+            Predicate::NewRuntimeError => guard(|| {
+                let mut original = exp.clone();
+                original.dynamic_metavars();
+                let migrated = migrate(session, env, exp)?;
+                Ok(runs_ok(original, FUEL) == Some(true) && runs_ok(migrated, FUEL) == Some(false))
+            })
+            .unwrap_or(false),
+            Predicate::ToolsDisagree => guard(|| {
+                let typewhich = runs_ok(migrate(session, env, exp)?, FUEL);
+                let ins_and_outs = runs_ok(migrate_ins_and_outs(exp), FUEL);
+                Ok(typewhich.is_some() && ins_and_outs.is_some() && typewhich != ins_and_outs)
+            })
+            .unwrap_or(false),
+        }
+    }
+}
+
+/// Prints the smallest program that we can find that exhibits the bug. Fails
+/// if the program does not exhibit the bug to begin with.
+pub fn reduce_main(
+    session: &Session,
+    env: &Env,
+    predicate: Predicate,
+    exp: Exp,
+) -> Result<(), String> {
+    // The predicates catch panics, so the default message is noise.
+    std::panic::set_hook(Box::new(|_| ()));
+    if !predicate.holds(session, env, &exp) {
+        let _ = std::panic::take_hook();
+        return Err(format!("the program does not satisfy {:?}", predicate));
+    }
+    let reduced = shrink::minimize(exp, |e| predicate.holds(session, env, e));
+    let _ = std::panic::take_hook();
+    println!("{}", reduced);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Predicate;
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::{Annot, Options};
+
+    #[test]
+    fn parse_predicates() {
+        assert_eq!("panics".parse(), Ok(Predicate::Panics));
+        assert_eq!("tcheck".parse(), Ok(Predicate::TypeCheckFails));
+        assert_eq!("new-error".parse(), Ok(Predicate::NewRuntimeError));
+        assert_eq!("disagree".parse(), Ok(Predicate::ToolsDisagree));
+        assert!("crash".parse::<Predicate>().is_err());
+    }
+
+    #[test]
+    fn correct_migrations_satisfy_no_predicate() {
+        let session = Session::new(Options {
+            annot: Annot::Ignore,
+            ..Options::default()
+        });
+        let exp = parse(&session, "(fun f . f 10) (fun x . x + 1)").unwrap();
+        for predicate in [
+            Predicate::Panics,
+            Predicate::TypeCheckFails,
+            Predicate::NewRuntimeError,
+            Predicate::ToolsDisagree,
+        ]
+        .iter()
+        {
+            assert!(!predicate.holds(&session, &Default::default(), &exp));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! The state of one migration: metavariable generation, warnings, and
+//! options.
+//!
+//! Everything that produces metavariables takes a session, including the
+//! parsers, so a program must be parsed and migrated with the same session.
+//! Sessions are independent of each other, so metavariable numbers only
+//! depend on the program, and several migrations can run at once in one
+//! process.
+use super::syntax::Typ;
+use super::Options;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Cloning a session produces a handle to the same session.
+#[derive(Clone, Default)]
+pub struct Session {
+    next_metavar: Rc<Cell<u32>>,
+    warnings: Rc<RefCell<BTreeSet<String>>>,
+    solver_stats: Rc<Cell<SolverStats>>,
+    pub options: Options,
+    pub interrupt: Interrupt,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        Session {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// A new session whose metavariables start at `next_metavar`. Sessions
+    /// cannot be sent to other threads, so a thread that migrates a program
+    /// parsed by another thread starts a session where the other one left off.
+    pub fn starting_at(next_metavar: u32, options: Options) -> Self {
+        let session = Session::new(options);
+        session.next_metavar.set(next_metavar);
+        session
+    }
+
+    /// The same session, with different options.
+    pub fn with_options(&self, options: Options) -> Self {
+        Session {
+            options,
+            ..self.clone()
+        }
+    }
+
+    pub fn next_metavar(&self) -> Typ {
+        Typ::Metavar(self.inc_metavar())
+    }
+
+    pub fn inc_metavar(&self) -> u32 {
+        let i = self.next_metavar.get();
+        self.next_metavar.set(i + 1);
+        i
+    }
+
+    /// The next metavariable that `next_metavar` will produce
+    pub fn metavar_counter(&self) -> u32 {
+        self.next_metavar.get()
+    }
+
+    pub fn warning(&self, msg: impl AsRef<str>) {
+        self.warnings.borrow_mut().insert(msg.as_ref().to_string());
+    }
+
+    pub fn show_warnings(&self) {
+        for w in self.warnings.replace(BTreeSet::new()).into_iter() {
+            eprintln!("Warning: {}", w);
+        }
+    }
+
+    /// The work that the solver has done so far in this session, summed over
+    /// every problem sent to it
+    pub fn solver_stats(&self) -> SolverStats {
+        self.solver_stats.get()
+    }
+
+    pub fn record_solver_stats(&self, f: impl FnOnce(&mut SolverStats)) {
+        let mut stats = self.solver_stats.get();
+        f(&mut stats);
+        self.solver_stats.set(stats);
+    }
+}
+
+/// The size of the problems sent to the solver, and the time it took to
+/// solve them
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct SolverStats {
+    /// Metavariables that the pre-solver did not eliminate
+    pub variables: usize,
+    /// Coercions that the solver tries to make identities
+    pub soft_constraints: usize,
+    /// Calls to the solver, which is two per problem when migrating for context
+    pub checks: usize,
+    /// Time spent in the solver, in seconds
+    pub seconds: f64,
+}
+
+/// Stops a migration from another thread, e.g., a strategy of a portfolio
+/// that lost, or a migration that timed out. Cloning an interrupt produces a
+/// handle to the same interrupt.
+#[derive(Clone, Default)]
+pub struct Interrupt(Arc<(Mutex<bool>, Condvar)>);
+
+/// Z3 ignores an interrupt that arrives before it starts solving, so we send
+/// it again at this interval until the solver returns.
+const RETRY_INTERRUPT: Duration = Duration::from_millis(10);
+
+impl Interrupt {
+    pub fn interrupt(&self) {
+        let (interrupted, changed) = &*self.0;
+        *interrupted.lock().unwrap() = true;
+        changed.notify_all();
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        *self.0 .0.lock().unwrap()
+    }
+
+    /// Runs `f`, and calls `stop` in another thread whenever the migration
+    /// is interrupted before `f` returns.
+    pub fn stop_during<T>(&self, stop: impl Fn() + Send, f: impl FnOnce() -> T) -> T {
+        let (interrupted, changed) = &*self.0;
+        let finished = &AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut guard = interrupted.lock().unwrap();
+                while !finished.load(Ordering::SeqCst) {
+                    guard = if *guard {
+                        stop();
+                        changed.wait_timeout(guard, RETRY_INTERRUPT).unwrap().0
+                    } else {
+                        changed.wait(guard).unwrap()
+                    };
+                }
+            });
+            let _finish = Finish {
+                interrupted,
+                changed,
+                finished,
+            };
+            f()
+        })
+    }
+}
+
+/// Wakes the thread of `stop_during` when `f` returns, or panics.
+struct Finish<'a> {
+    interrupted: &'a Mutex<bool>,
+    changed: &'a Condvar,
+    finished: &'a AtomicBool,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        let _guard = self.interrupted.lock().unwrap();
+        self.finished.store(true, Ordering::SeqCst);
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interrupt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn interrupts_stop_work_in_progress() {
+        let interrupt = Interrupt::default();
+        assert_eq!(interrupt.stop_during(|| panic!("not interrupted"), || 1), 1);
+        let stopped = AtomicBool::new(false);
+        let result = interrupt.stop_during(
+            || stopped.store(true, Ordering::SeqCst),
+            || {
+                interrupt.clone().interrupt();
+                while !stopped.load(Ordering::SeqCst) {
+                    std::thread::yield_now();
+                }
+                2
+            },
+        );
+        assert_eq!(result, 2);
+        assert!(interrupt.is_interrupted());
+    }
+}
@@ -0,0 +1,171 @@
+//! Shrinks programs that exhibit a bug, by repeatedly replacing the program
+//! with a smaller one that still exhibits the bug.
+use super::syntax::{Exp, Id, Lit, Typ};
+
+/// The variables that `exp` binds in its `i`th child
+fn bound_in(exp: &Exp, i: usize) -> Vec<Id> {
+    match exp {
+        Exp::Fun(x, _, _) | Exp::Fix(x, _, _) => vec![x.clone()],
+        Exp::Let(x, _, _) if i == 1 => vec![x.clone()],
+        Exp::LetRec(bindings, _) => bindings.iter().map(|(x, _, _)| x.clone()).collect(),
+        _ => vec![],
+    }
+}
+
+/// Whether every variable in the expression is bound in `scope` or by the
+/// expression itself
+fn is_closed_in(exp: &Exp, scope: &mut Vec<Id>) -> bool {
+    match exp {
+        Exp::Var(x) => scope.contains(x),
+        _ => exp.children().into_iter().enumerate().all(|(i, e)| {
+            let n = scope.len();
+            scope.extend(bound_in(exp, i));
+            let closed = is_closed_in(e, scope);
+            scope.truncate(n);
+            closed
+        }),
+    }
+}
+
+/// Whether the expression has no free variables
+pub fn is_closed(exp: &Exp) -> bool {
+    is_closed_in(exp, &mut Vec::new())
+}
+
+/// The number of free occurrences of `x` in `exp`
+fn occurrences(exp: &Exp, x: &Id) -> usize {
+    match exp {
+        Exp::Var(y) => (x == y) as usize,
+        _ => exp
+            .children()
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !bound_in(exp, *i).contains(x))
+            .map(|(_, e)| occurrences(e, x))
+            .sum(),
+    }
+}
+
+/// Replaces the free occurrences of `x` in `exp` with `v`. This may capture
+/// the free variables of `v`.
+fn subst(exp: &mut Exp, x: &Id, v: &Exp) {
+    if matches!(exp, Exp::Var(y) if y == x) {
+        *exp = v.clone();
+        return;
+    }
+    let shadowed: Vec<bool> = (0..exp.children().len())
+        .map(|i| bound_in(exp, i).contains(x))
+        .collect();
+    for (e, shadowed) in exp.children_mut().into_iter().zip(shadowed) {
+        if !shadowed {
+            subst(e, x, v);
+        }
+    }
+}
+
+/// Expressions that are one step smaller than `exp`, but may have free
+/// variables. In order, we try to replace the expression with one of its
+/// subexpressions, drop a recursive binding, inline a let that uses its
+/// variable at most once, replace the expression with a literal or a
+/// variable in `scope`, and drop annotations. Finally, we shrink each
+/// subexpression.
+fn shrink_in(exp: &Exp, scope: &mut Vec<Id>) -> Vec<Exp> {
+    let mut smaller = Vec::new();
+    if let Exp::Lit(_) = exp {
+        return smaller;
+    }
+    smaller.extend(exp.children().into_iter().cloned());
+    match exp {
+        Exp::LetRec(bindings, body) => {
+            for i in 0..bindings.len() {
+                let mut bindings = bindings.clone();
+                bindings.remove(i);
+                smaller.push(Exp::LetRec(bindings, body.clone()));
+            }
+        }
+        Exp::Let(x, e1, e2) if e1.is_atom() || occurrences(e2, x) <= 1 => {
+            let mut inlined = (**e2).clone();
+            subst(&mut inlined, x, e1);
+            smaller.push(inlined);
+        }
+        _ => (),
+    }
+    smaller.push(Exp::Lit(Lit::Int(0)));
+    smaller.push(Exp::Lit(Lit::Bool(true)));
+    if let Exp::Var(_) = exp {
+        return smaller;
+    }
+    smaller.extend(scope.iter().rev().map(|x| Exp::Var(x.clone())));
+    match exp {
+        Exp::Fun(x, t, e) if t != &Typ::Any => {
+            smaller.push(Exp::Fun(x.clone(), Typ::Any, e.clone()))
+        }
+        Exp::Ann(e, _, _) => smaller.push((**e).clone()),
+        _ => (),
+    }
+    let num_children = exp.children().len();
+    for i in 0..num_children {
+        let n = scope.len();
+        scope.extend(bound_in(exp, i));
+        for child in shrink_in(exp.children()[i], scope) {
+            let mut e = exp.clone();
+            *e.children_mut()[i] = child;
+            smaller.push(e);
+        }
+        scope.truncate(n);
+    }
+    smaller
+}
+
+/// Expressions that are one step smaller than the closed expression `exp`,
+/// but may have free variables
+pub fn shrink(exp: &Exp) -> Vec<Exp> {
+    shrink_in(exp, &mut Vec::new())
+}
+
+/// Shrinks a closed expression for as long as `fails` holds. The result is
+/// closed, and no single step of `shrink` makes it smaller.
+pub fn minimize(mut exp: Exp, mut fails: impl FnMut(&Exp) -> bool) -> Exp {
+    'search: loop {
+        for candidate in shrink(&exp) {
+            if is_closed(&candidate) && fails(&candidate) {
+                exp = candidate;
+                continue 'search;
+            }
+        }
+        return exp;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_closed, minimize};
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::syntax::{BinOp, Exp};
+
+    fn exp(program: &str) -> Exp {
+        parse(&Session::default(), program).unwrap()
+    }
+
+    #[test]
+    fn closed_expressions() {
+        assert!(is_closed(&exp("fun x . x + 1")));
+        assert!(is_closed(&exp(
+            "let rec f = fun x . g x and g = fun y . f y in f"
+        )));
+        assert!(is_closed(&exp("let y = 1 in y")));
+        assert!(!is_closed(&exp("let y = y in 1")));
+        assert!(!is_closed(&exp("(fun x . x) x")));
+    }
+
+    fn has_add(e: &Exp) -> bool {
+        matches!(e, Exp::BinaryOp(BinOp::IntAdd, _, _)) || e.children().into_iter().any(has_add)
+    }
+
+    #[test]
+    fn minimize_keeps_the_bug() {
+        let small = minimize(exp("(fun x . fun y . x + y) 5 true"), has_add);
+        assert_eq!(small, exp("0 + 0"));
+    }
+}
@@ -1,30 +1,144 @@
-use crate::parser::next_metavar;
+use crate::session::Session;
 
-/// Several ground types are presently missing. But, these are all we need
-/// for the non-Grift benchmarks.
+/// The ground types of lists, pairs, boxes, and vectors are presently
+/// missing, since the evaluator does not support those values.
 #[derive(Debug, PartialEq, Clone)]
 pub enum GroundTyp {
+    Unit,
     Int,
+    Float,
     Bool,
+    Str,
+    Char,
     Fun,
 }
 
+/// The blame label of a Grift annotation, `(: e T "label")`
+pub type Label = Option<String>;
+
+/// Who to blame when a coercion fails. Positive blame falls on the annotated
+/// expression, and negative blame falls on its context, e.g., when the
+/// context applies an annotated function to an argument of the wrong type.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Blame {
+    pub label: String,
+    pub positive: bool,
+}
+
+impl Blame {
+    pub fn positive(label: &Label) -> Option<Blame> {
+        label.as_ref().map(|label| Blame {
+            label: label.clone(),
+            positive: true,
+        })
+    }
+
+    /// The blame of a coercion on a function argument
+    pub fn negate(&self) -> Blame {
+        Blame {
+            label: self.label.clone(),
+            positive: !self.positive,
+        }
+    }
+}
+
+impl std::fmt::Display for Blame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.positive {
+            write!(f, "{}", self.label)
+        } else {
+            write!(f, "{} (negative)", self.label)
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Coerce {
     Id,
     Tag(GroundTyp),
-    Untag(GroundTyp),
-    Wrap(Box<Coerce>, Box<Coerce>),
+    Untag(GroundTyp, Option<Blame>),
+    Wrap(Box<Coerce>, Box<Coerce>, Option<Blame>),
     Seq(Box<Coerce>, Box<Coerce>),
-    Doomed,
+    Doomed(Option<Blame>),
 }
 
 impl Coerce {
+    /// Composes `self` and then `other`. If both coercions are in the normal
+    /// form of λS (Siek, Thiemann, and Wadler, 2015), so is the result:
+    ///
+    /// ```text
+    /// s ::= Id | G?p | G?p ; i | i
+    /// i ::= g ; G! | G! | g | Doomed(p)
+    /// g ::= Wrap(s, s)
+    /// ```
+    ///
+    /// The size of a normal form is bounded by the size of its type, so
+    /// composing coercions repeatedly takes constant space.
     pub fn seq(&self, other: &Coerce) -> Coerce {
         match (self, other) {
             (Coerce::Id, _) => other.clone(),
             (_, Coerce::Id) => self.clone(),
-            _ => Coerce::Seq(Box::new(self.clone()), Box::new(other.clone())),
+            (Coerce::Doomed(_), _) => self.clone(),
+            // (G?p ; i) ; t = G?p ; (i ; t)
+            (Coerce::Seq(k1, k2), _) if matches!(**k1, Coerce::Untag(..)) => k1.seq(&k2.seq(other)),
+            (Coerce::Untag(..), _) => Coerce::Seq(Box::new(self.clone()), Box::new(other.clone())),
+            (_, Coerce::Doomed(_)) => other.clone(),
+            // (s -> t) ; (s' -> t') = (s' ; s) -> (t ; t')
+            (Coerce::Wrap(s1, t1, blame), Coerce::Wrap(s2, t2, _)) => {
+                Coerce::Wrap(Box::new(s2.seq(s1)), Box::new(t1.seq(t2)), blame.clone())
+            }
+            _ => match (self.injection(), other.projection()) {
+                // (g ; G!) ; (H?p ; i) = g ; i if G = H, and Doomed(p) otherwise
+                (Some((g, g_tag)), Some((h_tag, blame, i))) => {
+                    if g_tag == h_tag {
+                        g.unwrap_or(&Coerce::Id).seq(i.unwrap_or(&Coerce::Id))
+                    } else {
+                        Coerce::Doomed(blame.clone())
+                    }
+                }
+                // g ; (h ; H!) = (g ; h) ; H!
+                (None, None) if matches!(self, Coerce::Wrap(..)) => match other.injection() {
+                    Some((h, tag)) => Coerce::Seq(
+                        Box::new(h.map_or_else(|| self.clone(), |h| self.seq(h))),
+                        Box::new(Coerce::Tag(tag.clone())),
+                    ),
+                    None => Coerce::Seq(Box::new(self.clone()), Box::new(other.clone())),
+                },
+                _ => Coerce::Seq(Box::new(self.clone()), Box::new(other.clone())),
+            },
+        }
+    }
+
+    /// Splits `g ; G!` into `g` and `G`.
+    fn injection(&self) -> Option<(Option<&Coerce>, &GroundTyp)> {
+        match self {
+            Coerce::Tag(g) => Some((None, g)),
+            Coerce::Seq(k, tag) => match &**tag {
+                Coerce::Tag(g) => Some((Some(k), g)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Splits `G?p ; i` into `G`, `p`, and `i`.
+    fn projection(&self) -> Option<(&GroundTyp, &Option<Blame>, Option<&Coerce>)> {
+        match self {
+            Coerce::Untag(g, blame) => Some((g, blame, None)),
+            Coerce::Seq(k, i) => match &**k {
+                Coerce::Untag(g, blame) => Some((g, blame, Some(i))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The number of constructors in the coercion
+    #[cfg(test)]
+    pub fn size(&self) -> usize {
+        match self {
+            Coerce::Id | Coerce::Tag(_) | Coerce::Untag(..) | Coerce::Doomed(_) => 1,
+            Coerce::Wrap(k1, k2, _) | Coerce::Seq(k1, k2) => 1 + k1.size() + k2.size(),
         }
     }
 }
@@ -97,6 +211,18 @@ impl Typ {
         matches!(self, Typ::Metavar(..))
     }
 
+    pub fn map_metavars(&mut self, f: &impl Fn(u32) -> Typ) {
+        match self {
+            Typ::Metavar(n) => *self = f(*n),
+            Typ::Arr(t1, t2) | Typ::Pair(t1, t2) => {
+                t1.map_metavars(f);
+                t2.map_metavars(f);
+            }
+            Typ::List(t) | Typ::Box(t) | Typ::Vect(t) => t.map_metavars(f),
+            Typ::Unit | Typ::Int | Typ::Float | Typ::Bool | Typ::Str | Typ::Char | Typ::Any => (),
+        }
+    }
+
     pub fn join(&self, other: &Typ) -> Typ {
         if self.is_metavar() || other.is_metavar() {
             panic!(".join on metavars")
@@ -164,7 +290,7 @@ pub enum Exp {
     BinaryOp(BinOp, Box<Exp>, Box<Exp>),
     Let(Id, Box<Exp>, Box<Exp>),
     LetRec(Vec<(Id, Typ, Exp)>, Box<Exp>),
-    Ann(Box<Exp>, Typ),
+    Ann(Box<Exp>, Typ, Label),
     AddOverload(Box<Exp>, Box<Exp>),
     If(Box<Exp>, Box<Exp>, Box<Exp>),
     // pairs
@@ -197,13 +323,26 @@ pub enum Exp {
     IsString(Box<Exp>),
     IsList(Box<Exp>),
     IsFun(Box<Exp>),
-    Coerce(Typ, Typ, Box<Exp>),
+    Coerce(Typ, Typ, Box<Exp>, Label),
     /// The Coerce variant is unfortunately named, since it is really an
     /// occurrence of the coerce metafunction. This PrimCoerce is actually a
     /// coercion application.
     PrimCoerce(Coerce, Box<Exp>),
 }
 
+/// A Grift `(repeat (var lo hi) (acc : acc_typ acc_init) body)` loop, which
+/// `Exp::repeat` desugars to a recursive function
+#[derive(Debug, PartialEq, Clone)]
+pub struct Repeat {
+    pub var: Id,
+    pub lo: Exp,
+    pub hi: Exp,
+    pub acc: Id,
+    pub acc_typ: Typ,
+    pub acc_init: Exp,
+    pub body: Exp,
+}
+
 /// Holds the type for a unary operator. Not guaranteed to hold the actual
 /// operation from the program
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -232,14 +371,25 @@ pub enum UnOp {
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum BinOp {
     IntEq,
-    /// Most other binops also stand in for a number of different operators of
-    /// a different type, which is obviously wrong. Our interpreter mostly doesn't
-    /// support them so it's not a problem, but it supports this one. (int -> int)
-    /// -> int operators that are not supported by the interpreter go in IntMul
+    IntLt,
+    IntGt,
+    IntLe,
+    IntGe,
     IntAdd,
-    /// May not actually be IntMul. Don't parse an (int -> int) -> int operation
-    /// besides + as IntAdd, parse it as IntMul
+    IntSub,
     IntMul,
+    /// Division that rounds toward zero, which is Grift's `%/` and `quotient`
+    IntQuotient,
+    /// The remainder of `IntQuotient`, which is Grift's `%%`
+    IntRemainder,
+    IntShiftLeft,
+    IntShiftRight,
+    BinaryAnd,
+    BinaryOr,
+    BinaryXor,
+    /// The float binops stand in for a number of different operators of the
+    /// same type, which is obviously wrong. Our interpreter does not support
+    /// them, so it's not a problem.
     FloatAdd,
     FloatEq,
     /// grift's `and`. i'm not sure what this is mostly because i'm not sure why
@@ -283,8 +433,19 @@ impl BinOp {
         use std::boxed::Box;
         use Typ::*;
         match self {
-            BinOp::IntEq => (Int, Int, Bool),
-            BinOp::IntAdd | BinOp::IntMul => (Int, Int, Int),
+            BinOp::IntEq | BinOp::IntLt | BinOp::IntGt | BinOp::IntLe | BinOp::IntGe => {
+                (Int, Int, Bool)
+            }
+            BinOp::IntAdd
+            | BinOp::IntSub
+            | BinOp::IntMul
+            | BinOp::IntQuotient
+            | BinOp::IntRemainder
+            | BinOp::IntShiftLeft
+            | BinOp::IntShiftRight
+            | BinOp::BinaryAnd
+            | BinOp::BinaryOr
+            | BinOp::BinaryXor => (Int, Int, Int),
             BinOp::FloatAdd => (Float, Float, Float),
             BinOp::FloatEq => (Float, Float, Bool),
             // see doc
@@ -300,10 +461,13 @@ impl Exp {
         std::mem::replace(self, Exp::Lit(Lit::Int(0)))
     }
 
+    /// Wraps the expression in the coercion `k`. Adjacent coercions are
+    /// composed, so an expression is never wrapped in more than one.
     pub fn coerce(self, k: Coerce) -> Self {
-        match k {
-            Coerce::Id => self,
-            _ => Exp::PrimCoerce(k, Box::new(self)),
+        match (k, self) {
+            (Coerce::Id, e) => e,
+            (k, Exp::PrimCoerce(k1, e)) => e.coerce(k1.seq(&k)),
+            (k, e) => Exp::PrimCoerce(k, Box::new(e)),
         }
     }
 
@@ -330,7 +494,7 @@ impl Exp {
         let mut res = body;
         for (x, t, e) in bindings.into_iter().rev() {
             let e = match t {
-                Some(t) => Exp::Ann(Box::new(e), t),
+                Some(t) => Exp::Ann(Box::new(e), t, None),
                 None => e,
             };
 
@@ -454,15 +618,16 @@ impl Exp {
         e
     }
 
-    pub fn repeat(
-        var: Id,
-        lo: Exp,
-        hi: Exp,
-        acc: Id,
-        acc_typ: Typ,
-        acc_init: Exp,
-        body: Exp,
-    ) -> Exp {
+    pub fn repeat(session: &Session, repeat: Repeat) -> Exp {
+        let Repeat {
+            var,
+            lo,
+            hi,
+            acc,
+            acc_typ,
+            acc_init,
+            body,
+        } = repeat;
         let loop_fun = format!("__loop_{}_{}", var, acc); // TODO(mmg): ensure freshness
         let loop_hi = format!("__loop_{}_{}_hi", var, acc);
         let index = Box::new(Exp::Var(var.clone()));
@@ -489,11 +654,14 @@ impl Exp {
 
         Exp::LetRec(
             vec![
-                (loop_hi, next_metavar(), hi),
+                (loop_hi, session.next_metavar(), hi),
                 (
                     loop_fun.clone(),
-                    next_metavar(),
-                    Exp::funs(vec![(var, next_metavar()), (acc, acc_typ)], loop_body),
+                    session.next_metavar(),
+                    Exp::funs(
+                        vec![(var, session.next_metavar()), (acc, acc_typ)],
+                        loop_body,
+                    ),
                 ),
             ],
             Box::new(Exp::apps(vec![Exp::Var(loop_fun), lo, acc_init])),
@@ -503,24 +671,24 @@ impl Exp {
     /// Replaces all type annotations with metavariables
     ///
     /// Removes `Exp::Ann` and `Exp::Coerce` nodes (but leaves in `Exp::Ann(e, Typ::Any))`)
-    pub fn fresh_types(&mut self) {
+    pub fn fresh_types(&mut self, session: &Session) {
         match self {
-            Exp::Ann(e, _) | Exp::Coerce(_, _, e) => {
-                e.fresh_types();
+            Exp::Ann(e, ..) | Exp::Coerce(_, _, e, _) => {
+                e.fresh_types(session);
                 *self = e.take();
             }
             Exp::Lit(_) | Exp::Var(_) => (),
-            Exp::Empty(t) => *t = next_metavar(),
+            Exp::Empty(t) => *t = session.next_metavar(),
             Exp::Fun(_, t, e) | Exp::Fix(_, t, e) => {
-                *t = next_metavar();
-                e.fresh_types();
+                *t = session.next_metavar();
+                e.fresh_types(session);
             }
             Exp::LetRec(bindings, e) => {
                 for (_, ti, ei) in bindings.iter_mut() {
-                    *ti = next_metavar();
-                    ei.fresh_types();
+                    *ti = session.next_metavar();
+                    ei.fresh_types(session);
                 }
-                e.fresh_types();
+                e.fresh_types(session);
             }
             Exp::UnaryOp(_, e)
             | Exp::Fst(e)
@@ -535,7 +703,7 @@ impl Exp {
             | Exp::IsString(e)
             | Exp::IsList(e)
             | Exp::IsFun(e)
-            | Exp::VectorLen(e) => e.fresh_types(),
+            | Exp::VectorLen(e) => e.fresh_types(session),
             Exp::App(e1, e2)
             | Exp::Let(_, e1, e2)
             | Exp::AddOverload(e1, e2)
@@ -545,18 +713,199 @@ impl Exp {
             | Exp::BoxSet(e1, e2)
             | Exp::Vector(e1, e2)
             | Exp::VectorRef(e1, e2) => {
-                e1.fresh_types();
-                e2.fresh_types();
+                e1.fresh_types(session);
+                e2.fresh_types(session);
             }
             Exp::If(e1, e2, e3) | Exp::VectorSet(e1, e2, e3) => {
-                e1.fresh_types();
-                e2.fresh_types();
-                e3.fresh_types();
+                e1.fresh_types(session);
+                e2.fresh_types(session);
+                e3.fresh_types(session);
             }
             Exp::PrimCoerce(..) => panic!("PrimCoerce should not appear in source"),
         };
     }
 
+    /// Replaces every metavariable in the expression with the same
+    /// metavariable. Two parses of the same source are equal after erasure.
+    pub fn erase_metavars(&mut self) {
+        self.map_metavars(&|_| Typ::Metavar(0));
+    }
+
+    /// Replaces every metavariable in the expression with Any, which is what
+    /// a missing annotation means in a gradually typed program.
+    pub fn dynamic_metavars(&mut self) {
+        self.map_metavars(&|_| Typ::Any);
+    }
+
+    pub fn map_metavars(&mut self, f: &impl Fn(u32) -> Typ) {
+        match self {
+            Exp::Lit(_) | Exp::Var(_) => (),
+            Exp::Empty(t) => t.map_metavars(f),
+            Exp::Fun(_, t, e) | Exp::Fix(_, t, e) | Exp::Ann(e, t, _) => {
+                t.map_metavars(f);
+                e.map_metavars(f);
+            }
+            Exp::Coerce(t1, t2, e, _) => {
+                t1.map_metavars(f);
+                t2.map_metavars(f);
+                e.map_metavars(f);
+            }
+            Exp::LetRec(bindings, e) => {
+                for (_, ti, ei) in bindings.iter_mut() {
+                    ti.map_metavars(f);
+                    ei.map_metavars(f);
+                }
+                e.map_metavars(f);
+            }
+            Exp::UnaryOp(_, e)
+            | Exp::Fst(e)
+            | Exp::Snd(e)
+            | Exp::IsEmpty(e)
+            | Exp::Head(e)
+            | Exp::Tail(e)
+            | Exp::Box(e)
+            | Exp::Unbox(e)
+            | Exp::IsBool(e)
+            | Exp::IsInt(e)
+            | Exp::IsString(e)
+            | Exp::IsList(e)
+            | Exp::IsFun(e)
+            | Exp::VectorLen(e)
+            | Exp::PrimCoerce(_, e) => e.map_metavars(f),
+            Exp::App(e1, e2)
+            | Exp::Let(_, e1, e2)
+            | Exp::AddOverload(e1, e2)
+            | Exp::BinaryOp(_, e1, e2)
+            | Exp::Pair(e1, e2)
+            | Exp::Cons(e1, e2)
+            | Exp::BoxSet(e1, e2)
+            | Exp::Vector(e1, e2)
+            | Exp::VectorRef(e1, e2) => {
+                e1.map_metavars(f);
+                e2.map_metavars(f);
+            }
+            Exp::If(e1, e2, e3) | Exp::VectorSet(e1, e2, e3) => {
+                e1.map_metavars(f);
+                e2.map_metavars(f);
+                e3.map_metavars(f);
+            }
+        }
+    }
+
+    /// The immediate subexpressions
+    pub fn children(&self) -> Vec<&Exp> {
+        match self {
+            Exp::Lit(_) | Exp::Var(_) | Exp::Empty(_) => vec![],
+            Exp::LetRec(bindings, e) => {
+                let mut children: Vec<&Exp> = bindings.iter().map(|(_, _, ei)| ei).collect();
+                children.push(&**e);
+                children
+            }
+            Exp::Fun(_, _, e)
+            | Exp::Fix(_, _, e)
+            | Exp::Ann(e, _, _)
+            | Exp::Coerce(_, _, e, _)
+            | Exp::UnaryOp(_, e)
+            | Exp::Fst(e)
+            | Exp::Snd(e)
+            | Exp::IsEmpty(e)
+            | Exp::Head(e)
+            | Exp::Tail(e)
+            | Exp::Box(e)
+            | Exp::Unbox(e)
+            | Exp::IsBool(e)
+            | Exp::IsInt(e)
+            | Exp::IsString(e)
+            | Exp::IsList(e)
+            | Exp::IsFun(e)
+            | Exp::VectorLen(e)
+            | Exp::PrimCoerce(_, e) => vec![&**e],
+            Exp::App(e1, e2)
+            | Exp::Let(_, e1, e2)
+            | Exp::AddOverload(e1, e2)
+            | Exp::BinaryOp(_, e1, e2)
+            | Exp::Pair(e1, e2)
+            | Exp::Cons(e1, e2)
+            | Exp::BoxSet(e1, e2)
+            | Exp::Vector(e1, e2)
+            | Exp::VectorRef(e1, e2) => vec![&**e1, &**e2],
+            Exp::If(e1, e2, e3) | Exp::VectorSet(e1, e2, e3) => {
+                vec![&**e1, &**e2, &**e3]
+            }
+        }
+    }
+
+    /// Like `children`, but the subexpressions are mutable
+    pub fn children_mut(&mut self) -> Vec<&mut Exp> {
+        match self {
+            Exp::Lit(_) | Exp::Var(_) | Exp::Empty(_) => vec![],
+            Exp::LetRec(bindings, e) => {
+                let mut children: Vec<&mut Exp> =
+                    bindings.iter_mut().map(|(_, _, ei)| ei).collect();
+                children.push(&mut **e);
+                children
+            }
+            Exp::Fun(_, _, e)
+            | Exp::Fix(_, _, e)
+            | Exp::Ann(e, _, _)
+            | Exp::Coerce(_, _, e, _)
+            | Exp::UnaryOp(_, e)
+            | Exp::Fst(e)
+            | Exp::Snd(e)
+            | Exp::IsEmpty(e)
+            | Exp::Head(e)
+            | Exp::Tail(e)
+            | Exp::Box(e)
+            | Exp::Unbox(e)
+            | Exp::IsBool(e)
+            | Exp::IsInt(e)
+            | Exp::IsString(e)
+            | Exp::IsList(e)
+            | Exp::IsFun(e)
+            | Exp::VectorLen(e)
+            | Exp::PrimCoerce(_, e) => vec![&mut **e],
+            Exp::App(e1, e2)
+            | Exp::Let(_, e1, e2)
+            | Exp::AddOverload(e1, e2)
+            | Exp::BinaryOp(_, e1, e2)
+            | Exp::Pair(e1, e2)
+            | Exp::Cons(e1, e2)
+            | Exp::BoxSet(e1, e2)
+            | Exp::Vector(e1, e2)
+            | Exp::VectorRef(e1, e2) => vec![&mut **e1, &mut **e2],
+            Exp::If(e1, e2, e3) | Exp::VectorSet(e1, e2, e3) => {
+                vec![&mut **e1, &mut **e2, &mut **e3]
+            }
+        }
+    }
+
+    /// The annotated binders of the expression, in order, except for the
+    /// variables that `print_id_types` also skips
+    pub fn binders(&self) -> Vec<(&Id, &Typ)> {
+        let mut binders = match self {
+            Exp::Fun(x, t, _) | Exp::Fix(x, t, _) => vec![(x, t)],
+            Exp::LetRec(bindings, _) => bindings.iter().map(|(x, t, _)| (x, t)).collect(),
+            _ => vec![],
+        };
+        binders.retain(|(x, _)| !x.starts_with("__"));
+        for e in self.children() {
+            binders.extend(e.binders());
+        }
+        binders
+    }
+
+    /// Replaces every occurrence of the variable `HOLE` with `exp`. This is
+    /// how the contexts in `benchmarks.yaml` are written.
+    pub fn plug(&mut self, exp: &Exp) {
+        if matches!(self, Exp::Var(x) if x == "HOLE") {
+            *self = exp.clone();
+        } else {
+            for e in self.children_mut() {
+                e.plug(exp);
+            }
+        }
+    }
+
     /// Returns true when for every annotation in other, self matches
     ///
     /// Should be used like
@@ -572,9 +921,9 @@ impl Exp {
     /// a coercion, the comparison is skipped
     pub fn matches_roughly(&self, other: &Exp) -> Result<(), String> {
         match (self, other) {
-            (_, Exp::Ann(e, _)) => self.matches_roughly(e),
+            (_, Exp::Ann(e, ..)) => self.matches_roughly(e),
             (Exp::Ann(..), _) => panic!("why ann on left-hand side?"),
-            (Exp::Coerce(.., e), _) => e.matches_roughly(other),
+            (Exp::Coerce(_, _, e, _), _) => e.matches_roughly(other),
             (Exp::Lit(_), Exp::Lit(_)) | (Exp::Var(_), Exp::Var(_)) => Ok(()),
             (Exp::Empty(t1), Exp::Empty(t2)) => {
                 if t2.is_metavar() || t1 == t2 {
@@ -652,7 +1001,7 @@ impl Exp {
     // Print the types of each bound identifier in program order
     pub fn print_id_types(&self) {
         match self {
-            Exp::Ann(e, _) | Exp::Coerce(_, _, e) => {
+            Exp::Ann(e, ..) | Exp::Coerce(_, _, e, _) => {
                 e.print_id_types();
             }
             Exp::Lit(_) | Exp::Var(_) | Exp::Empty(_) => (),
@@ -745,7 +1094,7 @@ impl Exp {
 
     pub fn is_coercion(&self) -> bool {
         match self {
-            Exp::Coerce(_, _, e) => e.is_atom(),
+            Exp::Coerce(_, _, e, _) => e.is_atom(),
             _ => false,
         }
     }
@@ -0,0 +1,289 @@
+//! Runs a directory of test programs, such as `grift-suite/core`, before and
+//! after migration.
+//!
+//! A program `p.grift` (or `p.gtlc`) must fail with an error that matches the
+//! regular expression in `p.err.rx`, if that file exists, and must run
+//! successfully otherwise. Migration ignores annotations, so it also erases
+//! blame labels: the migrated program only has to fail with some error.
+//! The evaluator does not support every feature of Grift, and programs that
+//! use unsupported features are skipped instead of failing.
+use super::cgen::typeinf_options;
+use super::eval;
+use super::grift;
+use super::insert_coercions::insert_coercions;
+use super::parser;
+use super::session::Session;
+use super::syntax::Exp;
+use super::Options;
+use regex::Regex;
+use serde::Serialize;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Serialize)]
+struct Check {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TestResult {
+    name: String,
+    original: Check,
+    migrated: Check,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    tests: Vec<TestResult>,
+}
+
+/// Programs that take more steps than this are assumed to diverge
+const FUEL: u64 = 10_000_000;
+
+/// The outcome of running a program
+enum Run {
+    Ok,
+    Error(String),
+    Unsupported(String),
+}
+
+impl Check {
+    fn pass() -> Self {
+        Check {
+            status: Status::Pass,
+            message: None,
+        }
+    }
+
+    fn fail(message: impl Into<String>) -> Self {
+        Check {
+            status: Status::Fail,
+            message: Some(message.into()),
+        }
+    }
+
+    fn skip(message: String) -> Self {
+        Check {
+            status: Status::Skip,
+            message: Some(message),
+        }
+    }
+
+    /// `expected` is the regular expression that the error must match, if
+    /// the program must fail.
+    fn new(run: Run, expected: Option<&Regex>) -> Self {
+        match (run, expected) {
+            (Run::Unsupported(msg), _) => Check::skip(msg),
+            (Run::Ok, None) => Check::pass(),
+            (Run::Ok, Some(rx)) => Check::fail(format!("expected an error matching /{}/", rx)),
+            (Run::Error(msg), None) => Check::fail(msg),
+            (Run::Error(msg), Some(rx)) => {
+                if rx.is_match(&msg) {
+                    Check::pass()
+                } else {
+                    Check::fail(format!("{} (expected /{}/)", msg, rx))
+                }
+            }
+        }
+    }
+}
+
+fn run(mut exp: Exp) -> Run {
+    if let Err(msg) = insert_coercions(&mut exp) {
+        return Run::Unsupported(msg);
+    }
+    match eval::eval_fuel(exp, FUEL).0 {
+        Ok(_) => Run::Ok,
+        Err(eval::Error::Unsupported(msg)) => Run::Unsupported(msg),
+        Err(err) => Run::Error(err.to_string()),
+    }
+}
+
+/// Parses a `.grift` program with the Grift parser, and anything else as GTLC
+pub fn parse(session: &Session, path: &Path, source: &str) -> Result<Exp, String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("grift") => Ok(grift::parse(session, source)),
+        _ => parser::parse(session, source),
+    }
+}
+
+/// Panics are failures, but they should not stop the rest of the suite.
+pub fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => match payload.downcast_ref::<&str>() {
+            Some(msg) => Err(format!("panicked: {}", msg)),
+            None => match payload.downcast_ref::<String>() {
+                Some(msg) => Err(format!("panicked: {}", msg)),
+                None => Err("panicked".to_string()),
+            },
+        },
+    }
+}
+
+fn test_one(path: &Path, expected: Option<&Regex>) -> (Check, Check) {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return (Check::fail(err.to_string()), Check::fail(err.to_string())),
+    };
+    let env = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("grift") => grift::env(),
+        _ => Default::default(),
+    };
+
+    let original = guard(|| {
+        let mut exp = parse(&Session::default(), path, &source)?;
+        exp.dynamic_metavars();
+        Ok(run(exp))
+    });
+    let original = match original {
+        Ok(run) => Check::new(run, expected),
+        Err(msg) => Check::fail(msg),
+    };
+
+    let migrated = guard(|| {
+        let session = Session::new(Options::default());
+        let mut exp = parse(&session, path, &source)?;
+        exp.fresh_types(&session);
+        let exp = typeinf_options(&session, exp, &env)
+            .map_err(|err| format!("migration failed: {}", err))?;
+        Ok(run(exp))
+    });
+    let any_error = Regex::new("").unwrap();
+    let migrated = match migrated {
+        Ok(run) => Check::new(run, expected.map(|_| &any_error)),
+        Err(msg) => Check::fail(msg),
+    };
+    (original, migrated)
+}
+
+/// The test programs in `dir` and its subdirectories, in order
+pub fn programs(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            programs(&path, out)?;
+        } else if let Some("grift") | Some("gtlc") = path.extension().and_then(|ext| ext.to_str()) {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn show(check: &Check) -> &'static str {
+    match check.status {
+        Status::Pass => "ok",
+        Status::Fail => "FAILED",
+        Status::Skip => "skipped",
+    }
+}
+
+/// Runs every program in `suite_dir` whose path contains one of the strings
+/// in `filter` (or every program, if `filter` is empty). Prints a table of
+/// results, followed by a JSON summary, or writes the summary to `summary`.
+/// Exits with an error if any program fails.
+pub fn test_suite_main(
+    suite_dir: impl AsRef<str>,
+    filter: &[String],
+    summary: Option<&str>,
+) -> Result<(), std::io::Error> {
+    let mut paths = Vec::new();
+    programs(Path::new(suite_dir.as_ref()), &mut paths)?;
+    paths.retain(|path| {
+        filter.is_empty()
+            || filter
+                .iter()
+                .any(|pat| path.to_string_lossy().contains(pat.as_str()))
+    });
+
+    let mut tests = Vec::new();
+    println!("{:50} {:10} {:10}", "program", "original", "migrated");
+    for path in paths {
+        let err_rx = path.with_extension("err.rx");
+        let expected = if err_rx.exists() {
+            let rx = std::fs::read_to_string(&err_rx)?;
+            Some(Regex::new(rx.trim()).map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", err_rx.display(), err),
+                )
+            })?)
+        } else {
+            None
+        };
+        let (original, migrated) = test_one(&path, expected.as_ref());
+        let name = path.to_string_lossy().to_string();
+        println!("{:50} {:10} {:10}", name, show(&original), show(&migrated));
+        for check in [&original, &migrated].iter() {
+            if check.status == Status::Fail {
+                println!("    {}", check.message.as_ref().unwrap());
+            }
+        }
+        tests.push(TestResult {
+            name,
+            original,
+            migrated,
+        });
+    }
+
+    let count = |status: Status| {
+        tests
+            .iter()
+            .flat_map(|t| vec![t.original.status, t.migrated.status])
+            .filter(|s| *s == status)
+            .count()
+    };
+    let results = Summary {
+        passed: count(Status::Pass),
+        failed: count(Status::Fail),
+        skipped: count(Status::Skip),
+        tests,
+    };
+    println!(
+        "{} passed, {} failed, {} skipped",
+        results.passed, results.failed, results.skipped
+    );
+    let json = serde_json::to_string_pretty(&results).unwrap();
+    match summary {
+        Some(file) => std::fs::write(file, json)?,
+        None => println!("{}", json),
+    }
+    if results.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Check, Run, Status};
+    use regex::Regex;
+
+    #[test]
+    fn expected_errors() {
+        let rx = Regex::new("Wrong").unwrap();
+        let check = |run, rx| Check::new(run, rx).status;
+        assert!(check(Run::Ok, None) == Status::Pass);
+        assert!(check(Run::Ok, Some(&rx)) == Status::Fail);
+        assert!(check(Run::Error("blame Wrong".to_string()), Some(&rx)) == Status::Pass);
+        assert!(check(Run::Error("blame Pass".to_string()), Some(&rx)) == Status::Fail);
+        assert!(check(Run::Error("blame Pass".to_string()), None) == Status::Fail);
+        assert!(check(Run::Unsupported("set!".to_string()), None) == Status::Skip);
+    }
+}
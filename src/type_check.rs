@@ -103,7 +103,7 @@ pub fn tcheck(env: &Env, exp: &Exp) -> Result<Typ, String> {
         // Γ ⊢ e : T
         // ---------
         // Γ ⊢ (e : T) : T
-        Exp::Ann(e, typ) => should_match(typ, tcheck(env, e)?),
+        Exp::Ann(e, typ, _) => should_match(typ, tcheck(env, e)?),
         // Γ ⊢ e_1 : T_1 where T_1 ∈ {int, str, any}
         // Γ ⊢ e_2 : T_1
         // ----------------------------------------------
@@ -278,7 +278,7 @@ pub fn tcheck(env: &Env, exp: &Exp) -> Result<Typ, String> {
         // Γ ⊢ e : T_1
         // ----------------------------------------------
         // Γ ⊢ coerce(T_1, T_2) e : T_2
-        Exp::Coerce(t1, t2, e) => {
+        Exp::Coerce(t1, t2, e, _) => {
             should_match(t1, tcheck(env, e)?)?;
             Ok(t2.clone())
         }
@@ -0,0 +1,104 @@
+//! Differential validation of migration: we run the original program and the
+//! result of migration, optionally plugged into a context, and check that
+//! migration did not change what the program does.
+use super::eval::{self, Answer};
+use super::insert_coercions::insert_coercions;
+use super::syntax::Exp;
+
+/// Programs that take more steps than this are assumed to diverge
+const FUEL: u64 = 10_000_000;
+
+/// The result of a program that we could evaluate, with what it printed
+struct Run {
+    result: Result<Answer, eval::Error>,
+    output: String,
+}
+
+fn run(mut exp: Exp) -> Result<Run, String> {
+    insert_coercions(&mut exp)?;
+    let (result, _, output) = eval::eval_io(exp, FUEL, "");
+    match result {
+        Err(eval::Error::Unsupported(msg)) => Err(format!("unsupported expression {}", msg)),
+        Err(eval::Error::OutOfFuel(steps)) => Err(format!("out of fuel after {} steps", steps)),
+        result => Ok(Run { result, output }),
+    }
+}
+
+/// Whether the program runs without error, or `None` if we cannot tell,
+/// because it uses unsupported features or runs out of fuel
+pub fn runs_ok(mut exp: Exp, fuel: u64) -> Option<bool> {
+    insert_coercions(&mut exp).ok()?;
+    match eval::eval_fuel(exp, fuel).0 {
+        Ok(_) => Some(true),
+        Err(eval::Error::Unsupported(_)) | Err(eval::Error::OutOfFuel(_)) => None,
+        Err(_) => Some(false),
+    }
+}
+
+/// Checks that `migrated` behaves like `original`, when both are plugged into
+/// `context` (if there is one). Migration must not introduce a runtime error,
+/// eliminate one, or change the value or output of the program. Produces an
+/// error when it cannot run either program.
+pub fn validate(original: &Exp, migrated: &Exp, context: Option<&Exp>) -> Result<(), String> {
+    let plug = |exp: &Exp| match context {
+        Some(context) => {
+            let mut plugged = context.clone();
+            plugged.plug(exp);
+            plugged
+        }
+        None => exp.clone(),
+    };
+    let before =
+        run(plug(original)).map_err(|e| format!("cannot run the original program: {}", e))?;
+    let after =
+        run(plug(migrated)).map_err(|e| format!("cannot run the migrated program: {}", e))?;
+    match (before.result, after.result) {
+        (Ok(v1), Ok(v2)) => {
+            if !v1.agrees(&v2) {
+                Err(format!(
+                    "migration changed the result from {} to {}",
+                    v1, v2
+                ))
+            } else if before.output != after.output {
+                Err(format!(
+                    "migration changed the output from {:?} to {:?}",
+                    before.output, after.output
+                ))
+            } else {
+                Ok(())
+            }
+        }
+        (Ok(_), Err(err)) => Err(format!("migration introduced a runtime error: {}", err)),
+        (Err(err), Ok(_)) => Err(format!("migration eliminated a runtime error: {}", err)),
+        (Err(_), Err(_)) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::validate;
+    use crate::parser::parse;
+    use crate::session::Session;
+    use crate::syntax::Exp;
+
+    fn exp(program: &str) -> Exp {
+        let mut exp = parse(&Session::default(), program).unwrap();
+        exp.dynamic_metavars();
+        exp
+    }
+
+    #[test]
+    fn contexts_witness_new_errors() {
+        let original = exp("fun x . x");
+        let migrated = exp("fun x : int . x");
+        assert!(validate(&original, &migrated, None).is_ok());
+        assert!(validate(&original, &migrated, Some(&exp("HOLE 5"))).is_ok());
+        assert!(validate(&original, &migrated, Some(&exp("HOLE true"))).is_err());
+    }
+
+    #[test]
+    fn changed_results() {
+        assert!(validate(&exp("1 + 2"), &exp("3"), None).is_ok());
+        assert!(validate(&exp("1 + 2"), &exp("4"), None).is_err());
+    }
+}
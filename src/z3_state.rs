@@ -80,6 +80,9 @@ impl<'a> Z3State<'a> {
     pub fn true_z3(&self) -> Bool<'a> {
         Bool::from_bool(self.cxt, true)
     }
+    pub fn false_z3(&self) -> Bool<'a> {
+        Bool::from_bool(self.cxt, false)
+    }
     pub fn z3_to_typ(&self, model: &'a Model, e: Dynamic) -> Typ {
         if self.is_int(model, &e) {
             Typ::Int